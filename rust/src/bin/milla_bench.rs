@@ -0,0 +1,4 @@
+fn main() -> eyre::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    rustlibs::milla::bench::run(&args)
+}
@@ -1,7 +1,7 @@
 mod jobs;
 mod logging;
 mod mapmanip;
-mod milla;
+pub mod milla;
 mod rustlibs_dmi;
 mod rustlibs_file;
 mod rustlibs_git;
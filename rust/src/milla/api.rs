@@ -1,104 +1,359 @@
 use crate::logging;
+use crate::milla::audit::{AuditPhase, ZLevelAudit};
 use crate::milla::constants::*;
 use crate::milla::conversion;
+use crate::milla::emitters::{GasEmitter, Heater};
+use crate::milla::explosion::{BlastMap, ExplosionTile};
+use crate::milla::gases::GasInfo;
+use crate::milla::heatmap::{Heatmap, HeatmapChannel};
+use crate::milla::mixture::GasMixture;
 use crate::milla::model::*;
+use crate::milla::pipenet::Device;
+use crate::milla::reactions;
 use crate::milla::simulate;
+use crate::milla::snapshot;
 use crate::milla::statics::*;
+use crate::milla::telemetry::{self, TickTelemetry};
 use crate::milla::tick;
+use crate::milla::watch::{WatchDirection, WatchQuantity, WatchRule};
+use crate::milla::wind;
 use byondapi::global_call::call_global;
 use byondapi::map::byond_block;
 use byondapi::map::byond_xyz;
+use byondapi::map::{byond_locatexyz, ByondXYZ};
 use byondapi::prelude::*;
+use chrono::Utc;
 use eyre::eyre;
 use eyre::Result;
 use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 
 /// BYOND API for ensuring the buffers are usable.
+/// `width` and `height` are the size of the Z level, and default to DEFAULT_MAP_SIZE if null.
 #[byondapi::bind]
-fn milla_initialize(byond_z: ByondValue) -> eyre::Result<ByondValue> {
+fn milla_initialize(
+    byond_z: ByondValue,
+    byond_width: ByondValue,
+    byond_height: ByondValue,
+) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     env::set_var("RUST_BACKTRACE", "1");
     let z = f32::try_from(byond_z)? as i32 - 1;
-    internal_initialize(z)?;
+    let width = conversion::bounded_byond_to_option_f32(byond_width, 0.0, MAX_MAP_SIZE as f32)?
+        .map_or(DEFAULT_MAP_SIZE, |width| width as usize);
+    let height = conversion::bounded_byond_to_option_f32(byond_height, 0.0, MAX_MAP_SIZE as f32)?
+        .map_or(DEFAULT_MAP_SIZE, |height| height as usize);
+    internal_initialize(z, width, height)?;
     Ok(ByondValue::null())
 }
 
-/// Ensure that buffers are available.
-pub(crate) fn internal_initialize(z: i32) -> eyre::Result<ByondValue> {
-    if z >= MAX_Z_LEVELS {
+/// Ensure that buffers are available, and Z level `z` is the given size.
+pub(crate) fn internal_initialize(z: i32, width: usize, height: usize) -> eyre::Result<ByondValue> {
+    if !(0..MAX_Z_LEVELS).contains(&z) {
+        return Err(eyre!(
+            "Suspicious Z level {} initialized, update MAX_Z_LEVELS if this is intentional.",
+            z + 1
+        ));
+    }
+    if width > MAX_MAP_SIZE || height > MAX_MAP_SIZE {
         return Err(eyre!(
-            "Suspiciously high Z level {} initialized, update MAX_Z_LEVELS if this is intentional.",
-            z
+            "Suspiciously large Z level {} initialized at {}x{}, update MAX_MAP_SIZE if this is intentional.",
+            z + 1,
+            width,
+            height
         ));
     }
     let buffers = BUFFERS.get_or_init(Buffers::new);
-    buffers.init_to(z);
+    buffers.init_z_level(z as usize, width, height)?;
     Ok(ByondValue::null())
 }
 
+/// BYOND API for registering a gas, or updating an existing one.
+/// Builtin gases, and the specific heat of registered ones, can't be changed.
+/// Returns the gas's 0-based index, which is its offset into MILLA gas lists.
+#[byondapi::bind]
+fn milla_register_gas(
+    id: ByondValue,
+    name: ByondValue,
+    specific_heat: ByondValue,
+    visibility_moles: ByondValue,
+    molar_mass: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let index = internal_register_gas(GasInfo {
+        id: id.get_string()?,
+        name: name.get_string()?,
+        specific_heat: conversion::bounded_byond_to_option_f32(specific_heat, 0.0, f32::INFINITY)?
+            .ok_or(eyre!("Specific heat is required."))?,
+        visibility_moles: conversion::bounded_byond_to_option_f32(
+            visibility_moles,
+            0.0,
+            f32::INFINITY,
+        )?,
+        molar_mass: conversion::bounded_byond_to_option_f32(molar_mass, 0.0, f32::INFINITY)?
+            .unwrap_or(0.0),
+    })?;
+    Ok(ByondValue::from(index as f32))
+}
+
+/// Rust version of registering a gas.
+pub(crate) fn internal_register_gas(info: GasInfo) -> Result<usize> {
+    gas_registry().register(info)
+}
+
+/// BYOND API for listing the registered gases.
+/// Returns a flat list of id, name, specific heat, visibility moles and molar mass for each gas,
+/// in registry order.
+#[byondapi::bind]
+fn milla_get_gases() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mut values: Vec<ByondValue> = Vec::new();
+    for info in gas_registry().all() {
+        values.push(ByondValue::new_str(info.id)?);
+        values.push(ByondValue::new_str(info.name)?);
+        values.push(ByondValue::from(info.specific_heat));
+        match info.visibility_moles {
+            Some(moles) => values.push(ByondValue::from(moles)),
+            None => values.push(ByondValue::null()),
+        }
+        values.push(ByondValue::from(info.molar_mass));
+    }
+    Ok(values.as_slice().try_into()?)
+}
+
 /// BYOND API for defining an environment that a tile can be exposed to.
+/// `gases` is a list of moles, in gas registry order.
 #[byondapi::bind]
 fn milla_create_environment(
-    oxygen: ByondValue,
-    carbon_dioxide: ByondValue,
-    nitrogen: ByondValue,
-    toxins: ByondValue,
-    sleeping_agent: ByondValue,
-    agent_b: ByondValue,
+    gases: ByondValue,
     temperature: ByondValue,
 ) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     Ok(ByondValue::from(internal_create_environment(
-        conversion::byond_to_option_f32(oxygen)?,
-        conversion::byond_to_option_f32(carbon_dioxide)?,
-        conversion::byond_to_option_f32(nitrogen)?,
-        conversion::byond_to_option_f32(toxins)?,
-        conversion::byond_to_option_f32(sleeping_agent)?,
-        conversion::byond_to_option_f32(agent_b)?,
+        &conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?,
         conversion::byond_to_option_f32(temperature)?,
-    ) as f32))
+    )? as f32))
 }
 
 /// Define an environment that a tile can be exposed to.
 pub(crate) fn internal_create_environment(
-    oxygen: Option<f32>,
-    carbon_dioxide: Option<f32>,
-    nitrogen: Option<f32>,
-    toxins: Option<f32>,
-    sleeping_agent: Option<f32>,
-    agent_b: Option<f32>,
+    gases: &[Option<f32>],
     temperature: Option<f32>,
-) -> u8 {
+) -> Result<u16> {
     let mut tile = Tile::new();
-    if let Some(value) = oxygen {
-        tile.gases.set_oxygen(value);
-    }
-    if let Some(value) = carbon_dioxide {
-        tile.gases.set_carbon_dioxide(value);
+    set_gases(&mut tile.gases, gases)?;
+    if let Some(value) = temperature {
+        tile.thermal_energy = value * tile.heat_capacity();
     }
-    if let Some(value) = nitrogen {
-        tile.gases.set_nitrogen(value);
+
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers.create_environment(tile)
+}
+
+/// BYOND API for changing an environment, such as for weather or day and night.
+/// `gases` is a list of moles, in gas registry order. Nulls, or a null temperature, leave things
+/// as they would have ended up.
+/// The change happens gradually over `duration` MILLA ticks, not deciseconds, or all at once on
+/// the next tick if that's null or 0. BYOND can convert with milla_ticks_for_duration.
+#[byondapi::bind]
+fn milla_update_environment(
+    id: ByondValue,
+    gases: ByondValue,
+    temperature: ByondValue,
+    duration: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let id = f32::try_from(id)?;
+    if !(0.0..MAX_ENVIRONMENTS as f32).contains(&id) {
+        return Err(eyre!("Invalid environment: {}", id));
     }
-    if let Some(value) = toxins {
-        tile.gases.set_toxins(value);
+    internal_update_environment(
+        id as u16,
+        &conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?,
+        conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?,
+        conversion::bounded_byond_to_option_f32(duration, 0.0, u32::MAX as f32)?.unwrap_or(0.0)
+            as u32,
+    )?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of changing an environment.
+pub(crate) fn internal_update_environment(
+    id: u16,
+    gases: &[Option<f32>],
+    temperature: Option<f32>,
+    duration: u32,
+) -> Result<()> {
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut environments = buffers.environments.write().unwrap();
+    // Start from wherever it was heading, so updates build on each other.
+    let mut tile = environments.get(id)?.target().clone();
+    let old_temperature = tile.temperature();
+    set_gases(&mut tile.gases, gases)?;
+    tile.thermal_energy = temperature.unwrap_or(old_temperature) * tile.heat_capacity();
+    environments.update(id, tile, duration)
+}
+
+/// BYOND API for listing every environment.
+/// Returns a flat list with, for each environment in ID order: its ID, temperature, how many
+/// ticks until it stops changing, and moles of each gas in registry order.
+#[byondapi::bind]
+fn milla_get_environments() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let environments = buffers.environments.read().unwrap();
+    let gas_count = gas_registry().count();
+    let mut values = Vec::with_capacity(environments.len() * (3 + gas_count));
+    for (id, environment) in environments.iter().enumerate() {
+        values.push(ByondValue::from(id as f32));
+        values.push(ByondValue::from(environment.tile.temperature()));
+        values.push(ByondValue::from(environment.ticks_left() as f32));
+        for gas in 0..gas_count {
+            values.push(ByondValue::from(environment.tile.gases.get(gas)));
+        }
     }
-    if let Some(value) = sleeping_agent {
-        tile.gases.set_sleeping_agent(value);
+    Ok(values.as_slice().try_into()?)
+}
+
+/// Writes a list of moles, in gas registry order, into a GasSet. None leaves a gas unchanged.
+fn set_gases(gas_set: &mut GasSet, gases: &[Option<f32>]) -> Result<()> {
+    check_gas_count(gases.len())?;
+    apply_gases(gas_set, gases);
+    Ok(())
+}
+
+/// Makes sure BYOND didn't send more gases than are registered.
+fn check_gas_count(count: usize) -> Result<()> {
+    let gas_count = gas_registry().count();
+    if count > gas_count {
+        return Err(eyre!(
+            "Got {} gases, but only {} are registered.",
+            count,
+            gas_count
+        ));
     }
-    if let Some(value) = agent_b {
-        tile.gases.set_agent_b(value);
+    Ok(())
+}
+
+/// Makes sure there's a tile at the given 0-indexed coordinates.
+fn require_tile_exists((x, y, z): (i32, i32, i32)) -> Result<()> {
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let active = buffers.get_active().read().unwrap();
+    let exists = match active.0.get(z as usize) {
+        Some(z_level) => z_level.read().unwrap().maybe_get_index(x, y).is_some(),
+        None => false,
+    };
+    if !exists {
+        return Err(eyre!("Bad coordinates ({}, {}, {})", x + 1, y + 1, z + 1));
     }
-    if let Some(value) = temperature {
-        tile.thermal_energy = value * tile.heat_capacity();
+    Ok(())
+}
+
+/// Sets the gases BYOND provided, leaving the rest alone. The count must already be checked.
+fn apply_gases(gas_set: &mut GasSet, gases: &[Option<f32>]) {
+    for (gas, maybe_value) in gases.iter().enumerate() {
+        if let Some(value) = maybe_value {
+            gas_set.set(gas, *value);
+        }
     }
+}
 
+/// BYOND API for replacing the reactions MILLA runs with the ones defined in a TOML or JSON file.
+/// Takes effect from the next tick.
+#[byondapi::bind]
+fn milla_load_reactions(path: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let count = internal_load_reactions(Path::new(&path.get_string()?))?;
+    Ok(ByondValue::from(count as f32))
+}
+
+/// Rust version of replacing the reactions MILLA runs.
+/// Returns how many reactions were loaded.
+pub(crate) fn internal_load_reactions(path: &Path) -> Result<usize> {
+    let reactions = reactions::load_reactions(gas_registry(), path)?;
+    let count = reactions.len();
+    *current_reactions().write().unwrap() = Arc::new(reactions);
+    Ok(count)
+}
+
+/// BYOND API for saving the whole atmos model to a snapshot file.
+#[byondapi::bind]
+fn milla_save_snapshot(path: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    internal_save_snapshot(Path::new(&path.get_string()?))?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of saving the whole atmos model to a snapshot file.
+pub(crate) fn internal_save_snapshot(path: &Path) -> Result<()> {
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    snapshot::save_to_file(buffers, gas_registry(), path)
+}
+
+/// BYOND API for rendering a Z level to a PNG heatmap under data/, for admins to download.
+/// `channel` is "pressure", "temperature", "wind", "hotspots", "superconductivity", or a gas ID.
+/// Returns the path of the new file.
+#[byondapi::bind]
+fn milla_save_heatmap(z: ByondValue, channel: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let z = f32::try_from(z)? as i32 - 1;
+    let channel_name = channel.get_string()?;
+    let path = format!(
+        "data/milla_heatmap_z{}_{}_{}.png",
+        z + 1,
+        channel_name,
+        Utc::now().format("%Y%m%d_%H%M%S")
+    );
+    internal_save_heatmap(z, HeatmapChannel::parse(&channel_name)?, Path::new(&path))?;
+    Ok(ByondValue::new_str(path)?)
+}
+
+/// Rust version of rendering a Z level to a PNG heatmap.
+pub(crate) fn internal_save_heatmap(z: i32, channel: HeatmapChannel, path: &Path) -> Result<()> {
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    let active = buffers.get_active().read().unwrap();
+    let heatmap = {
+        let z_level = active
+            .0
+            .get(z as usize)
+            .ok_or(eyre!("Z level {} not initialized.", z + 1))?
+            .read()
+            .unwrap();
+        Heatmap::render(&z_level, channel)
+    };
+    // Don't hold up the next tick while we encode.
+    drop(active);
+    heatmap.write_png(path)
+}
+
+/// BYOND API for replacing the whole atmos model with one from a snapshot file.
+/// Any gases in the snapshot must already be registered.
+#[byondapi::bind]
+fn milla_load_snapshot(path: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    internal_load_snapshot(Path::new(&path.get_string()?))?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of replacing the whole atmos model with one from a snapshot file.
+pub(crate) fn internal_load_snapshot(path: &Path) -> Result<()> {
     let buffers = BUFFERS.get_or_init(Buffers::new);
-    buffers.create_environment(tile)
+    snapshot::load_from_file(buffers, gas_registry(), path)
 }
 
 /// BYOND API for loading a block of turfs into MILLA with their default air.
+/// Each turf's data property is a list of:
+/// * airtight north, east, south, west
+/// * atmos mode and environment
+/// * moles of each gas, either just the builtin gases or every gas in gas registry order
+/// * temperature
+/// * superconductivity north, east, south, west
+/// * innate heat capacity
 #[byondapi::bind]
 fn milla_load_turfs(
     data_property: ByondValue,
@@ -109,55 +364,84 @@ fn milla_load_turfs(
     for turf in byond_block(byond_xyz(&low_corner)?, byond_xyz(&high_corner)?)? {
         let (x, y, z) = byond_xyz(&turf)?.coordinates();
         let mut property = turf.read_var_id(property_ref)?;
-        let data = property.get_list_values()?;
+        let values = property.get_list_values()?;
         property.decrement_tempref();
 
-        if data.len() != 17 {
+        let mut data: Vec<Option<f32>> = Vec::with_capacity(values.len());
+        for value in values {
+            data.push(conversion::byond_to_option_f32(value)?);
+        }
+        internal_load_turf(x as i32 - 1, y as i32 - 1, z as i32 - 1, &data)?;
+    }
+    Ok(ByondValue::null())
+}
+
+/// Picks the gases out of a turf's milla_data, which lists either just the builtin gases or all
+/// `registered_count` of them. Gases it leaves out are zero.
+/// Returns the gases, and where the values after them start.
+fn turf_data_gases(
+    data: &[Option<f32>],
+    registered_count: usize,
+) -> Result<(Vec<Option<f32>>, usize)> {
+    let given_count = match data.len().checked_sub(12) {
+        Some(count) if count == BUILTIN_GAS_COUNT || count == registered_count => count,
+        _ => {
             return Err(eyre!(
-                "data property has the wrong length: {} vs {}",
+                "data property has the wrong length: {} vs {} or {}",
                 data.len(),
-                17
-            ));
+                BUILTIN_GAS_COUNT + 12,
+                registered_count + 12
+            ))
         }
+    };
+    let mut gases: Vec<Option<f32>> = vec![Some(0.0); registered_count];
+    for (gas, value) in data[6..6 + given_count].iter().enumerate() {
+        gases[gas] = value.map(|value| value.max(0.0));
+    }
+    Ok((gases, 6 + given_count))
+}
 
-        internal_set_tile(
-            x as i32 - 1,
-            y as i32 - 1,
-            z as i32 - 1,
-            conversion::byond_to_option_f32(data[0])?,
-            conversion::byond_to_option_f32(data[1])?,
-            conversion::byond_to_option_f32(data[2])?,
-            conversion::byond_to_option_f32(data[3])?,
-            conversion::byond_to_option_f32(data[4])?,
-            conversion::byond_to_option_f32(data[5])?,
-            conversion::bounded_byond_to_option_f32(data[6], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[7], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[8], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[9], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[10], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[11], 0.0, f32::INFINITY)?,
-            conversion::bounded_byond_to_option_f32(data[12], 0.0, f32::INFINITY)?,
-            None,
-            Some(0.0),
-            Some(0.0),
-            Some(0.0),
-        )?;
+/// Rust version of loading a single turf, from the same data as milla_load_turfs().
+/// Gases that aren't in the data, such as registered gases when only the builtin ones are given,
+/// start at zero.
+pub(crate) fn internal_load_turf(x: i32, y: i32, z: i32, data: &[Option<f32>]) -> Result<()> {
+    let (gases, after_gases) = turf_data_gases(data, gas_registry().count())?;
+    let non_negative = |value: Option<f32>| value.map(|value| value.max(0.0));
+    let fraction = |value: Option<f32>| value.map(|value| value.clamp(0.0, 1.0));
 
-        internal_reset_superconductivity(x as i32 - 1, y as i32 - 1, z as i32 - 1)?;
-        internal_reduce_superconductivity(
-            x as i32 - 1,
-            y as i32 - 1,
-            z as i32 - 1,
-            conversion::bounded_byond_to_option_f32(data[13], 0.0, 1.0)?,
-            conversion::bounded_byond_to_option_f32(data[14], 0.0, 1.0)?,
-            conversion::bounded_byond_to_option_f32(data[15], 0.0, 1.0)?,
-            conversion::bounded_byond_to_option_f32(data[16], 0.0, 1.0)?,
-        )?;
-    }
-    Ok(ByondValue::null())
+    internal_set_tile(
+        x,
+        y,
+        z,
+        data[0],
+        data[1],
+        data[2],
+        data[3],
+        data[4],
+        data[5],
+        &gases,
+        non_negative(data[after_gases]),
+        None,
+        non_negative(data[after_gases + 5]),
+        Some(0.0),
+        Some(0.0),
+    )?;
+
+    internal_reset_superconductivity(x, y, z)?;
+    internal_reduce_superconductivity(
+        x,
+        y,
+        z,
+        fraction(data[after_gases + 1]),
+        fraction(data[after_gases + 2]),
+        fraction(data[after_gases + 3]),
+        fraction(data[after_gases + 4]),
+    )
 }
 
 /// BYOND API for setting the atmos details of a tile.
+/// `gases` is a list of moles, in gas registry order. Null entries, or a null list, leave the
+/// corresponding gases unchanged.
 #[byondapi::bind]
 fn milla_set_tile(
     turf: ByondValue,
@@ -167,14 +451,9 @@ fn milla_set_tile(
     airtight_west: ByondValue,
     atmos_mode: ByondValue,
     environment: ByondValue,
-    oxygen: ByondValue,
-    carbon_dioxide: ByondValue,
-    nitrogen: ByondValue,
-    toxins: ByondValue,
-    sleeping_agent: ByondValue,
-    agent_b: ByondValue,
+    gases: ByondValue,
     temperature: ByondValue,
-    _innate_heat_capacity: ByondValue,
+    innate_heat_capacity: ByondValue,
     hotspot_temperature: ByondValue,
     hotspot_volume: ByondValue,
 ) -> eyre::Result<ByondValue> {
@@ -190,17 +469,10 @@ fn milla_set_tile(
         conversion::byond_to_option_f32(airtight_west)?,
         conversion::byond_to_option_f32(atmos_mode)?,
         conversion::byond_to_option_f32(environment)?,
-        conversion::bounded_byond_to_option_f32(oxygen, 0.0, f32::INFINITY)?,
-        conversion::bounded_byond_to_option_f32(carbon_dioxide, 0.0, f32::INFINITY)?,
-        conversion::bounded_byond_to_option_f32(nitrogen, 0.0, f32::INFINITY)?,
-        conversion::bounded_byond_to_option_f32(toxins, 0.0, f32::INFINITY)?,
-        conversion::bounded_byond_to_option_f32(sleeping_agent, 0.0, f32::INFINITY)?,
-        conversion::bounded_byond_to_option_f32(agent_b, 0.0, f32::INFINITY)?,
+        &conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?,
         conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?,
         None,
-        // Temporarily disabled to better match the existing system.
-        //bounded_byond_to_option_f32(innate_heat_capacity, 0.0, f32::INFINITY)?,
-        Some(0.0),
+        conversion::bounded_byond_to_option_f32(innate_heat_capacity, 0.0, f32::INFINITY)?,
         conversion::bounded_byond_to_option_f32(hotspot_temperature, 0.0, f32::INFINITY)?,
         conversion::bounded_byond_to_option_f32(hotspot_volume, 0.0, 1.0)?,
     )?;
@@ -209,6 +481,8 @@ fn milla_set_tile(
 
 /// BYOND API for setting the directions a tile is airtight in.
 /// Like set_tile, just with a smaller set of fields.
+/// Up and down only matter between Z levels connected with milla_set_z_connected, and tiles are
+/// airtight both ways until they're opened here.
 #[byondapi::bind]
 fn milla_set_tile_airtight(
     turf: ByondValue,
@@ -216,6 +490,8 @@ fn milla_set_tile_airtight(
     airtight_east: ByondValue,
     airtight_south: ByondValue,
     airtight_west: ByondValue,
+    airtight_up: ByondValue,
+    airtight_down: ByondValue,
 ) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     let (x, y, z) = byond_xyz(&turf)?.coordinates();
@@ -229,21 +505,47 @@ fn milla_set_tile_airtight(
         conversion::byond_to_option_f32(airtight_west)?,
         None,
         None,
+        &[],
         None,
         None,
         None,
         None,
         None,
-        None,
-        None,
-        None,
-        None,
-        None,
-        None,
+    )?;
+    internal_set_tile_airtight_vertical(
+        x as i32 - 1,
+        y as i32 - 1,
+        z as i32 - 1,
+        conversion::byond_to_option_f32(airtight_up)?,
+        conversion::byond_to_option_f32(airtight_down)?,
     )?;
     Ok(ByondValue::null())
 }
 
+/// Rust version of setting whether a tile is airtight up and down.
+pub(crate) fn internal_set_tile_airtight_vertical(
+    x: i32,
+    y: i32,
+    z: i32,
+    airtight_up: Option<f32>,
+    airtight_down: Option<f32>,
+) -> Result<()> {
+    if airtight_up.is_none() && airtight_down.is_none() {
+        return Ok(());
+    }
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    buffers.write_tile(x, y, z, move |tile| {
+        if let Some(value) = airtight_up {
+            tile.airtight_directions
+                .set(AirtightDirections::UP, value > 0.0);
+        }
+        if let Some(value) = airtight_down {
+            tile.airtight_directions
+                .set(AirtightDirections::DOWN, value > 0.0);
+        }
+    })
+}
+
 /// Rust version of setting the atmos details of a tile.
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn internal_set_tile(
@@ -256,12 +558,7 @@ pub(crate) fn internal_set_tile(
     airtight_west: Option<f32>,
     atmos_mode: Option<f32>,
     environment: Option<f32>,
-    oxygen: Option<f32>,
-    carbon_dioxide: Option<f32>,
-    nitrogen: Option<f32>,
-    toxins: Option<f32>,
-    sleeping_agent: Option<f32>,
-    agent_b: Option<f32>,
+    gases: &[Option<f32>],
     temperature: Option<f32>,
     thermal_energy: Option<f32>,
     innate_heat_capacity: Option<f32>,
@@ -269,88 +566,212 @@ pub(crate) fn internal_set_tile(
     hotspot_volume: Option<f32>,
 ) -> Result<()> {
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to write during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
+    // Check everything up front, as the write might not happen until after the tick.
+    check_gas_count(gases.len())?;
+    let update = TileUpdate {
+        airtight: [airtight_north, airtight_east, airtight_south, airtight_west],
+        mode: parse_atmos_mode(atmos_mode, environment)?,
+        gases: gases.to_vec(),
+        add_gases: false,
+        temperature,
+        thermal_energy,
+        innate_heat_capacity,
+        hotspot_temperature,
+        hotspot_volume,
+    };
+    buffers.write_tile(x, y, z, move |tile| update.apply(tile))
+}
+
+/// Turns BYOND's atmos mode and environment into an AtmosMode.
+/// ExposedTo without an environment leaves the mode unchanged.
+fn parse_atmos_mode(
+    atmos_mode: Option<f32>,
+    environment: Option<f32>,
+) -> Result<Option<AtmosMode>> {
+    let Some(value) = atmos_mode else {
+        return Ok(None);
+    };
+    match value as i32 {
+        0 => Ok(Some(AtmosMode::Space)),
+        1 => Ok(Some(AtmosMode::Sealed)),
+        2 => match environment {
+            Some(env) if env < 0.0 || env as usize >= MAX_ENVIRONMENTS => {
+                Err(eyre!("Invalid environment: {}", env))
+            }
+            Some(env) => Ok(Some(AtmosMode::ExposedTo {
+                environment_id: env as u16,
+            })),
+            None => Ok(None),
+        },
+        3 => Ok(Some(AtmosMode::NoDecay)),
+        _ => Err(eyre!("Invalid atmos_mode: {}", value)),
     }
-    let mut z_level = maybe_z_level.unwrap();
-    let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
-        "Bad coordinates ({}, {}, {})",
-        x + 1,
-        y + 1,
-        z + 1
-    ))?);
-    if let Some(value) = airtight_north {
-        tile.airtight_directions
-            .set(AirtightDirections::NORTH, value > 0.0);
-    }
-    if let Some(value) = airtight_east {
-        tile.airtight_directions
-            .set(AirtightDirections::EAST, value > 0.0);
-    }
-    if let Some(value) = airtight_south {
-        tile.airtight_directions
-            .set(AirtightDirections::SOUTH, value > 0.0);
-    }
-    if let Some(value) = airtight_west {
-        tile.airtight_directions
-            .set(AirtightDirections::WEST, value > 0.0);
-    }
-    if let Some(value) = atmos_mode {
-        match value as i32 {
-            0 => tile.mode = AtmosMode::Space,
-            1 => tile.mode = AtmosMode::Sealed,
-            2 => {
-                if let Some(env) = environment {
-                    tile.mode = AtmosMode::ExposedTo {
-                        environment_id: env as u8,
-                    };
-                }
+}
+
+/// A checked set of changes to a tile, ready to be applied now or after the tick.
+/// None leaves the corresponding value unchanged.
+struct TileUpdate {
+    /// North, east, south, west.
+    airtight: [Option<f32>; 4],
+    mode: Option<AtmosMode>,
+    /// Moles of each gas, in gas registry order. The count must already be checked.
+    gases: Vec<Option<f32>>,
+    /// Whether `gases` are added to the tile, rather than replacing what's there.
+    /// When they are, `temperature` is the temperature of the added gas, not the tile's.
+    add_gases: bool,
+    temperature: Option<f32>,
+    thermal_energy: Option<f32>,
+    innate_heat_capacity: Option<f32>,
+    hotspot_temperature: Option<f32>,
+    hotspot_volume: Option<f32>,
+}
+
+impl TileUpdate {
+    fn apply(self, tile: &mut Tile) {
+        // A tile that had no heat capacity of its own, like an open turf, has no temperature of
+        // its own either, so something built there starts at the temperature of the air.
+        let old_innate_temperature = if tile.innate_heat_capacity > 0.0 {
+            tile.innate_temperature()
+        } else if tile.gases.moles() > 0.0 {
+            tile.temperature()
+        } else {
+            T20C
+        };
+        let directions = [
+            AirtightDirections::NORTH,
+            AirtightDirections::EAST,
+            AirtightDirections::SOUTH,
+            AirtightDirections::WEST,
+        ];
+        for (direction, maybe_value) in directions.into_iter().zip(self.airtight) {
+            if let Some(value) = maybe_value {
+                tile.airtight_directions.set(direction, value > 0.0);
             }
-            3 => tile.mode = AtmosMode::NoDecay,
-            _ => return Err(eyre!("Invalid atmos_mode: {}", value)),
+        }
+        if let Some(value) = self.mode {
+            tile.mode = value;
+        }
+        let temperature = if self.add_gases {
+            add_gases(tile, &self.gases, self.temperature);
+            None
+        } else {
+            apply_gases(&mut tile.gases, &self.gases);
+            self.temperature
+        };
+        // Setting the tile's own heat capacity also sets its own temperature, to the new
+        // temperature if there is one. Otherwise, it keeps the temperature it had.
+        if let Some(value) = self.innate_heat_capacity {
+            let innate_temperature = temperature.unwrap_or(old_innate_temperature);
+            tile.innate_heat_capacity = value;
+            tile.innate_thermal_energy = value * innate_temperature;
+        }
+        if let Some(value) = temperature {
+            tile.thermal_energy = value * tile.heat_capacity();
+        }
+        if let Some(value) = self.thermal_energy {
+            tile.thermal_energy = value;
+        }
+        if let Some(value) = self.hotspot_temperature {
+            tile.hotspot_temperature = value;
+        }
+        if let Some(value) = self.hotspot_volume {
+            tile.hotspot_volume = value;
         }
     }
-    if let Some(value) = oxygen {
-        tile.gases.set_oxygen(value);
-    }
-    if let Some(value) = carbon_dioxide {
-        tile.gases.set_carbon_dioxide(value);
-    }
-    if let Some(value) = nitrogen {
-        tile.gases.set_nitrogen(value);
-    }
-    if let Some(value) = toxins {
-        tile.gases.set_toxins(value);
-    }
-    if let Some(value) = sleeping_agent {
-        tile.gases.set_sleeping_agent(value);
-    }
-    if let Some(value) = agent_b {
-        tile.gases.set_agent_b(value);
-    }
-    // Done sooner because we need innate heat capacity to calculate thermal energy from
-    // temperature.
-    if let Some(value) = innate_heat_capacity {
-        tile.innate_heat_capacity = value;
+}
+
+/// Adds gases to a tile, or removes them with negative amounts. Added gas arrives at
+/// `temperature`, or the tile's temperature if that's None. The count must already be checked.
+fn add_gases(tile: &mut Tile, deltas: &[Option<f32>], temperature: Option<f32>) {
+    let mut rates = [0.0; MAX_GAS_COUNT];
+    for (gas, maybe_delta) in deltas.iter().enumerate() {
+        rates[gas] = maybe_delta.unwrap_or(0.0);
     }
-    if let Some(value) = temperature {
-        tile.thermal_energy = value * tile.heat_capacity();
+    // A one-off emitter does exactly what we want.
+    GasEmitter {
+        x: 0,
+        y: 0,
+        z: 0,
+        rates,
+        temperature: temperature.unwrap_or(tile.temperature()),
+        pressure_cap: None,
     }
-    if let Some(value) = thermal_energy {
-        tile.thermal_energy = value;
+    .apply(tile);
+}
+
+/// BYOND API for setting the atmos details of many tiles at once.
+/// `data` is a flat list, with one group of values for each turf, in the same order:
+/// * airtight north, east, south, west
+/// * atmos mode and environment
+/// * temperature, innate heat capacity, hotspot temperature, hotspot volume
+/// * moles of each gas, in gas registry order
+/// Nulls leave the corresponding values unchanged.
+/// If `delta` is true, the gases are added to each tile instead, with negative values removing
+/// gas, and the temperature is that of the added gas, or the tile's if null.
+#[byondapi::bind]
+fn milla_set_tiles(
+    turfs: ByondValue,
+    data: ByondValue,
+    delta: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mut coords = Vec::new();
+    for turf in turfs.get_list_values()? {
+        let (x, y, z) = byond_xyz(&turf)?.coordinates();
+        coords.push((x as i32 - 1, y as i32 - 1, z as i32 - 1));
     }
-    if let Some(value) = hotspot_temperature {
-        tile.hotspot_temperature = value;
+    internal_set_tiles(
+        &coords,
+        &conversion::bounded_byond_list_to_option_f32s(data, f32::MIN, f32::MAX)?,
+        bool::try_from(delta)?,
+    )?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of setting the atmos details of many tiles at once.
+/// If anything is wrong, no tiles are changed.
+pub(crate) fn internal_set_tiles(
+    coords: &[(i32, i32, i32)],
+    values: &[Option<f32>],
+    delta: bool,
+) -> Result<()> {
+    let gas_count = gas_registry().count();
+    let stride = SET_TILES_FIXED_VALUES + gas_count;
+    if values.len() != coords.len() * stride {
+        return Err(eyre!(
+            "Expected {} values for {} turfs, but got {}.",
+            coords.len() * stride,
+            coords.len(),
+            values.len()
+        ));
     }
-    if let Some(value) = hotspot_volume {
-        tile.hotspot_volume = value;
+    let clamp = |value: Option<f32>, min: f32, max: f32| value.map(|v| v.clamp(min, max));
+    let min_moles = if delta { f32::MIN } else { 0.0 };
+    let mut writes = Vec::with_capacity(coords.len());
+    for (&tile_coords, tile_values) in coords.iter().zip(values.chunks_exact(stride)) {
+        let update = TileUpdate {
+            airtight: [
+                tile_values[0],
+                tile_values[1],
+                tile_values[2],
+                tile_values[3],
+            ],
+            mode: parse_atmos_mode(tile_values[4], tile_values[5])?,
+            gases: tile_values[SET_TILES_FIXED_VALUES..]
+                .iter()
+                .map(|&moles| clamp(moles, min_moles, f32::MAX))
+                .collect(),
+            add_gases: delta,
+            temperature: clamp(tile_values[6], 0.0, f32::INFINITY),
+            thermal_energy: None,
+            innate_heat_capacity: clamp(tile_values[7], 0.0, f32::INFINITY),
+            hotspot_temperature: clamp(tile_values[8], 0.0, f32::INFINITY),
+            hotspot_volume: clamp(tile_values[9], 0.0, 1.0),
+        };
+        writes.push((tile_coords, move |tile: &mut Tile| update.apply(tile)));
     }
-
-    Ok(())
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    buffers.write_tiles(writes)
 }
 
 /// BYOND API for fetching the atmos details of a tile.
@@ -358,18 +779,24 @@ pub(crate) fn internal_set_tile(
 fn milla_get_tile(turf: ByondValue, list: ByondValue) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     let (x, y, z) = byond_xyz(&turf)?.coordinates();
-    let vec: Vec<ByondValue>;
-    if let Ok(tile) = internal_get_tile(x as i32 - 1, y as i32 - 1, z as i32 - 1) {
-        vec = (&tile).into();
-    } else {
-        // MILLA has died and is unrecoverable.
-        // Uh... uh... report everything as breathable air, I guess?
-        let mut air = Tile::new();
-        air.gases.set_oxygen(20.0);
-        air.gases.set_nitrogen(80.0);
-        air.thermal_energy = air.heat_capacity() * T20C;
-        vec = (&air).into();
-    }
+    let vec: Vec<ByondValue> = match internal_get_tile(x as i32 - 1, y as i32 - 1, z as i32 - 1) {
+        Ok(tile) => (&tile).into(),
+        Err(_)
+            if BUFFERS
+                .get()
+                .is_none_or(|buffers| buffers.get_active().is_poisoned()) =>
+        {
+            // MILLA has died and is unrecoverable.
+            // Uh... uh... report everything as breathable air, I guess?
+            let mut air = Tile::new();
+            air.gases.set(GAS_OXYGEN, 20.0);
+            air.gases.set(GAS_NITROGEN, 80.0);
+            air.thermal_energy = air.heat_capacity() * T20C;
+            (&air).into()
+        }
+        // Anywhere else, the turf is outside what MILLA was told about, which is a bug.
+        Err(err) => return Err(err),
+    };
     list.write_list(vec.as_slice())?;
     Ok(ByondValue::null())
 }
@@ -382,9 +809,14 @@ pub(crate) fn internal_get_tile(x: i32, y: i32, z: i32) -> Result<Tile> {
         return Err(eyre!("MILLA buffers have been poisoned."));
     }
     let active = maybe_active.unwrap();
-    let z_level = active.0[z as usize].read().unwrap();
+    let z_level = active
+        .0
+        .get(z as usize)
+        .ok_or(eyre!("Z level {} not initialized.", z + 1))?
+        .read()
+        .unwrap();
     Ok(z_level
-        .get_tile(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
+        .get_tile(z_level.maybe_get_index(x, y).ok_or(eyre!(
             "Bad coordinates ({}, {}, {})",
             x + 1,
             y + 1,
@@ -393,6 +825,98 @@ pub(crate) fn internal_get_tile(x: i32, y: i32, z: i32) -> Result<Tile> {
         .clone())
 }
 
+/// BYOND API for fetching the atmos details of many tiles at once.
+/// Returns a flat list with the same values as milla_get_tile for each turf, in the same order.
+#[byondapi::bind]
+fn milla_get_tiles(turfs: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mut coords = Vec::new();
+    for turf in turfs.get_list_values()? {
+        let (x, y, z) = byond_xyz(&turf)?.coordinates();
+        coords.push((x as i32 - 1, y as i32 - 1, z as i32 - 1));
+    }
+    tiles_to_byond(&internal_get_tiles(&coords)?)
+}
+
+/// BYOND API for fetching the atmos details of a block of turfs, between two corners.
+/// Returns a flat list with the same values as milla_get_tile for each turf, in the same order
+/// as block().
+#[byondapi::bind]
+fn milla_get_block_tiles(
+    low_corner: ByondValue,
+    high_corner: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let coords = block_coords(
+        byond_xyz(&low_corner)?.coordinates(),
+        byond_xyz(&high_corner)?.coordinates(),
+    );
+    tiles_to_byond(&internal_get_tiles(&coords)?)
+}
+
+/// Lists the 0-indexed coordinates of every tile between two BYOND corners, in the same order as
+/// block(): X fastest, then Y, then Z.
+fn block_coords(low: (i16, i16, i16), high: (i16, i16, i16)) -> Vec<(i32, i32, i32)> {
+    let (low_x, low_y, low_z) = low;
+    let (high_x, high_y, high_z) = high;
+    let mut coords = Vec::new();
+    for z in low_z.min(high_z)..=low_z.max(high_z) {
+        for y in low_y.min(high_y)..=low_y.max(high_y) {
+            for x in low_x.min(high_x)..=low_x.max(high_x) {
+                coords.push((x as i32 - 1, y as i32 - 1, z as i32 - 1));
+            }
+        }
+    }
+    coords
+}
+
+/// Packs tiles into a flat BYOND list.
+fn tiles_to_byond(tiles: &[Tile]) -> eyre::Result<ByondValue> {
+    let values: Vec<ByondValue> = tiles.iter().flat_map(Vec::from).collect();
+    Ok(values.as_slice().try_into()?)
+}
+
+/// Rust version of fetching the atmos details of many tiles at once.
+pub(crate) fn internal_get_tiles(coords: &[(i32, i32, i32)]) -> Result<Vec<Tile>> {
+    let mut tiles = Vec::with_capacity(coords.len());
+    for_each_active_tile(coords, |z_level, _, index| {
+        tiles.push(z_level.get_tile(index).clone());
+    })?;
+    Ok(tiles)
+}
+
+/// Calls `f` with the Z level, coordinates and index of each of `coords`, in order, from the
+/// active buffer. Fails on the first coordinates that don't exist.
+fn for_each_active_tile<F>(coords: &[(i32, i32, i32)], mut f: F) -> Result<()>
+where
+    F: FnMut(&ZLevel, (i32, i32), usize),
+{
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    let active = buffers.get_active().read().unwrap();
+    let mut current_z = None;
+    let mut maybe_z_level = None;
+    for &(x, y, z) in coords {
+        // Coordinates usually come grouped by Z level, so only lock each one once.
+        if current_z != Some(z) {
+            let z_level_lock = active
+                .0
+                .get(z as usize)
+                .ok_or(eyre!("Z level {} not initialized.", z + 1))?;
+            maybe_z_level = Some(z_level_lock.read().unwrap());
+            current_z = Some(z);
+        }
+        let z_level = maybe_z_level.as_ref().unwrap();
+        let index = z_level.maybe_get_index(x, y).ok_or(eyre!(
+            "Bad coordinates ({}, {}, {})",
+            x + 1,
+            y + 1,
+            z + 1
+        ))?;
+        f(z_level, (x, y), index);
+    }
+    Ok(())
+}
+
 /// BYOND API for getting a list of interesting tiles this tick.
 /// There are three kinds:
 /// * Turfs that are hot eough to cause fires.
@@ -465,33 +989,20 @@ pub(crate) fn internal_reduce_superconductivity(
     west: Option<f32>,
 ) -> eyre::Result<()> {
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to write during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
-    }
-    let mut z_level = maybe_z_level.unwrap();
-    let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
-        "Bad coordinates ({}, {}, {})",
-        x + 1,
-        y + 1,
-        z + 1
-    ))?);
-    if let Some(value) = north {
-        tile.superconductivity.north = tile.superconductivity.north.min(value);
-    }
-    if let Some(value) = east {
-        tile.superconductivity.east = tile.superconductivity.east.min(value);
-    }
-    if let Some(value) = south {
-        tile.superconductivity.south = tile.superconductivity.south.min(value);
-    }
-    if let Some(value) = west {
-        tile.superconductivity.west = tile.superconductivity.west.min(value);
-    }
-    Ok(())
+    buffers.write_tile(x, y, z, move |tile| {
+        if let Some(value) = north {
+            tile.superconductivity.north = tile.superconductivity.north.min(value);
+        }
+        if let Some(value) = east {
+            tile.superconductivity.east = tile.superconductivity.east.min(value);
+        }
+        if let Some(value) = south {
+            tile.superconductivity.south = tile.superconductivity.south.min(value);
+        }
+        if let Some(value) = west {
+            tile.superconductivity.west = tile.superconductivity.west.min(value);
+        }
+    })
 }
 
 /// BYOND API for resetting the superconductivity of a tile.
@@ -506,25 +1017,12 @@ fn milla_reset_superconductivity(turf: ByondValue) -> eyre::Result<ByondValue> {
 /// Rust version of resetting the superconductivity of a tile.
 pub(crate) fn internal_reset_superconductivity(x: i32, y: i32, z: i32) -> Result<()> {
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to write during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
-    }
-    let mut z_level = maybe_z_level.unwrap();
-    let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
-        "Bad coordinates ({}, {}, {})",
-        x + 1,
-        y + 1,
-        z + 1
-    ))?);
-    tile.superconductivity.north = OPEN_HEAT_TRANSFER_COEFFICIENT;
-    tile.superconductivity.east = OPEN_HEAT_TRANSFER_COEFFICIENT;
-    tile.superconductivity.south = OPEN_HEAT_TRANSFER_COEFFICIENT;
-    tile.superconductivity.west = OPEN_HEAT_TRANSFER_COEFFICIENT;
-    Ok(())
+    buffers.write_tile(x, y, z, |tile| {
+        tile.superconductivity.north = OPEN_HEAT_TRANSFER_COEFFICIENT;
+        tile.superconductivity.east = OPEN_HEAT_TRANSFER_COEFFICIENT;
+        tile.superconductivity.south = OPEN_HEAT_TRANSFER_COEFFICIENT;
+        tile.superconductivity.west = OPEN_HEAT_TRANSFER_COEFFICIENT;
+    })
 }
 
 /// BYOND API for a heat source creating a hotspot on a tile.
@@ -561,80 +1059,115 @@ pub(crate) fn internal_create_hotspot(
     volume: f32,
 ) -> Result<()> {
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to write during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
-    }
-    let mut z_level = maybe_z_level.unwrap();
-    let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
-        "Bad coordinates ({}, {}, {})",
-        x + 1,
-        y + 1,
-        z + 1
-    ))?);
+    buffers.write_tile(x, y, z, move |tile| {
+        if temperature <= tile.temperature() || volume == 0.0 {
+            return;
+        }
 
-    if temperature <= tile.temperature() || volume == 0.0 {
-        return Ok(());
-    }
+        if tile.hotspot_volume == 0.0 {
+            tile.hotspot_temperature = temperature;
+            tile.hotspot_volume = volume;
+            return;
+        }
 
-    if tile.hotspot_volume == 0.0 {
-        tile.hotspot_temperature = temperature;
-        tile.hotspot_volume = volume;
-        return Ok(());
-    }
+        let excess_thermal_energy =
+            (temperature - tile.temperature()) * tile.heat_capacity() * volume;
+        if excess_thermal_energy > 0.0 {
+            simulate::adjust_hotspot(tile, excess_thermal_energy);
+        }
+    })
+}
 
-    let excess_thermal_energy = (temperature - tile.temperature()) * tile.heat_capacity() * volume;
-    if excess_thermal_energy > 0.0 {
-        simulate::adjust_hotspot(tile, excess_thermal_energy);
-    }
+/// BYOND API for a heat source creating a hotspot on a tile.
+#[byondapi::bind]
+fn milla_extinguish_hotspot(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
 
-    Ok(())
+    internal_extinguish_hotspot(x as i32 - 1, y as i32 - 1, z as i32 - 1)?;
+    Ok(ByondValue::null())
 }
 
-/// BYOND API for a heat source creating a hotspot on a tile.
+/// Rust version of a heat source creating a hotspot.
+pub(crate) fn internal_extinguish_hotspot(x: i32, y: i32, z: i32) -> Result<()> {
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    buffers.write_tile(x, y, z, |tile| {
+        tile.hotspot_temperature = 0.0;
+        tile.hotspot_volume = 0.0;
+    })
+}
+
+/// BYOND API for setting how much solid fuel, like burnable floors and objects, a tile has.
+/// `amount` is in moles of oxygen it can burn. Fires in the tile burn it up over time.
+#[byondapi::bind]
+fn milla_set_solid_fuel(turf: ByondValue, amount: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let rust_amount = conversion::bounded_byond_to_option_f32(amount, 0.0, MAX_SOLID_FUEL)?
+        .ok_or(eyre!("Solid fuel amount is required."))?;
+
+    internal_set_solid_fuel(x as i32 - 1, y as i32 - 1, z as i32 - 1, rust_amount)?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of setting a tile's solid fuel.
+pub(crate) fn internal_set_solid_fuel(x: i32, y: i32, z: i32, amount: f32) -> Result<()> {
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    buffers.write_tile(x, y, z, move |tile| tile.solid_fuel = amount)
+}
+
+/// BYOND API for adding or removing gas from a tile.
+/// `deltas` is a list of moles, in gas registry order. Negative deltas remove gas, and nulls are
+/// treated as zero. Added gas arrives at `temperature`, in kelvin, or at the tile's temperature if
+/// that's null.
+/// Unlike milla_set_tile, this stacks with whatever else happens to the tile, including a tick
+/// that's running right now.
 #[byondapi::bind]
-fn milla_extinguish_hotspot(
+fn milla_add_gases(
     turf: ByondValue,
+    deltas: ByondValue,
+    temperature: ByondValue,
 ) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     let (x, y, z) = byond_xyz(&turf)?.coordinates();
-
-    internal_extinguish_hotspot(
+    internal_add_gases(
         x as i32 - 1,
         y as i32 - 1,
         z as i32 - 1,
+        &conversion::bounded_byond_list_to_option_f32s(deltas, f32::MIN, f32::MAX)?,
+        conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?,
     )?;
     Ok(ByondValue::null())
 }
 
-/// Rust version of a heat source creating a hotspot.
-pub(crate) fn internal_extinguish_hotspot(
+/// Rust version of adding or removing gas from a tile.
+pub(crate) fn internal_add_gases(
     x: i32,
     y: i32,
     z: i32,
+    deltas: &[Option<f32>],
+    temperature: Option<f32>,
 ) -> Result<()> {
+    check_gas_count(deltas.len())?;
+    let deltas = deltas.to_vec();
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to write during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
-    }
-    let mut z_level = maybe_z_level.unwrap();
-    let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(x, y).ok_or(eyre!(
-        "Bad coordinates ({}, {}, {})",
-        x + 1,
-        y + 1,
-        z + 1
-    ))?);
+    buffers.write_tile(x, y, z, move |tile| add_gases(tile, &deltas, temperature))
+}
 
-    tile.hotspot_temperature = 0.0;
-    tile.hotspot_volume = 0.0;
-    Ok(())
+/// BYOND API for checking how backed up MILLA's writes are.
+/// Returns list(writes waiting right now, writes that waited for the last tick).
+/// Writes made during a tick wait until it finishes, then apply in the order they were made.
+#[byondapi::bind]
+fn milla_get_write_queue_depth() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let queue = buffers.write_queue.lock().unwrap();
+    Ok([
+        ByondValue::from(queue.depth() as f32),
+        ByondValue::from(queue.last_tick_writes as f32),
+    ]
+    .as_slice()
+    .try_into()?)
 }
 
 /// BYOND API for tracking the pressure of all nearby tiles next tick.
@@ -645,7 +1178,7 @@ fn milla_track_pressure_tiles(
 ) -> eyre::Result<ByondValue> {
     logging::setup_panic_handler();
     let (x, y, z) = byond_xyz(&turf)?.coordinates();
-    let radius = conversion::bounded_byond_to_option_f32(byond_radius, 0.0, MAP_SIZE as f32)?
+    let radius = conversion::bounded_byond_to_option_f32(byond_radius, 0.0, MAX_MAP_SIZE as f32)?
         .ok_or(eyre!("Invalid radius: {:#?}", byond_radius))? as i32;
 
     internal_track_pressure_tiles(x as i32 - 1, y as i32 - 1, z as i32 - 1, radius)?;
@@ -654,19 +1187,30 @@ fn milla_track_pressure_tiles(
 
 /// Rust version of tracking the pressure of all nearby tiles next tick.
 fn internal_track_pressure_tiles(x: i32, y: i32, z: i32, radius: i32) -> eyre::Result<()> {
+    let (width, height) = {
+        let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active
+            .0
+            .get(z as usize)
+            .ok_or(eyre!("Z level {} not initialized.", z + 1))?
+            .read()
+            .unwrap();
+        (z_level.width() as i32, z_level.height() as i32)
+    };
     let mut tracked_pressure_tiles = TRACKED_PRESSURE_TILES.lock().unwrap();
     for dx in -radius..=radius {
         if x + dx < 0 {
             continue;
         }
-        if x + dx >= MAP_SIZE as i32 {
+        if x + dx >= width {
             break;
         }
         for dy in -radius..=radius {
             if y + dy < 0 {
                 continue;
             }
-            if y + dy >= MAP_SIZE as i32 {
+            if y + dy >= height {
                 break;
             }
             tracked_pressure_tiles.push((x + dx, y + dy, z as usize));
@@ -711,7 +1255,7 @@ fn internal_get_tracked_pressure_tiles() -> eyre::Result<Vec<f32>> {
     for z in 0..tiles_by_zlevel.len() {
         let z_level = inactive.0[z as usize].read().unwrap();
         for (x, y) in &tiles_by_zlevel[z] {
-            if let Some(index) = ZLevel::maybe_get_index(*x, *y) {
+            if let Some(index) = z_level.maybe_get_index(*x, *y) {
                 tracked_pressures.push(*x as f32 + 1.0);
                 tracked_pressures.push(*y as f32 + 1.0);
                 tracked_pressures.push(z as f32 + 1.0);
@@ -723,6 +1267,106 @@ fn internal_get_tracked_pressure_tiles() -> eyre::Result<Vec<f32>> {
     Ok(tracked_pressures)
 }
 
+/// BYOND API for summing up the atmos in a block of turfs, between two corners.
+/// Sees the last finished tick, plus any writes made since.
+#[byondapi::bind]
+fn milla_get_block_stats(
+    low_corner: ByondValue,
+    high_corner: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let coords = block_coords(
+        byond_xyz(&low_corner)?.coordinates(),
+        byond_xyz(&high_corner)?.coordinates(),
+    );
+    let stats = internal_get_region_stats(&coords)?;
+    let values: Vec<ByondValue> = (&stats).into();
+    Ok(values.as_slice().try_into()?)
+}
+
+/// BYOND API for summing up the atmos in a list of turfs.
+/// Sees the last finished tick, plus any writes made since.
+#[byondapi::bind]
+fn milla_get_turf_stats(turfs: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mut coords = Vec::new();
+    for turf in turfs.get_list_values()? {
+        let (x, y, z) = byond_xyz(&turf)?.coordinates();
+        coords.push((x as i32 - 1, y as i32 - 1, z as i32 - 1));
+    }
+    let stats = internal_get_region_stats(&coords)?;
+    let values: Vec<ByondValue> = (&stats).into();
+    Ok(values.as_slice().try_into()?)
+}
+
+/// Rust version of summing up the atmos in a group of tiles.
+pub(crate) fn internal_get_region_stats(coords: &[(i32, i32, i32)]) -> Result<RegionStats> {
+    let mut stats = RegionStats::default();
+    for_each_active_tile(coords, |z_level, _, index| {
+        stats.add(z_level.get_tile(index));
+    })?;
+    Ok(stats)
+}
+
+/// BYOND API for getting the force the wind exerts on things in a turf, as of the last finished
+/// tick.
+/// Returns list(force_x, force_y), in newtons.
+#[byondapi::bind]
+fn milla_get_wind_force(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let forces = internal_get_wind_forces(&[(x as i32 - 1, y as i32 - 1, z as i32 - 1)])?;
+    let force = forces[0];
+    Ok([
+        ByondValue::from(force[AXIS_X]),
+        ByondValue::from(force[AXIS_Y]),
+    ]
+    .as_slice()
+    .try_into()?)
+}
+
+/// BYOND API for working out how far the wind pushes a batch of objects this tick.
+/// `objects` is a flat list of turf, mass pairs, with mass in kilograms.
+/// Returns a flat list with one displacement per object, in tiles. See MILLA_WIND_DISPLACEMENT_*.
+/// Uses the gas flows from the last finished tick.
+#[byondapi::bind]
+fn milla_get_wind_displacements(objects: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let values = objects.get_list_values()?;
+    if values.len() % 2 != 0 {
+        return Err(eyre!(
+            "Expected turf, mass pairs, but got {} values.",
+            values.len()
+        ));
+    }
+    let mut coords = Vec::with_capacity(values.len() / 2);
+    let mut masses = Vec::with_capacity(values.len() / 2);
+    for pair in values.chunks_exact(2) {
+        let (x, y, z) = byond_xyz(&pair[0])?.coordinates();
+        coords.push((x as i32 - 1, y as i32 - 1, z as i32 - 1));
+        masses.push(f32::try_from(pair[1])?);
+    }
+    let forces = internal_get_wind_forces(&coords)?;
+    let mut byond_displacements = Vec::with_capacity(values.len());
+    for (force, mass) in forces.into_iter().zip(masses) {
+        let displacement = wind::wind_displacement(force, mass);
+        byond_displacements.push(ByondValue::from(displacement[AXIS_X]));
+        byond_displacements.push(ByondValue::from(displacement[AXIS_Y]));
+    }
+    Ok(byond_displacements.as_slice().try_into()?)
+}
+
+/// Rust version of getting the wind force on a group of tiles.
+pub(crate) fn internal_get_wind_forces(
+    coords: &[(i32, i32, i32)],
+) -> Result<Vec<[f32; AXES.len()]>> {
+    let mut forces = Vec::with_capacity(coords.len());
+    for_each_active_tile(coords, |z_level, (x, y), _| {
+        forces.push(wind::wind_force(z_level, x, y));
+    })?;
+    Ok(forces)
+}
+
 /// BYOND API for starting an atmos tick.
 #[byondapi::bind]
 fn milla_spawn_tick_thread() -> eyre::Result<ByondValue> {
@@ -730,22 +1374,149 @@ fn milla_spawn_tick_thread() -> eyre::Result<ByondValue> {
         let now = Instant::now();
         let buffers = BUFFERS.get_or_init(Buffers::new);
         let result = tick::tick(buffers);
+        let mut telemetry_to_log = None;
         TICK_TIME.store(
             now.elapsed().as_millis() as usize,
             std::sync::atomic::Ordering::Relaxed,
         );
+        if let Ok(stats) = &result {
+            let audits: Vec<ZLevelAudit> = stats
+                .iter()
+                .filter_map(|z_stats| z_stats.audit.clone())
+                .collect();
+            if audits.iter().any(ZLevelAudit::is_significant) {
+                log_audits(&audits);
+            }
+            *AUDIT_REPORTS.lock().unwrap() = audits;
+            TICK_ACTIVE_TILES.store(
+                stats.iter().map(|z_stats| z_stats.active_tiles).sum(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+            TICK_SKIPPED_TILES.store(
+                stats.iter().map(|z_stats| z_stats.skipped_tiles).sum(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+            let telemetry = TickTelemetry::new(
+                TICK_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1,
+                Utc::now().to_rfc3339(),
+                now.elapsed(),
+                buffers.write_queue.lock().unwrap().last_tick_writes,
+                stats,
+            );
+            if TELEMETRY_LOG_ENABLED.load(std::sync::atomic::Ordering::Relaxed) {
+                telemetry_to_log = Some(telemetry.clone());
+            }
+            *TICK_TELEMETRY.lock().unwrap() = Some(telemetry);
+        }
         if result.is_ok() {
             call_global("milla_tick_finished", &[])?;
         } else {
             let err = format!("MILLA tick error:\n----\n{:#?}\n----", result);
             call_global("milla_tick_error", &[ByondValue::new_str(err)?])?;
         }
+        // Writing to disk can be slow, so it waits until BYOND knows the tick is done.
+        if let Some(telemetry) = telemetry_to_log {
+            telemetry::log_telemetry(&telemetry)?;
+        }
 
         Ok(())
     });
     Ok(ByondValue::null())
 }
 
+/// BYOND API for starting to work out an explosion, using the airtightness of the turf's Z level.
+/// The explosion is worked out in another thread, which calls milla_explosion_finished() with the
+/// returned ID once milla_get_explosion() can fetch it, or milla_explosion_error() if it fails.
+#[byondapi::bind]
+fn milla_spawn_explosion(epicenter: ByondValue, power: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&epicenter)?.coordinates();
+    let id = internal_spawn_explosion(
+        x as i32 - 1,
+        y as i32 - 1,
+        z as i32 - 1,
+        f32::try_from(power)?,
+    )?;
+    Ok(ByondValue::from(id as f32))
+}
+
+/// Rust version of starting an explosion.
+/// Copies what it needs out of the active buffer right away, so the explosion thread never holds
+/// a lock BYOND might want.
+pub(crate) fn internal_spawn_explosion(x: i32, y: i32, z: i32, power: f32) -> Result<usize> {
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let blast_map = {
+        let active = buffers.get_active().read().unwrap();
+        let Some(z_level) = active.0.get(z as usize) else {
+            return Err(eyre!("Bad coordinates ({}, {}, {})", x + 1, y + 1, z + 1));
+        };
+        let z_level = z_level.read().unwrap();
+        if z_level.maybe_get_index(x, y).is_none() {
+            return Err(eyre!("Bad coordinates ({}, {}, {})", x + 1, y + 1, z + 1));
+        }
+        BlastMap::of_z_level(&z_level, z)
+    };
+    let id = NEXT_EXPLOSION_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    thread::spawn(move || -> Result<(), eyre::Error> {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            blast_map.explode(x, y, power)
+        }));
+        let Ok(tiles) = result else {
+            let err = format!(
+                "MILLA explosion error:\n----\nExplosion {} panicked.\n----",
+                id
+            );
+            call_global(
+                "milla_explosion_error",
+                &[ByondValue::from(id as f32), ByondValue::new_str(err)?],
+            )?;
+            return Ok(());
+        };
+        store_explosion(id, tiles);
+        if let Err(err) = call_global("milla_explosion_finished", &[ByondValue::from(id as f32)]) {
+            // Nobody is going to collect it now.
+            take_explosion(id);
+            return Err(err.into());
+        }
+        Ok(())
+    });
+    Ok(id)
+}
+
+/// Keeps a finished explosion until BYOND collects it, and throws away any that BYOND has left
+/// for too long.
+fn store_explosion(id: usize, tiles: Vec<ExplosionTile>) {
+    let mut explosions = EXPLOSIONS.lock().unwrap();
+    explosions.retain(|(_, finished_at, _)| {
+        finished_at.elapsed().as_secs() < EXPLOSION_COLLECTION_TIMEOUT
+    });
+    explosions.push((id, Instant::now(), tiles));
+}
+
+/// Removes a finished explosion, if it's still there.
+fn take_explosion(id: usize) -> Option<Vec<ExplosionTile>> {
+    let mut explosions = EXPLOSIONS.lock().unwrap();
+    let position = explosions
+        .iter()
+        .position(|(explosion_id, _, _)| *explosion_id == id)?;
+    Some(explosions.swap_remove(position).2)
+}
+
+/// BYOND API for fetching a finished explosion, strongest tiles first. Each explosion can only
+/// be fetched once.
+/// Returns a flat list, see MILLA_EXPLOSION_TILE_*.
+#[byondapi::bind]
+fn milla_get_explosion(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let id = f32::try_from(id)? as usize;
+    let tiles = take_explosion(id).ok_or(eyre!("No finished explosion with ID {}", id))?;
+    let byond_tiles = tiles
+        .iter()
+        .flat_map(|v| Vec::from(v))
+        .collect::<Vec<ByondValue>>();
+    Ok(byond_tiles.as_slice().try_into()?)
+}
+
 /// BYOND API for asking how long the prior tick took.
 #[byondapi::bind]
 fn milla_get_tick_time() -> eyre::Result<ByondValue> {
@@ -755,6 +1526,108 @@ fn milla_get_tick_time() -> eyre::Result<ByondValue> {
     ))
 }
 
+/// BYOND API for getting telemetry for the prior tick, as a JSON string.
+/// Includes per-Z and per-phase timings, airflow iterations, and tile counts. Null if no tick has
+/// finished yet.
+#[byondapi::bind]
+fn milla_get_tick_telemetry() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    match TICK_TELEMETRY.lock().unwrap().as_ref() {
+        Some(telemetry) => Ok(ByondValue::new_str(telemetry.to_json())?),
+        None => Ok(ByondValue::null()),
+    }
+}
+
+/// BYOND API for setting how many threads MILLA ticks on, from the next tick onward.
+/// 0 means one for each CPU core.
+#[byondapi::bind]
+fn milla_set_thread_count(count: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    internal_set_thread_count(buffers, f32::try_from(count)?)?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of setting the thread count.
+pub(crate) fn internal_set_thread_count(buffers: &Buffers, count: f32) -> Result<()> {
+    if !(0.0..=MAX_THREAD_COUNT as f32).contains(&count) {
+        return Err(eyre!(
+            "Thread count must be from 0 to {}, not {}",
+            MAX_THREAD_COUNT,
+            count
+        ));
+    }
+    buffers.set_thread_count(count as usize);
+    Ok(())
+}
+
+/// BYOND API for asking how many threads MILLA ticks on.
+#[byondapi::bind]
+fn milla_get_thread_count() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok(ByondValue::from(
+        BUFFERS
+            .get_or_init(Buffers::new)
+            .worker_pool()?
+            .current_num_threads() as f32,
+    ))
+}
+
+/// BYOND API for turning the telemetry log on or off.
+/// While it's on, every tick's telemetry is appended to data/milla_telemetry.log as a line of
+/// JSON. The log is rotated once it gets too big.
+#[byondapi::bind]
+fn milla_set_telemetry_log(enabled: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    TELEMETRY_LOG_ENABLED.store(
+        bool::try_from(enabled)?,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for asking how much of the map the prior tick actually simulated.
+/// Returns list(active tiles, skipped tiles), where active tiles were simulated and skipped tiles
+/// cost nothing because nothing was happening near them.
+#[byondapi::bind]
+fn milla_get_tick_breakdown() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok([
+        ByondValue::from(TICK_ACTIVE_TILES.load(std::sync::atomic::Ordering::Relaxed) as f32),
+        ByondValue::from(TICK_SKIPPED_TILES.load(std::sync::atomic::Ordering::Relaxed) as f32),
+    ]
+    .as_slice()
+    .try_into()?)
+}
+
+/// BYOND API for connecting two Z levels, so air can flow between them.
+/// `upper_z` is directly above `lower_z`.
+#[byondapi::bind]
+fn milla_set_z_connected(
+    byond_lower_z: ByondValue,
+    byond_upper_z: ByondValue,
+    byond_connected: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    internal_set_z_connected(
+        f32::try_from(byond_lower_z)? as i32 - 1,
+        f32::try_from(byond_upper_z)? as i32 - 1,
+        bool::try_from(byond_connected)?,
+    )?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of connecting two Z levels.
+pub(crate) fn internal_set_z_connected(lower_z: i32, upper_z: i32, connected: bool) -> Result<()> {
+    for z in [lower_z, upper_z] {
+        if !(0..MAX_Z_LEVELS).contains(&z) {
+            return Err(eyre!("Bad Z level {}", z + 1));
+        }
+    }
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers.set_z_connected(lower_z as usize, upper_z as usize, connected)
+}
+
 /// BYOND API for freezing a specific z-level.
 #[byondapi::bind]
 fn milla_set_zlevel_frozen(
@@ -764,102 +1637,884 @@ fn milla_set_zlevel_frozen(
     let z = f32::try_from(byond_z)? as i32 - 1;
     let frozen = bool::try_from(byond_frozen)?;
     let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
-    let active = buffers.get_active().read().unwrap();
-    let maybe_z_level = active.0[z as usize].try_write();
-    if maybe_z_level.is_err() {
-        return Err(eyre!(
-            "Tried to freeze or unfreeze during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
-        ));
+    if z < 0 || z as usize >= buffers.get_active().read().unwrap().0.len() {
+        return Err(eyre!("Z level {} not initialized.", z + 1));
     }
-    let mut z_level = maybe_z_level.unwrap();
-    z_level.frozen = frozen;
+    buffers.write_z_level(
+        z as usize,
+        Box::new(move |z_level| {
+            z_level.frozen = frozen;
+            Vec::new()
+        }),
+    );
     Ok(ByondValue::null())
 }
 
-// Yay, tests!
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// BYOND API for creating a gas mixture that isn't on the map, like the air in a tank.
+/// `gases` is a list of moles, in gas registry order, at `temperature`, in kelvin.
+/// Returns the mixture's ID. BYOND must free it with milla_free_gas_mixture once it's done.
+#[byondapi::bind]
+fn milla_create_gas_mixture(
+    gases: ByondValue,
+    temperature: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok(ByondValue::from(internal_create_gas_mixture(
+        &conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?,
+        conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?,
+    )? as f32))
+}
 
-    // The data set by internal_set_tile() should be retrieved by internal_get_tile().
-    #[test]
-    fn set_get_loop() {
-        let test_z = 0;
-        internal_initialize(test_z).unwrap();
+/// Rust version of creating a gas mixture.
+pub(crate) fn internal_create_gas_mixture(
+    gases: &[Option<f32>],
+    temperature: Option<f32>,
+) -> Result<usize> {
+    let mut mixture = GasMixture::new();
+    mixture.add_moles(&gas_deltas(gases)?, temperature);
+    Ok(GAS_MIXTURES.lock().unwrap().create(mixture))
+}
 
-        // Set some arbitrary data.
-        internal_set_tile(
-            1,
-            2,
-            test_z,
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-            None,
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-        )
-        .unwrap();
+/// Turns BYOND's list of gas amounts into one amount per gas, with nulls as zero.
+fn gas_deltas(gases: &[Option<f32>]) -> Result<Vec<f32>> {
+    check_gas_count(gases.len())?;
+    Ok(gases.iter().map(|moles| moles.unwrap_or(0.0)).collect())
+}
 
-        // Check that we got the same data back.
-        {
-            let tile = internal_get_tile(1, 2, test_z).unwrap();
-            for gas in 0..GAS_COUNT {
-                if gas % 2 == 0 {
-                    assert_eq!(tile.gases.values[gas], 0.0, "{}", gas);
-                } else {
-                    assert_eq!(tile.gases.values[gas], 1.0, "{}", gas);
-                }
-            }
-            assert_eq!(tile.thermal_energy, 0.0);
-            assert_eq!(tile.innate_heat_capacity, 1.0);
-            assert_eq!(tile.hotspot_temperature, 0.0);
-            assert_eq!(tile.hotspot_volume, 1.0);
-        }
+/// BYOND API for freeing a gas mixture. Its ID may be reused.
+#[byondapi::bind]
+fn milla_free_gas_mixture(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    GAS_MIXTURES
+        .lock()
+        .unwrap()
+        .free(f32::try_from(id)? as usize)?;
+    Ok(ByondValue::null())
+}
 
-        // Set a different set of arbitrary data.
-        internal_set_tile(
-            1,
-            1,
-            test_z,
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-            None,
-            None,
-            Some(1.0),
-            None,
-            Some(1.0),
-            None,
-        )
-        .unwrap();
+/// BYOND API for fetching the contents of a gas mixture.
+/// Returns list(temperature, heat capacity, total moles, moles of each gas in registry order).
+#[byondapi::bind]
+fn milla_get_gas_mixture(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mixtures = GAS_MIXTURES.lock().unwrap();
+    let mixture = mixtures.get(f32::try_from(id)? as usize)?;
+    let mut values = vec![
+        ByondValue::from(mixture.temperature()),
+        ByondValue::from(mixture.gases.heat_capacity()),
+        ByondValue::from(mixture.gases.moles()),
+    ];
+    for gas in 0..gas_registry().count() {
+        values.push(ByondValue::from(mixture.gases.get(gas)));
+    }
+    Ok(values.as_slice().try_into()?)
+}
 
-        // Check that we got the same data back.
-        {
-            let tile = internal_get_tile(1, 1, test_z).unwrap();
-            for gas in 0..GAS_COUNT {
-                if gas % 2 == 0 {
-                    assert_eq!(tile.gases.values[gas], 1.0, "{}", gas);
-                } else {
+/// BYOND API for the pressure a gas mixture would have in `volume` liters, in kPa.
+#[byondapi::bind]
+fn milla_get_gas_mixture_pressure(id: ByondValue, volume: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mixtures = GAS_MIXTURES.lock().unwrap();
+    let mixture = mixtures.get(f32::try_from(id)? as usize)?;
+    let volume = f32::try_from(volume)?;
+    if !volume.is_finite() {
+        return Err(eyre!("Invalid gas mixture volume {}", volume));
+    }
+    Ok(ByondValue::from(mixture.pressure(volume)))
+}
+
+/// BYOND API for replacing the contents of a gas mixture.
+/// `gases` is a list of moles, in gas registry order. Nulls leave things unchanged.
+#[byondapi::bind]
+fn milla_set_gas_mixture(
+    id: ByondValue,
+    gases: ByondValue,
+    temperature: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let gases = conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?;
+    let temperature = conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?;
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    let mixture = mixtures.get_mut(f32::try_from(id)? as usize)?;
+    let old_temperature = mixture.temperature();
+    set_gases(&mut mixture.gases, &gases)?;
+    mixture.thermal_energy = temperature.unwrap_or(old_temperature) * mixture.gases.heat_capacity();
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for adding gas to a mixture, or removing it with negative amounts.
+/// `deltas` is a list of moles, in gas registry order, with nulls as zero. Added gas arrives at
+/// `temperature`, or the mixture's temperature if that's null.
+#[byondapi::bind]
+fn milla_add_gas_mixture_moles(
+    id: ByondValue,
+    deltas: ByondValue,
+    temperature: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let deltas = gas_deltas(&conversion::bounded_byond_list_to_option_f32s(
+        deltas,
+        f32::MIN,
+        f32::MAX,
+    )?)?;
+    let temperature = conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?;
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    mixtures
+        .get_mut(f32::try_from(id)? as usize)?
+        .add_moles(&deltas, temperature);
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for moving all the gas in one mixture into another, leaving the first one empty.
+#[byondapi::bind]
+fn milla_merge_gas_mixtures(into: ByondValue, from: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    let (into_mixture, from_mixture) =
+        mixtures.get_pair_mut(f32::try_from(into)? as usize, f32::try_from(from)? as usize)?;
+    into_mixture.merge(from_mixture);
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for taking `ratio` of the gas in a mixture out into a new one.
+/// Returns the new mixture's ID.
+#[byondapi::bind]
+fn milla_split_gas_mixture(id: ByondValue, ratio: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let ratio = conversion::bounded_byond_to_option_f32(ratio, 0.0, 1.0)?
+        .ok_or(eyre!("Split ratio is required."))?;
+    Ok(ByondValue::from(
+        internal_split_gas_mixture(f32::try_from(id)? as usize, ratio)? as f32,
+    ))
+}
+
+/// BYOND API for taking `removed_volume` liters of gas out of a mixture that fills `volume`
+/// liters, into a new mixture.
+/// Returns the new mixture's ID.
+#[byondapi::bind]
+fn milla_split_gas_mixture_volume(
+    id: ByondValue,
+    volume: ByondValue,
+    removed_volume: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let volume = f32::try_from(volume)?;
+    if !volume.is_finite() || volume <= 0.0 {
+        return Err(eyre!("Invalid gas mixture volume {}", volume));
+    }
+    let removed_volume = f32::try_from(removed_volume)?;
+    if !removed_volume.is_finite() {
+        return Err(eyre!("Invalid removed volume {}", removed_volume));
+    }
+    let ratio = (removed_volume / volume).clamp(0.0, 1.0);
+    Ok(ByondValue::from(
+        internal_split_gas_mixture(f32::try_from(id)? as usize, ratio)? as f32,
+    ))
+}
+
+/// Rust version of splitting a gas mixture.
+pub(crate) fn internal_split_gas_mixture(id: usize, ratio: f32) -> Result<usize> {
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    let removed = mixtures.get_mut(id)?.split(ratio);
+    Ok(mixtures.create(removed))
+}
+
+/// BYOND API for letting two gas mixtures, filling the given volumes in liters, even out.
+#[byondapi::bind]
+fn milla_equalize_gas_mixtures(
+    first: ByondValue,
+    first_volume: ByondValue,
+    second: ByondValue,
+    second_volume: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let first_volume = conversion::bounded_byond_to_option_f32(first_volume, 0.0, f32::INFINITY)?
+        .ok_or(eyre!("Volume is required."))?;
+    let second_volume = conversion::bounded_byond_to_option_f32(second_volume, 0.0, f32::INFINITY)?
+        .ok_or(eyre!("Volume is required."))?;
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    let (first_mixture, second_mixture) = mixtures.get_pair_mut(
+        f32::try_from(first)? as usize,
+        f32::try_from(second)? as usize,
+    )?;
+    first_mixture.equalize(first_volume, second_mixture, second_volume);
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for running reactions on a gas mixture, with the same reactions as the map.
+/// Returns how much fuel was burnt.
+#[byondapi::bind]
+fn milla_react_gas_mixture(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let reactions = current_reactions().read().unwrap().clone();
+    let mut mixtures = GAS_MIXTURES.lock().unwrap();
+    let fuel_burnt = mixtures
+        .get_mut(f32::try_from(id)? as usize)?
+        .react(&reactions);
+    Ok(ByondValue::from(fuel_burnt))
+}
+
+/// BYOND API for creating an empty pipenet, with a volume in liters.
+/// Returns the pipenet's ID.
+#[byondapi::bind]
+fn milla_create_pipenet(volume: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok(ByondValue::from(
+        internal_create_pipenet(f32::try_from(volume)?)? as f32,
+    ))
+}
+
+/// Rust version of creating a pipenet.
+pub(crate) fn internal_create_pipenet(volume: f32) -> Result<usize> {
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    pipenets.create_net(volume)
+}
+
+/// BYOND API for removing a pipenet, along with any devices attached to it.
+#[byondapi::bind]
+fn milla_remove_pipenet(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    pipenets.remove_net(f32::try_from(id)? as usize)?;
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for changing a pipenet, such as when pipes are added, or a canister is filled.
+/// `gases` is a list of moles, in gas registry order. Nulls leave things unchanged.
+#[byondapi::bind]
+fn milla_set_pipenet(
+    id: ByondValue,
+    volume: ByondValue,
+    gases: ByondValue,
+    temperature: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    internal_set_pipenet(
+        f32::try_from(id)? as usize,
+        conversion::byond_to_option_f32(volume)?,
+        &conversion::bounded_byond_list_to_option_f32s(gases, 0.0, f32::INFINITY)?,
+        conversion::bounded_byond_to_option_f32(temperature, 0.0, f32::INFINITY)?,
+    )?;
+    Ok(ByondValue::null())
+}
+
+/// Rust version of changing a pipenet.
+pub(crate) fn internal_set_pipenet(
+    id: usize,
+    volume: Option<f32>,
+    gases: &[Option<f32>],
+    temperature: Option<f32>,
+) -> Result<()> {
+    if let Some(value) = volume {
+        if !value.is_finite() || value <= 0.0 {
+            return Err(eyre!("Invalid pipenet volume {}", value));
+        }
+    }
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    let net = pipenets.get_net_mut(id)?;
+    if let Some(value) = volume {
+        net.volume = value;
+    }
+    set_gases(&mut net.gases, gases)?;
+    if let Some(value) = temperature {
+        net.thermal_energy = value * net.gases.heat_capacity();
+    }
+    Ok(())
+}
+
+/// BYOND API for fetching the contents of a pipenet.
+/// Returns list(volume, pressure, temperature, moles of each gas in registry order).
+#[byondapi::bind]
+fn milla_get_pipenet(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let pipenets = buffers.pipenets.read().unwrap();
+    let net = pipenets.get_net(f32::try_from(id)? as usize)?;
+    let mut values = vec![
+        ByondValue::from(net.volume),
+        ByondValue::from(net.pressure()),
+        ByondValue::from(net.temperature()),
+    ];
+    for gas in 0..gas_registry().count() {
+        values.push(ByondValue::from(net.gases.get(gas)));
+    }
+    Ok(values.as_slice().try_into()?)
+}
+
+/// BYOND API for adding a passive vent, which lets a pipenet equalize with a turf.
+/// Returns the device's ID.
+#[byondapi::bind]
+fn milla_add_vent(id: ByondValue, turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    Ok(ByondValue::from(internal_add_device(Device::Vent {
+        net: f32::try_from(id)? as usize,
+        x: x as i32 - 1,
+        y: y as i32 - 1,
+        z: z as i32 - 1,
+    })? as f32))
+}
+
+/// BYOND API for adding a scrubber, which pulls the gases with the given IDs out of a turf and
+/// into a pipenet.
+/// Returns the device's ID.
+#[byondapi::bind]
+fn milla_add_scrubber(
+    id: ByondValue,
+    turf: ByondValue,
+    gas_ids: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let registry = gas_registry();
+    let mut gases = Vec::new();
+    for gas_id in gas_ids.get_list_values()? {
+        let gas_id = gas_id.get_string()?;
+        gases.push(
+            registry
+                .find(&gas_id)
+                .ok_or(eyre!("Unknown gas {}", gas_id))?,
+        );
+    }
+    Ok(ByondValue::from(internal_add_device(Device::Scrubber {
+        net: f32::try_from(id)? as usize,
+        x: x as i32 - 1,
+        y: y as i32 - 1,
+        z: z as i32 - 1,
+        gases,
+    })? as f32))
+}
+
+/// BYOND API for adding a pump, which moves gas from one pipenet to another until the output
+/// reaches the target pressure, in kPa.
+/// Returns the device's ID.
+#[byondapi::bind]
+fn milla_add_pump(
+    input: ByondValue,
+    output: ByondValue,
+    target_pressure: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok(ByondValue::from(internal_add_device(Device::Pump {
+        input: f32::try_from(input)? as usize,
+        output: f32::try_from(output)? as usize,
+        target_pressure: f32::try_from(target_pressure)?.clamp(0.0, MAX_PUMP_PRESSURE),
+    })? as f32))
+}
+
+/// BYOND API for adding a connector, which lets two pipenets equalize.
+/// Returns the device's ID.
+#[byondapi::bind]
+fn milla_add_connector(first: ByondValue, second: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    Ok(ByondValue::from(internal_add_device(Device::Connector {
+        first: f32::try_from(first)? as usize,
+        second: f32::try_from(second)? as usize,
+    })? as f32))
+}
+
+/// Rust version of adding a device.
+pub(crate) fn internal_add_device(device: Device) -> Result<usize> {
+    if let Device::Vent { x, y, z, .. } | Device::Scrubber { x, y, z, .. } = device {
+        require_tile_exists((x, y, z))?;
+    }
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    pipenets.add_device(device)
+}
+
+/// BYOND API for changing the target pressure of a pump, in kPa.
+#[byondapi::bind]
+fn milla_set_pump_target(
+    device: ByondValue,
+    target_pressure: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    let id = f32::try_from(device)? as usize;
+    match pipenets.get_device_mut(id)? {
+        Device::Pump {
+            target_pressure: target,
+            ..
+        } => *target = f32::try_from(target_pressure)?.clamp(0.0, MAX_PUMP_PRESSURE),
+        _ => return Err(eyre!("Device {} isn't a pump.", id)),
+    }
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for removing a device.
+#[byondapi::bind]
+fn milla_remove_device(device: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut pipenets = buffers.pipenets.write().unwrap();
+    pipenets.remove_device(f32::try_from(device)? as usize)?;
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for adding a gas emitter, which adds gas to a turf or removes it every tick.
+/// `rates` is a list of moles per tick, in gas registry order. Negative rates remove gas, and
+/// nulls are treated as zero. Added gas arrives at `temperature`, in kelvin.
+/// If `pressure_cap` isn't null, the emitter stops adding gas once the turf reaches that pressure,
+/// in kPa.
+/// Returns the emitter's ID.
+#[byondapi::bind]
+fn milla_add_gas_emitter(
+    turf: ByondValue,
+    rates: ByondValue,
+    temperature: ByondValue,
+    pressure_cap: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    Ok(ByondValue::from(internal_add_gas_emitter(
+        x as i32 - 1,
+        y as i32 - 1,
+        z as i32 - 1,
+        &conversion::bounded_byond_list_to_option_f32s(rates, -MAX_EMITTER_RATE, MAX_EMITTER_RATE)?,
+        f32::try_from(temperature)?,
+        conversion::byond_to_option_f32(pressure_cap)?,
+    )? as f32))
+}
+
+/// Rust version of adding a gas emitter.
+pub(crate) fn internal_add_gas_emitter(
+    x: i32,
+    y: i32,
+    z: i32,
+    rates: &[Option<f32>],
+    temperature: f32,
+    pressure_cap: Option<f32>,
+) -> Result<usize> {
+    check_gas_count(rates.len())?;
+    require_tile_exists((x, y, z))?;
+    let mut emitter = GasEmitter {
+        x,
+        y,
+        z,
+        rates: [0.0; MAX_GAS_COUNT],
+        temperature,
+        pressure_cap,
+    };
+    for (gas, maybe_rate) in rates.iter().enumerate() {
+        emitter.rates[gas] = maybe_rate.unwrap_or(0.0);
+    }
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers.emitters.write().unwrap().add(emitter)
+}
+
+/// BYOND API for removing a gas emitter.
+#[byondapi::bind]
+fn milla_remove_gas_emitter(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut emitters = buffers.emitters.write().unwrap();
+    emitters.remove(f32::try_from(id)? as usize)?;
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for adding a heater, which heats or cools a turf every tick.
+/// `power` is in joules per tick, and negative power cools. Heaters stop once the turf reaches
+/// `target_temperature`, in kelvin, if it isn't null.
+/// Heaters warm the turf's air if it has any, and the turf itself if not.
+/// Returns the heater's ID.
+#[byondapi::bind]
+fn milla_add_heater(
+    turf: ByondValue,
+    power: ByondValue,
+    target_temperature: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    Ok(ByondValue::from(internal_add_heater(Heater {
+        x: x as i32 - 1,
+        y: y as i32 - 1,
+        z: z as i32 - 1,
+        power: f32::try_from(power)?,
+        target_temperature: conversion::byond_to_option_f32(target_temperature)?,
+    })? as f32))
+}
+
+/// Rust version of adding a heater.
+pub(crate) fn internal_add_heater(heater: Heater) -> Result<usize> {
+    require_tile_exists((heater.x, heater.y, heater.z))?;
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers.heaters.write().unwrap().add(heater)
+}
+
+/// BYOND API for removing a heater.
+#[byondapi::bind]
+fn milla_remove_heater(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut heaters = buffers.heaters.write().unwrap();
+    heaters.remove(f32::try_from(id)? as usize)?;
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for watching a turf, or a block of turfs, for a value crossing a threshold.
+/// `quantity` is "pressure", "temperature", or a gas ID.
+/// `direction` is a bitfield of MILLA_WATCH_RISING and MILLA_WATCH_FALLING.
+/// Returns the rule's ID. Fired rules are collected by milla_get_watch_events().
+#[byondapi::bind]
+fn milla_add_watch(
+    low_corner: ByondValue,
+    high_corner: ByondValue,
+    quantity: ByondValue,
+    threshold: ByondValue,
+    direction: ByondValue,
+) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (low_x, low_y, low_z) = byond_xyz(&low_corner)?.coordinates();
+    let (high_x, high_y, high_z) = if high_corner.is_null() {
+        (low_x, low_y, low_z)
+    } else {
+        byond_xyz(&high_corner)?.coordinates()
+    };
+    if low_z != high_z {
+        return Err(eyre!("Watch rules must stay on one Z level."));
+    }
+    let quantity = match quantity.get_string()?.as_str() {
+        "pressure" => WatchQuantity::Pressure,
+        "temperature" => WatchQuantity::Temperature,
+        gas_id => WatchQuantity::Gas(
+            gas_registry()
+                .find(gas_id)
+                .ok_or(eyre!("Unknown gas {}", gas_id))?,
+        ),
+    };
+    let direction = WatchDirection::from_bits_truncate(f32::try_from(direction)? as u8);
+    Ok(ByondValue::from(internal_add_watch(WatchRule {
+        low: (low_x as i32 - 1, low_y as i32 - 1),
+        high: (high_x as i32 - 1, high_y as i32 - 1),
+        z: low_z as i32 - 1,
+        quantity,
+        threshold: f32::try_from(threshold)?,
+        direction,
+    })? as f32))
+}
+
+/// Rust version of adding a watch rule.
+pub(crate) fn internal_add_watch(rule: WatchRule) -> Result<usize> {
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let active = buffers.get_active().read().unwrap();
+    let z_level = active
+        .0
+        .get(rule.z as usize)
+        .ok_or(eyre!("Z level {} not initialized.", rule.z + 1))?
+        .read()
+        .unwrap();
+    for (x, y) in [rule.low, rule.high] {
+        if z_level.maybe_get_index(x, y).is_none() {
+            return Err(eyre!(
+                "Bad coordinates ({}, {}, {})",
+                x + 1,
+                y + 1,
+                rule.z + 1
+            ));
+        }
+    }
+    buffers.watch_rules.write().unwrap().add(rule, &z_level)
+}
+
+/// BYOND API for removing a watch rule.
+#[byondapi::bind]
+fn milla_remove_watch(id: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    let mut watch_rules = buffers.watch_rules.write().unwrap();
+    watch_rules.remove(f32::try_from(id)? as usize)?;
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for getting the watch rules that fired during the last tick.
+/// Returns a flat list, see MILLA_WATCH_EVENT_* in code/__DEFINES/rust.dm
+#[byondapi::bind]
+fn milla_get_watch_events() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let watch_events = WATCH_EVENTS.lock().unwrap();
+    let byond_watch_events = watch_events
+        .iter()
+        .flat_map(|v| Vec::from(v))
+        .collect::<Vec<ByondValue>>();
+    Ok(byond_watch_events.as_slice().try_into()?)
+}
+
+/// BYOND API for getting the ID of the zone a turf is in, or null if it's space or a wall.
+/// Turfs on the same Z level with the same zone ID share air. IDs change when walls do.
+#[byondapi::bind]
+fn milla_get_zone(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    match internal_get_zone(x as i32 - 1, y as i32 - 1, z as i32 - 1)? {
+        Some(zone) => Ok(ByondValue::from(zone.id as f32)),
+        None => Ok(ByondValue::null()),
+    }
+}
+
+/// BYOND API for getting every turf that shares air with a turf.
+/// Returns an empty list for space and walls.
+#[byondapi::bind]
+fn milla_get_zone_turfs(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let mut turfs = Vec::new();
+    if let Some(zone) = internal_get_zone(x as i32 - 1, y as i32 - 1, z as i32 - 1)? {
+        for (zone_x, zone_y) in zone.tiles {
+            // +1 here to convert from our 0-indexing to BYOND's 1-indexing.
+            turfs.push(byond_locatexyz(ByondXYZ::with_coords((
+                zone_x as i16 + 1,
+                zone_y as i16 + 1,
+                z as i16,
+            )))?);
+        }
+    }
+    Ok(turfs.as_slice().try_into()?)
+}
+
+/// BYOND API for summing up the atmos in the zone a turf is in. See MILLA_REGION_INDEX_*.
+/// Returns null for space and walls.
+#[byondapi::bind]
+fn milla_get_zone_stats(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let Some(stats) = internal_get_zone_stats(x as i32 - 1, y as i32 - 1, z as i32 - 1)? else {
+        return Ok(ByondValue::null());
+    };
+    let values: Vec<ByondValue> = (&stats).into();
+    Ok(values.as_slice().try_into()?)
+}
+
+/// Rust version of summing up the atmos in the zone a tile is in.
+/// Like the zone itself, this sees the last finished tick plus any writes made since.
+pub(crate) fn internal_get_zone_stats(x: i32, y: i32, z: i32) -> Result<Option<RegionStats>> {
+    let Some(zone) = internal_get_zone(x, y, z)? else {
+        return Ok(None);
+    };
+    let coords: Vec<(i32, i32, i32)> = zone
+        .tiles
+        .iter()
+        .map(|&(zone_x, zone_y)| (zone_x, zone_y, z))
+        .collect();
+    Ok(Some(internal_get_region_stats(&coords)?))
+}
+
+/// BYOND API for checking whether the zone a turf is in is open to space.
+#[byondapi::bind]
+fn milla_is_zone_breached(turf: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let (x, y, z) = byond_xyz(&turf)?.coordinates();
+    let breached = internal_get_zone(x as i32 - 1, y as i32 - 1, z as i32 - 1)?
+        .is_some_and(|zone| zone.breached);
+    Ok(ByondValue::from(breached))
+}
+
+/// A zone, as seen from outside MILLA.
+pub(crate) struct ZoneInfo {
+    pub(crate) id: usize,
+    /// Coordinates of each tile in the zone, 0-indexed.
+    pub(crate) tiles: Vec<(i32, i32)>,
+    pub(crate) breached: bool,
+}
+
+/// Rust version of looking up the zone a tile is in.
+/// Brings the Z level's zones up to date first.
+pub(crate) fn internal_get_zone(x: i32, y: i32, z: i32) -> Result<Option<ZoneInfo>> {
+    let buffers = BUFFERS.get().ok_or(eyre!("BUFFERS not initialized."))?;
+    let active = buffers.get_active().read().unwrap();
+    let z_level = active
+        .0
+        .get(z as usize)
+        .ok_or(eyre!("Z level {} not initialized.", z + 1))?
+        .read()
+        .unwrap();
+    let index = z_level.maybe_get_index(x, y).ok_or(eyre!(
+        "Bad coordinates ({}, {}, {})",
+        x + 1,
+        y + 1,
+        z + 1
+    ))?;
+    let mut zones = buffers.zones.write().unwrap();
+    let zone_map = zones.level_mut(z as usize);
+    zone_map.refresh(&z_level);
+    let Some(id) = zone_map.zone_of(index) else {
+        return Ok(None);
+    };
+    let zone = zone_map.get_zone(id).unwrap();
+    Ok(Some(ZoneInfo {
+        id,
+        tiles: zone
+            .tiles
+            .iter()
+            .map(|&index| z_level.get_coords(index))
+            .collect(),
+        breached: zone.breached,
+    }))
+}
+
+/// Appends a tick's audits to today's audit log, for when audit mode finds something.
+fn log_audits(audits: &[ZLevelAudit]) {
+    let now = Utc::now();
+    let mut message = format!("MILLA audit at {}\n", now.format("%H:%M:%S%.3f"));
+    for audit in audits.iter().filter(|audit| audit.is_significant()) {
+        message += &audit.to_string();
+    }
+    let _ = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(now.format("data/milla_audit_%Y%m%d.log").to_string())
+        .and_then(|mut file| file.write_all(message.as_bytes()));
+}
+
+/// BYOND API for turning deterministic mode on or off.
+/// In deterministic mode, the same inputs always give exactly the same tick results, including
+/// the order of interesting tiles. It makes ticks a little slower.
+#[byondapi::bind]
+fn milla_set_deterministic(enabled: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers.deterministic.store(
+        bool::try_from(enabled)?,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for turning audit mode on or off.
+/// Audit mode tracks where gas and heat go during each tick, and logs any drift to
+/// data/milla_audit_*.log. It makes ticks much slower.
+#[byondapi::bind]
+fn milla_set_audit(enabled: ByondValue) -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let enabled = bool::try_from(enabled)?;
+    let buffers = BUFFERS.get_or_init(Buffers::new);
+    buffers
+        .audit
+        .store(enabled, std::sync::atomic::Ordering::Relaxed);
+    if !enabled {
+        AUDIT_REPORTS.lock().unwrap().clear();
+    }
+    Ok(ByondValue::null())
+}
+
+/// BYOND API for getting how much each phase of the last tick changed each Z level's gas and
+/// heat, while audit mode is on.
+/// Returns a flat list, see MILLA_AUDIT_INDEX_* in code/__DEFINES/rust.dm
+#[byondapi::bind]
+fn milla_get_audit() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let gas_count = gas_registry().count();
+    let audits = AUDIT_REPORTS.lock().unwrap();
+    let mut values = Vec::new();
+    for audit in audits.iter() {
+        for phase in AuditPhase::ALL {
+            let change = &audit.changes[phase as usize];
+            values.push(ByondValue::from((audit.z + 1) as f32));
+            values.push(ByondValue::from(phase as u8 as f32));
+            values.push(ByondValue::from(change.thermal_energy as f32));
+            values.push(ByondValue::from(change.non_finite as f32));
+            for gas in 0..gas_count {
+                values.push(ByondValue::from(change.moles[gas] as f32));
+            }
+        }
+    }
+    Ok(values.as_slice().try_into()?)
+}
+
+/// BYOND API for getting the tiles that drifted the most during the last tick, while audit mode
+/// is on.
+/// Returns a flat list, see MILLA_AUDIT_TILE_* in code/__DEFINES/rust.dm
+#[byondapi::bind]
+fn milla_get_audit_worst_tiles() -> eyre::Result<ByondValue> {
+    logging::setup_panic_handler();
+    let audits = AUDIT_REPORTS.lock().unwrap();
+    let values = audits
+        .iter()
+        .flat_map(|audit| audit.worst_tiles.iter())
+        .flat_map(|v| Vec::from(v))
+        .collect::<Vec<ByondValue>>();
+    Ok(values.as_slice().try_into()?)
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::gases::GasRegistry;
+
+    // The data set by internal_set_tile() should be retrieved by internal_get_tile().
+    #[test]
+    fn set_get_loop() {
+        let test_z = 0;
+        internal_initialize(test_z, DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE).unwrap();
+
+        // Set some arbitrary data.
+        internal_set_tile(
+            1,
+            2,
+            test_z,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[None, Some(1.0), None, Some(1.0), None, Some(1.0)],
+            None,
+            None,
+            Some(1.0),
+            None,
+            Some(1.0),
+        )
+        .unwrap();
+
+        // Check that we got the same data back.
+        {
+            let tile = internal_get_tile(1, 2, test_z).unwrap();
+            for gas in 0..BUILTIN_GAS_COUNT {
+                if gas % 2 == 0 {
+                    assert_eq!(tile.gases.values[gas], 0.0, "{}", gas);
+                } else {
+                    assert_eq!(tile.gases.values[gas], 1.0, "{}", gas);
+                }
+            }
+            assert_eq!(tile.thermal_energy, 0.0);
+            assert_eq!(tile.innate_heat_capacity, 1.0);
+            assert_eq!(tile.hotspot_temperature, 0.0);
+            assert_eq!(tile.hotspot_volume, 1.0);
+        }
+
+        // Set a different set of arbitrary data.
+        internal_set_tile(
+            1,
+            1,
+            test_z,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[Some(1.0), None, Some(1.0), None, Some(1.0), None],
+            None,
+            Some(1.0),
+            None,
+            Some(1.0),
+            None,
+        )
+        .unwrap();
+
+        // Check that we got the same data back.
+        {
+            let tile = internal_get_tile(1, 1, test_z).unwrap();
+            for gas in 0..BUILTIN_GAS_COUNT {
+                if gas % 2 == 0 {
+                    assert_eq!(tile.gases.values[gas], 1.0, "{}", gas);
+                } else {
                     assert_eq!(tile.gases.values[gas], 0.0, "{}", gas);
                 }
             }
@@ -869,4 +2524,413 @@ mod tests {
             assert_eq!(tile.hotspot_volume, 0.0);
         }
     }
+
+    // Z levels smaller than the world should load their own turfs, and refuse anything outside
+    // them rather than make up air for it.
+    #[test]
+    fn small_level() {
+        let test_z = 6;
+        internal_initialize(test_z, 3, 2).unwrap();
+        let mut data = vec![Some(0.0); 6];
+        data.extend([Some(21.0), None, Some(79.0), None, None, None]);
+        data.push(Some(T20C));
+        data.extend([Some(1.0); 4]);
+        data.push(Some(0.0));
+        internal_load_turf(2, 1, test_z, &data).unwrap();
+        assert_eq!(
+            internal_get_tile(2, 1, test_z)
+                .unwrap()
+                .gases
+                .get(GAS_OXYGEN),
+            21.0
+        );
+        assert!(internal_load_turf(3, 1, test_z, &data).is_err());
+        assert!(internal_load_turf(0, 2, test_z, &data).is_err());
+        assert!(internal_get_tile(3, 1, test_z).is_err());
+        assert!(internal_get_tile(0, 0, MAX_Z_LEVELS).is_err());
+    }
+
+    // Turfs should load with just the builtin gases even after another gas is registered.
+    // Registering gases in the global registry would change the gas count under other tests, so
+    // this uses its own registry for the layout, and loads a builtin-only turf for real.
+    #[test]
+    fn load_turfs_with_registered_gas() {
+        let test_z = 2;
+        internal_initialize(test_z, 10, 10).unwrap();
+        let registry = GasRegistry::new();
+        let hydrogen = registry
+            .register(GasInfo::new("hydrogen", "Hydrogen", 15.0, None, 2.0))
+            .unwrap();
+        let registered_count = registry.count();
+
+        // Builtin layout, as Initialize_Atmos() sends it.
+        let mut data = vec![Some(0.0); 6];
+        data.extend([Some(21.0), None, Some(79.0), None, None, None]);
+        data.push(Some(T20C));
+        data.extend([Some(1.0); 4]);
+        data.push(Some(0.0));
+        let (gases, after_gases) = turf_data_gases(&data, registered_count).unwrap();
+        assert_eq!(gases.len(), registered_count);
+        assert_eq!(gases[GAS_OXYGEN], Some(21.0));
+        assert_eq!(gases[GAS_CARBON_DIOXIDE], None);
+        assert_eq!(gases[hydrogen], Some(0.0));
+        assert_eq!(data[after_gases], Some(T20C));
+        internal_load_turf(1, 1, test_z, &data).unwrap();
+        {
+            let tile = internal_get_tile(1, 1, test_z).unwrap();
+            assert_eq!(tile.gases.values[GAS_OXYGEN], 21.0);
+            assert_eq!(tile.gases.values[GAS_NITROGEN], 79.0);
+            assert_eq!(tile.temperature(), T20C);
+        }
+
+        // Full layout, with a value for every registered gas.
+        let mut data = vec![Some(0.0); 6];
+        data.extend(vec![Some(0.0); registered_count]);
+        data[6 + hydrogen] = Some(5.0);
+        data.push(Some(T20C));
+        data.extend([Some(1.0); 4]);
+        data.push(Some(0.0));
+        let (gases, after_gases) = turf_data_gases(&data, registered_count).unwrap();
+        assert_eq!(gases[hydrogen], Some(5.0));
+        assert_eq!(data[after_gases], Some(T20C));
+
+        // Anything else is rejected.
+        data.push(Some(0.0));
+        assert!(turf_data_gases(&data, registered_count).is_err());
+        assert!(internal_load_turf(3, 1, test_z, &data).is_err());
+    }
+
+    // A turf loaded with the map should tick exactly like the same turf set by
+    // initialize_turf, including its own heat capacity.
+    #[test]
+    fn loaded_turf_ticks_like_set_turf() {
+        let test_z = 3;
+        internal_initialize(test_z, 10, 10).unwrap();
+        let gas_count = gas_registry().count();
+        let mut gases = vec![Some(0.0); gas_count];
+        gases[GAS_OXYGEN] = Some(21.0);
+        gases[GAS_NITROGEN] = Some(79.0);
+
+        // A floor with plenty of thermal mass, and a default /turf with barely any.
+        for (x, heat_capacity) in [(1, 100000.0), (2, 1.0)] {
+            let mut data = vec![Some(1.0); 4];
+            data.extend([Some(1.0), None]);
+            data.extend(gases.iter().copied());
+            data.push(Some(350.0));
+            data.extend([Some(0.0); 4]);
+            data.push(Some(heat_capacity));
+            internal_load_turf(x, 1, test_z, &data).unwrap();
+
+            internal_set_tile(
+                x,
+                2,
+                test_z,
+                Some(1.0),
+                Some(1.0),
+                Some(1.0),
+                Some(1.0),
+                Some(1.0),
+                None,
+                &gases,
+                Some(350.0),
+                None,
+                Some(heat_capacity),
+                Some(0.0),
+                Some(0.0),
+            )
+            .unwrap();
+            internal_reset_superconductivity(x, 2, test_z).unwrap();
+            internal_reduce_superconductivity(
+                x,
+                2,
+                test_z,
+                Some(0.0),
+                Some(0.0),
+                Some(0.0),
+                Some(0.0),
+            )
+            .unwrap();
+
+            let loaded = internal_get_tile(x, 1, test_z).unwrap();
+            let set = internal_get_tile(x, 2, test_z).unwrap();
+            assert_eq!(loaded.innate_heat_capacity, heat_capacity);
+            assert_eq!(
+                loaded.has_thermal_mass(),
+                heat_capacity >= MINIMUM_INNATE_HEAT_CAPACITY
+            );
+
+            // Tick both side by side, with cold air next to them.
+            let buffers = Buffers::new();
+            buffers.init_z_level(0, 2, 2).unwrap();
+            {
+                let active = buffers.get_active().read().unwrap();
+                let mut z_level = active.0[0].write().unwrap();
+                for (y, tile) in [(0, &loaded), (1, &set)] {
+                    let index = z_level.maybe_get_index(0, y).unwrap();
+                    z_level.edit_tile(index).copy_from(tile);
+                    let mut cold = tile.clone();
+                    cold.thermal_energy = cold.heat_capacity() * T0C;
+                    cold.innate_heat_capacity = 0.0;
+                    cold.innate_thermal_energy = 0.0;
+                    cold.airtight_directions = AirtightDirections::empty();
+                    let index = z_level.maybe_get_index(1, y).unwrap();
+                    z_level.edit_tile(index).copy_from(&cold);
+                }
+            }
+            tick::tick(&buffers).unwrap();
+            let active = buffers.get_active().read().unwrap();
+            let z_level = active.0[0].read().unwrap();
+            let loaded = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
+            let set = z_level.get_tile(z_level.maybe_get_index(0, 1).unwrap());
+            assert_eq!(
+                loaded.thermal_energy, set.thermal_energy,
+                "{}",
+                heat_capacity
+            );
+            assert_eq!(
+                loaded.innate_thermal_energy, set.innate_thermal_energy,
+                "{}",
+                heat_capacity
+            );
+        }
+    }
+
+    // Tiles shouldn't accept more gases than are registered.
+    #[test]
+    fn too_many_gases() {
+        let test_z = 0;
+        internal_initialize(test_z, DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE).unwrap();
+
+        let gases = vec![Some(1.0); MAX_GAS_COUNT + 1];
+        assert!(internal_set_tile(
+            3, 3, test_z, None, None, None, None, None, None, &gases, None, None, None, None, None,
+        )
+        .is_err());
+    }
+
+    // Pipenets should take their contents from BYOND, and devices should only go on real tiles.
+    #[test]
+    fn pipenet_setup() {
+        let test_z = 0;
+        internal_initialize(test_z, DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE).unwrap();
+
+        let net = internal_create_pipenet(70.0).unwrap();
+        internal_set_pipenet(net, None, &[Some(5.0)], Some(T20C)).unwrap();
+        assert!(internal_set_pipenet(net, Some(-1.0), &[], None).is_err());
+        {
+            let buffers = BUFFERS.get().unwrap();
+            let pipenets = buffers.pipenets.read().unwrap();
+            let pipenet = pipenets.get_net(net).unwrap();
+            assert_eq!(pipenet.volume, 70.0);
+            assert_eq!(pipenet.gases.get(GAS_OXYGEN), 5.0);
+            assert!((pipenet.temperature() - T20C).abs() < 0.01);
+        }
+
+        let vent = Device::Vent {
+            net,
+            x: 2,
+            y: 2,
+            z: test_z,
+        };
+        assert!(internal_add_device(vent).is_ok());
+        let lost_vent = Device::Vent {
+            net,
+            x: -1,
+            y: 2,
+            z: test_z,
+        };
+        assert!(internal_add_device(lost_vent).is_err());
+    }
+
+    // Batched writes should check their stride, add or remove gas in delta mode, and change
+    // nothing if any coordinates are bad.
+    #[test]
+    fn set_tiles() {
+        let test_z = 5;
+        internal_initialize(test_z, 10, 10).unwrap();
+        let stride = SET_TILES_FIXED_VALUES + gas_registry().count();
+        let values_for = |temperature: f32, oxygen: f32| {
+            let mut values = vec![None; stride];
+            values[4] = Some(1.0);
+            values[6] = Some(temperature);
+            values[SET_TILES_FIXED_VALUES + GAS_OXYGEN] = Some(oxygen);
+            values
+        };
+
+        assert!(internal_set_tiles(&[(1, 1, test_z)], &vec![None; stride - 1], false).is_err());
+        assert!(internal_set_tiles(&[(1, 1, test_z)], &vec![None; stride + 1], false).is_err());
+
+        internal_set_tiles(&[(1, 1, test_z)], &values_for(T20C, 10.0), false).unwrap();
+        let tile = internal_get_tile(1, 1, test_z).unwrap();
+        assert_eq!(tile.gases.get(GAS_OXYGEN), 10.0);
+        assert!((tile.temperature() - T20C).abs() < TEST_TOLERANCE);
+
+        // Adding hot gas raises the temperature part of the way.
+        internal_set_tiles(&[(1, 1, test_z)], &values_for(2.0 * T20C, 10.0), true).unwrap();
+        let tile = internal_get_tile(1, 1, test_z).unwrap();
+        assert!((tile.gases.get(GAS_OXYGEN) - 20.0).abs() < TEST_TOLERANCE);
+        assert!((tile.temperature() - 1.5 * T20C).abs() < TEST_TOLERANCE);
+
+        // Removing gas leaves the temperature alone.
+        internal_set_tiles(&[(1, 1, test_z)], &values_for(T20C, -5.0), true).unwrap();
+        let tile = internal_get_tile(1, 1, test_z).unwrap();
+        assert!((tile.gases.get(GAS_OXYGEN) - 15.0).abs() < TEST_TOLERANCE);
+        assert!((tile.temperature() - 1.5 * T20C).abs() < TEST_TOLERANCE);
+
+        // One bad coordinate rejects the whole batch.
+        let mut values = values_for(T20C, 50.0);
+        values.extend(values_for(T20C, 50.0));
+        assert!(internal_set_tiles(&[(1, 1, test_z), (10, 1, test_z)], &values, false).is_err());
+        let tile = internal_get_tile(1, 1, test_z).unwrap();
+        assert!((tile.gases.get(GAS_OXYGEN) - 15.0).abs() < TEST_TOLERANCE);
+    }
+
+    // Blocks should come out in the same order as BYOND's block(), with X changing fastest.
+    #[test]
+    fn block_order() {
+        assert_eq!(
+            block_coords((2, 3, 1), (1, 1, 2)),
+            vec![
+                (0, 0, 0),
+                (1, 0, 0),
+                (0, 1, 0),
+                (1, 1, 0),
+                (0, 2, 0),
+                (1, 2, 0),
+                (0, 0, 1),
+                (1, 0, 1),
+                (0, 1, 1),
+                (1, 1, 1),
+                (0, 2, 1),
+                (1, 2, 1),
+            ]
+        );
+    }
+
+    // A wall built with just a heat capacity should start at the temperature of the air it
+    // replaced, or room temperature in a vacuum, not absolute zero.
+    #[test]
+    fn innate_heat_capacity_temperature() {
+        let test_z = 7;
+        internal_initialize(test_z, 10, 10).unwrap();
+        let no_gases = vec![None; gas_registry().count()];
+        let mut oxygen = no_gases.clone();
+        oxygen[GAS_OXYGEN] = Some(100.0);
+        let set_tile = |x, gases: &[Option<f32>], temperature, innate_heat_capacity| {
+            internal_set_tile(
+                x,
+                0,
+                test_z,
+                None,
+                None,
+                None,
+                None,
+                Some(1.0),
+                None,
+                gases,
+                temperature,
+                None,
+                innate_heat_capacity,
+                None,
+                None,
+            )
+            .unwrap();
+        };
+
+        set_tile(0, &oxygen, Some(400.0), None);
+        set_tile(0, &no_gases, None, Some(1000.0));
+        let tile = internal_get_tile(0, 0, test_z).unwrap();
+        assert!((tile.innate_temperature() - 400.0).abs() < TEST_TOLERANCE);
+
+        set_tile(1, &no_gases, None, Some(1000.0));
+        let tile = internal_get_tile(1, 0, test_z).unwrap();
+        assert!((tile.innate_temperature() - T20C).abs() < TEST_TOLERANCE);
+
+        // Once it has a temperature of its own, it keeps it.
+        set_tile(1, &oxygen, Some(400.0), None);
+        set_tile(1, &no_gases, None, Some(2000.0));
+        let tile = internal_get_tile(1, 0, test_z).unwrap();
+        assert!((tile.innate_temperature() - T20C).abs() < TEST_TOLERANCE);
+    }
+
+    // Zone stats should sum up the whole zone, including what was just written.
+    #[test]
+    fn zone_stats() {
+        let test_z = 4;
+        internal_initialize(test_z, 3, 1).unwrap();
+        // A two tile room, with a wall at x = 2.
+        for (x, airtight) in [(0, None), (1, None), (2, Some(1.0))] {
+            internal_set_tile(
+                x,
+                0,
+                test_z,
+                airtight,
+                airtight,
+                airtight,
+                airtight,
+                Some(1.0),
+                None,
+                &[None, None, Some(10.0)],
+                Some(T20C),
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+        }
+        let stats = internal_get_zone_stats(0, 0, test_z).unwrap().unwrap();
+        assert_eq!(stats.tiles, 2);
+        assert_eq!(stats.moles[GAS_NITROGEN], 20.0);
+        assert!(internal_get_zone_stats(2, 0, test_z).unwrap().is_none());
+    }
+
+    // Region stats should sum up what was just written.
+    #[test]
+    fn region_stats() {
+        let test_z = 1;
+        internal_initialize(test_z, 10, 10).unwrap();
+        for (x, temperature, hotspot_volume) in [(0, 100.0, 0.0), (1, 300.0, 0.5)] {
+            internal_set_tile(
+                x,
+                0,
+                test_z,
+                None,
+                None,
+                None,
+                None,
+                Some(1.0),
+                None,
+                &[None, None, Some(10.0)],
+                Some(temperature),
+                None,
+                None,
+                None,
+                Some(hotspot_volume),
+            )
+            .unwrap();
+        }
+
+        let stats =
+            internal_get_region_stats(&[(0, 0, test_z), (1, 0, test_z), (2, 0, test_z)]).unwrap();
+        assert_eq!(stats.tiles, 3);
+        assert_eq!(stats.moles[GAS_NITROGEN], 20.0);
+        assert!((stats.mean_temperature() - 400.0 / 3.0).abs() < 0.01);
+        assert_eq!(stats.min_temperature, 0.0);
+        assert!((stats.max_temperature - 300.0).abs() < 0.01);
+        assert_eq!(stats.min_pressure, 0.0);
+        assert_eq!(stats.hotspots, 1);
+        assert!(internal_get_region_stats(&[(10, 0, test_z)]).is_err());
+    }
+
+    // The thread count should be checked, and take effect once the pool restarts.
+    #[test]
+    fn thread_count() {
+        let buffers = Buffers::new();
+        assert!(internal_set_thread_count(&buffers, -1.0).is_err());
+        assert!(internal_set_thread_count(&buffers, f32::NAN).is_err());
+        assert!(internal_set_thread_count(&buffers, (MAX_THREAD_COUNT + 1) as f32).is_err());
+        internal_set_thread_count(&buffers, 3.0).unwrap();
+        assert_eq!(buffers.worker_pool().unwrap().current_num_threads(), 3);
+    }
 }
@@ -0,0 +1,494 @@
+//! Audit mode, for catching the simulation creating or destroying gas and heat.
+//!
+//! When enabled, each Z level sums up its moles and thermal energy around every phase of the
+//! tick. Tile modes, reactions and emitters are expected to change those totals, the other phases should
+//! leave them alone, so anything they change is drift.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::statics::gas_registry;
+use byondapi::map::{byond_locatexyz, ByondXYZ};
+use byondapi::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+
+/// The parts of a tick that audit mode watches.
+/// Must match MILLA_AUDIT_PHASE_* in code/__DEFINES/rust.dm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuditPhase {
+    Wind,
+    Flow,
+    TileModes,
+    Superconduction,
+    Reactions,
+    Sanitization,
+    Emitters,
+}
+
+impl AuditPhase {
+    pub(crate) const ALL: [AuditPhase; 7] = [
+        AuditPhase::Wind,
+        AuditPhase::Flow,
+        AuditPhase::TileModes,
+        AuditPhase::Superconduction,
+        AuditPhase::Reactions,
+        AuditPhase::Sanitization,
+        AuditPhase::Emitters,
+    ];
+
+    /// Whether this phase is supposed to create or destroy gas and heat.
+    /// Space and environments replace the tile's air, reactions turn gases into other gases, and
+    /// emitters add and remove gas on purpose.
+    pub(crate) fn is_source_or_sink(self) -> bool {
+        matches!(
+            self,
+            AuditPhase::TileModes | AuditPhase::Reactions | AuditPhase::Emitters
+        )
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            AuditPhase::Wind => "wind",
+            AuditPhase::Flow => "flow",
+            AuditPhase::TileModes => "tile modes",
+            AuditPhase::Superconduction => "superconduction",
+            AuditPhase::Reactions => "reactions",
+            AuditPhase::Sanitization => "sanitization",
+            AuditPhase::Emitters => "emitters",
+        }
+    }
+}
+
+/// Moles of each gas and thermal energy, summed over some tiles.
+/// Summed as f64, so the totals for a whole Z level don't lose the drift we're looking for.
+/// NaN and infinity aren't included in the sums, they're counted separately instead, so one bad
+/// value doesn't hide everything else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Totals {
+    pub(crate) moles: [f64; MAX_GAS_COUNT],
+    pub(crate) thermal_energy: f64,
+    /// How many values were NaN or infinite.
+    pub(crate) non_finite: i64,
+}
+
+impl Default for Totals {
+    fn default() -> Self {
+        Totals {
+            moles: [0.0; MAX_GAS_COUNT],
+            thermal_energy: 0.0,
+            non_finite: 0,
+        }
+    }
+}
+
+impl Totals {
+    /// Sums up the given tiles.
+    pub(crate) fn of_tiles<'a>(tiles: impl IntoIterator<Item = &'a Tile>) -> Self {
+        let gas_count = gas_registry().count();
+        let mut totals = Totals::default();
+        for tile in tiles {
+            for gas in 0..gas_count {
+                totals.add_value(GasOrHeat::Gas(gas), tile.gases.values[gas] as f64);
+            }
+            totals.add_value(GasOrHeat::Heat, tile.thermal_energy as f64);
+            totals.add_value(GasOrHeat::Heat, tile.innate_thermal_energy as f64);
+        }
+        totals
+    }
+
+    fn add_value(&mut self, what: GasOrHeat, value: f64) {
+        if !value.is_finite() {
+            self.non_finite += 1;
+            return;
+        }
+        match what {
+            GasOrHeat::Gas(gas) => self.moles[gas] += value,
+            GasOrHeat::Heat => self.thermal_energy += value,
+        }
+    }
+
+    /// Estimates how much gas and heat flow moved between the region's tiles and any space or
+    /// environment tiles next to them, which flow treats as fixed.
+    /// Gas is exact, heat is only an estimate, as flow averages temperatures rather than moving
+    /// thermal energy.
+    pub(crate) fn of_flow_boundaries(next: &ZLevel, region: &ActiveRegion) -> Self {
+        let registry = gas_registry();
+        let gas_count = registry.count();
+        let mut totals = Totals::default();
+        for &my_index in &region.tiles {
+            let my_tile = next.get_tile(my_index);
+            if is_fixed(my_tile) {
+                continue;
+            }
+            let (x, y) = next.get_coords(my_index);
+            for (dir, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+                let neighbor_index = match next.maybe_get_index(x + dx, y + dy) {
+                    Some(index) if region.contains(index) => index,
+                    _ => continue,
+                };
+                let neighbor = next.get_tile(neighbor_index);
+                if !is_fixed(neighbor) {
+                    continue;
+                }
+                // Same as flow_air_once_at_index().
+                let axis = DIRECTION_AXIS[dir];
+                let flows = if dx + dy > 0 {
+                    if my_tile.wall[axis] {
+                        continue;
+                    }
+                    my_tile.gas_flow[axis]
+                } else {
+                    if neighbor.wall[axis] {
+                        continue;
+                    }
+                    neighbor.gas_flow[axis].map(|[flow_out, flow_in]| [flow_in, flow_out])
+                };
+                for (gas, [flow_in, flow_out]) in flows.iter().enumerate().take(gas_count) {
+                    let moles_in = flow_in * neighbor.gases.values[gas];
+                    let moles_out = flow_out * my_tile.gases.values[gas];
+                    totals.add_value(GasOrHeat::Gas(gas), (moles_in - moles_out) as f64);
+                    let heat = registry.specific_heat(gas)
+                        * (moles_in * neighbor.temperature() - moles_out * my_tile.temperature());
+                    totals.add_value(GasOrHeat::Heat, heat as f64);
+                }
+            }
+        }
+        totals
+    }
+
+    /// How much changed going from `before` to `after`.
+    pub(crate) fn change(before: &Totals, after: &Totals) -> Self {
+        let mut change = Totals::default();
+        for gas in 0..MAX_GAS_COUNT {
+            change.moles[gas] = after.moles[gas] - before.moles[gas];
+        }
+        change.thermal_energy = after.thermal_energy - before.thermal_energy;
+        change.non_finite = after.non_finite - before.non_finite;
+        change
+    }
+
+    fn add(&mut self, other: &Totals) {
+        for gas in 0..MAX_GAS_COUNT {
+            self.moles[gas] += other.moles[gas];
+        }
+        self.thermal_energy += other.thermal_energy;
+        self.non_finite += other.non_finite;
+    }
+
+    fn subtract(&mut self, other: &Totals) {
+        for gas in 0..MAX_GAS_COUNT {
+            self.moles[gas] -= other.moles[gas];
+        }
+        self.thermal_energy -= other.thermal_energy;
+        self.non_finite -= other.non_finite;
+    }
+
+    /// The total moles of every gas.
+    pub(crate) fn total_moles(&self) -> f64 {
+        self.moles.iter().sum()
+    }
+}
+
+enum GasOrHeat {
+    Gas(usize),
+    Heat,
+}
+
+/// Whether flow leaves a tile alone, because its air is fixed.
+fn is_fixed(tile: &Tile) -> bool {
+    matches!(tile.mode, AtmosMode::Space | AtmosMode::ExposedTo { .. })
+}
+
+/// A tile where something changed that shouldn't have.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditTile {
+    /// Where the tile is, 0-indexed.
+    pub(crate) coords: (i32, i32, i32),
+    pub(crate) phase: AuditPhase,
+    /// How many moles were created (or destroyed, if negative).
+    pub(crate) moles: f32,
+    /// How much thermal energy was created (or destroyed, if negative).
+    pub(crate) thermal_energy: f32,
+    /// How many values became NaN or infinite (or stopped being, if negative).
+    pub(crate) non_finite: i64,
+}
+
+impl AuditTile {
+    /// How bad this is, in multiples of what we'd consider a significant change.
+    /// NaN and infinity are always the worst.
+    fn score(&self) -> f32 {
+        if self.non_finite != 0 {
+            return f32::INFINITY;
+        }
+        self.moles.abs() / GAS_CHANGE_SIGNIFICANCE
+            + self.thermal_energy.abs() / THERMAL_CHANGE_SIGNIFICANCE
+    }
+}
+
+/// An audit tile ordered by its score, so a heap can keep just the worst ones.
+#[derive(Debug, Clone)]
+struct ByScore(AuditTile);
+
+impl PartialEq for ByScore {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByScore {}
+
+impl PartialOrd for ByScore {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByScore {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.score().total_cmp(&other.0.score())
+    }
+}
+
+impl From<&AuditTile> for Vec<ByondValue> {
+    /// Converts an audit tile into BYOND values.
+    /// Must match the order in code/__DEFINES/rust.dm
+    fn from(value: &AuditTile) -> Self {
+        let (x, y, z) = value.coords;
+        vec![
+            // +1 here to convert from our 0-indexing to BYOND's 1-indexing.
+            byond_locatexyz(ByondXYZ::with_coords((
+                x as i16 + 1,
+                y as i16 + 1,
+                z as i16 + 1,
+            )))
+            .unwrap(),
+            ByondValue::from(value.phase as u8 as f32),
+            ByondValue::from(value.moles),
+            ByondValue::from(value.thermal_energy),
+            ByondValue::from(value.non_finite as f32),
+        ]
+    }
+}
+
+/// What audit mode found on one Z level during one tick.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ZLevelAudit {
+    /// Which Z level this is, 0-indexed.
+    pub(crate) z: i32,
+    /// How much each phase changed the Z level's totals, indexed by AuditPhase.
+    pub(crate) changes: [Totals; AuditPhase::ALL.len()],
+    /// The totals for the simulated tiles at the start of the tick.
+    pub(crate) initial: Totals,
+    /// The tiles with the most drift, worst first. Filled in by finish().
+    /// Flow moves air between tiles, so its drift can't be pinned on any one tile, and isn't
+    /// included.
+    pub(crate) worst_tiles: Vec<AuditTile>,
+    /// The worst tiles so far, least bad on top, so it never holds more than AUDIT_WORST_TILES.
+    candidates: BinaryHeap<Reverse<ByScore>>,
+}
+
+impl ZLevelAudit {
+    pub(crate) fn new(z: i32) -> Self {
+        ZLevelAudit {
+            z,
+            ..Default::default()
+        }
+    }
+
+    /// Records what a phase did to the whole Z level.
+    pub(crate) fn record(&mut self, phase: AuditPhase, before: &Totals, after: &Totals) {
+        self.changes[phase as usize].add(&Totals::change(before, after));
+    }
+
+    /// Records what flow did to the whole Z level.
+    /// Anything that went into space or came from an environment is expected, so it's counted as
+    /// part of tile modes instead.
+    pub(crate) fn record_flow(&mut self, before: &Totals, after: &Totals, boundaries: &Totals) {
+        let mut change = Totals::change(before, after);
+        change.subtract(boundaries);
+        self.changes[AuditPhase::Flow as usize].add(&change);
+        self.changes[AuditPhase::TileModes as usize].add(boundaries);
+    }
+
+    /// Records what a phase did to a single tile (or pair of tiles).
+    pub(crate) fn record_tile(
+        &mut self,
+        phase: AuditPhase,
+        coords: (i32, i32, i32),
+        before: &Totals,
+        after: &Totals,
+    ) {
+        let change = Totals::change(before, after);
+        self.changes[phase as usize].add(&change);
+        if phase.is_source_or_sink() {
+            return;
+        }
+        let tile = AuditTile {
+            coords,
+            phase,
+            moles: change.total_moles() as f32,
+            thermal_energy: change.thermal_energy as f32,
+            non_finite: change.non_finite,
+        };
+        if tile.score() > 0.0 {
+            self.consider(tile);
+        }
+    }
+
+    /// Keeps a tile if it's one of the worst seen so far.
+    fn consider(&mut self, tile: AuditTile) {
+        self.candidates.push(Reverse(ByScore(tile)));
+        if self.candidates.len() > AUDIT_WORST_TILES {
+            self.candidates.pop();
+        }
+    }
+
+    /// Adds in what was found on another part of the same Z level.
+    pub(crate) fn merge(&mut self, other: ZLevelAudit) {
+        for (change, other_change) in self.changes.iter_mut().zip(other.changes.iter()) {
+            change.add(other_change);
+        }
+        for Reverse(ByScore(tile)) in other.candidates {
+            self.consider(tile);
+        }
+    }
+
+    /// Sorts out the worst tiles, once the tick is done.
+    pub(crate) fn finish(&mut self) {
+        // Sorting the reversed heap puts the worst tile first.
+        self.worst_tiles = std::mem::take(&mut self.candidates)
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ByScore(tile))| tile)
+            .collect();
+    }
+
+    /// The total change from every phase that isn't supposed to change anything.
+    pub(crate) fn drift(&self) -> Totals {
+        let mut drift = Totals::default();
+        for phase in AuditPhase::ALL {
+            if !phase.is_source_or_sink() {
+                drift.add(&self.changes[phase as usize]);
+            }
+        }
+        drift
+    }
+
+    /// Whether anything went wrong that someone should look at.
+    /// Flow always drifts a little, so small drift compared to the Z level's totals is ignored.
+    pub(crate) fn is_significant(&self) -> bool {
+        let drift = self.drift();
+        drift.non_finite != 0
+            || drift.total_moles().abs()
+                >= (GAS_CHANGE_SIGNIFICANCE as f64)
+                    .max(self.initial.total_moles() * GAS_CHANGE_SIGNIFICANCE_FRACTION as f64)
+            || drift.thermal_energy.abs()
+                >= (THERMAL_CHANGE_SIGNIFICANCE as f64)
+                    .max(self.initial.thermal_energy * THERMAL_CHANGE_SIGNIFICANCE_FRACTION as f64)
+    }
+}
+
+impl fmt::Display for ZLevelAudit {
+    /// Formats the audit for the log file.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let drift = self.drift();
+        writeln!(
+            f,
+            "Z {}: drift of {} moles, {} thermal energy, {} non-finite values",
+            self.z + 1,
+            drift.total_moles(),
+            drift.thermal_energy,
+            drift.non_finite
+        )?;
+        for phase in AuditPhase::ALL {
+            let change = &self.changes[phase as usize];
+            writeln!(
+                f,
+                "  {}{}: {} moles, {} thermal energy, {} non-finite values",
+                phase.name(),
+                if phase.is_source_or_sink() {
+                    " (expected)"
+                } else {
+                    ""
+                },
+                change.total_moles(),
+                change.thermal_energy,
+                change.non_finite
+            )?;
+        }
+        for tile in &self.worst_tiles {
+            let (x, y, z) = tile.coords;
+            writeln!(
+                f,
+                "  ({}, {}, {}) during {}: {} moles, {} thermal energy, {} non-finite values",
+                x + 1,
+                y + 1,
+                z + 1,
+                tile.phase.name(),
+                tile.moles,
+                tile.thermal_energy,
+                tile.non_finite
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drift should only count phases that aren't sources or sinks, and the worst tiles should be
+    // sorted, with NaN first.
+    #[test]
+    fn drift_and_worst_tiles() {
+        let mut audit = ZLevelAudit::new(0);
+        let mut before = Totals::default();
+        let mut after = Totals::default();
+        before.moles[GAS_OXYGEN] = 10.0;
+        after.moles[GAS_OXYGEN] = 5.0;
+        audit.record(AuditPhase::TileModes, &before, &after);
+        assert!(!audit.is_significant());
+
+        after.moles[GAS_OXYGEN] = 11.0;
+        audit.record_tile(AuditPhase::Sanitization, (1, 2, 0), &before, &after);
+        after.moles[GAS_OXYGEN] = 10.5;
+        audit.record_tile(AuditPhase::Superconduction, (3, 4, 0), &before, &after);
+        after.non_finite = 1;
+        audit.record_tile(AuditPhase::Sanitization, (5, 6, 0), &before, &after);
+        // Expected changes never count as bad tiles.
+        audit.record_tile(AuditPhase::Reactions, (7, 8, 0), &before, &after);
+        audit.finish();
+
+        assert!(audit.is_significant());
+        let coords: Vec<(i32, i32, i32)> = audit.worst_tiles.iter().map(|t| t.coords).collect();
+        assert_eq!(coords, vec![(5, 6, 0), (1, 2, 0), (3, 4, 0)]);
+        assert_eq!(
+            audit.changes[AuditPhase::TileModes as usize].moles[GAS_OXYGEN],
+            -5.0
+        );
+    }
+
+    // Only the worst AUDIT_WORST_TILES tiles should be kept, even across merged strips.
+    #[test]
+    fn worst_tiles_bounded() {
+        let before = Totals::default();
+        let mut after = Totals::default();
+        let mut audit = ZLevelAudit::new(0);
+        let mut other = ZLevelAudit::new(0);
+        for x in 0..(AUDIT_WORST_TILES as i32 * 2) {
+            after.moles[GAS_OXYGEN] = (x + 1) as f64;
+            let part = if x % 2 == 0 { &mut audit } else { &mut other };
+            part.record_tile(AuditPhase::Sanitization, (x, 0, 0), &before, &after);
+        }
+        audit.merge(other);
+        audit.finish();
+
+        let xs: Vec<i32> = audit.worst_tiles.iter().map(|t| t.coords.0).collect();
+        let expected: Vec<i32> = (AUDIT_WORST_TILES as i32..AUDIT_WORST_TILES as i32 * 2)
+            .rev()
+            .collect();
+        assert_eq!(xs, expected);
+    }
+}
@@ -0,0 +1,240 @@
+//! A headless harness for replaying and benchmarking MILLA outside of BYOND.
+//!
+//! Usage: `milla_bench [--ticks N] [--deterministic] (--snapshot PATH | --map PATH...)`
+//!
+//! With `--deterministic`, ticks run in deterministic mode, and the final state of each Z level
+//! is hashed, so two runs can be compared.
+//!
+//! Snapshots are the files written by milla_save_snapshot(). Maps are plain text, one file per Z
+//! level, one line per row, with +Y going up the file like in game. Each Z level is sized to fit
+//! its map. Each character is a tile:
+//! * ` `: Space.
+//! * `#`: A solid wall.
+//! * `0`: Sealed vacuum.
+//! * `.`: Sealed, breathable air.
+//! * `E`: Exposed to an environment of breathable air.
+//! * `P`: Sealed air with plasma in it, on fire.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::snapshot;
+use crate::milla::statics::gas_registry;
+use crate::milla::tick::{self, ZLevelStats};
+use eyre::eyre;
+use eyre::Result;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+
+/// How many ticks we run if not told otherwise.
+const DEFAULT_TICKS: usize = 100;
+
+/// Runs the benchmark with the given command-line arguments, not including the program name.
+pub fn run(args: &[String]) -> Result<()> {
+    let mut ticks = DEFAULT_TICKS;
+    let mut snapshot_path = None;
+    let mut map_paths = Vec::new();
+    let mut deterministic = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(eyre!("{} needs a value.", arg));
+        match arg.as_str() {
+            "--ticks" => ticks = value()?.parse()?,
+            "--snapshot" => snapshot_path = Some(value()?.clone()),
+            "--map" => map_paths.push(value()?.clone()),
+            "--deterministic" => deterministic = true,
+            _ => {
+                return Err(eyre!(
+                    "Unknown argument {}.\nUsage: milla_bench [--ticks N] [--deterministic] (--snapshot PATH | --map PATH...)",
+                    arg
+                ))
+            }
+        }
+    }
+
+    let buffers = match (snapshot_path, map_paths.is_empty()) {
+        (Some(path), true) => snapshot::load_buffers(gas_registry(), Path::new(&path))?,
+        (None, false) => {
+            let buffers = Buffers::new();
+            for (z, path) in map_paths.iter().enumerate() {
+                load_map(&buffers, &fs::read_to_string(path)?, z as i32)?;
+            }
+            buffers
+        }
+        _ => return Err(eyre!("Give either one --snapshot or at least one --map.")),
+    };
+
+    buffers.deterministic.store(deterministic, Relaxed);
+
+    let mut totals: Vec<ZLevelStats> = Vec::new();
+    let mut max_iterations: Vec<usize> = Vec::new();
+    let mut total_time = Duration::ZERO;
+    for _ in 0..ticks {
+        let start = Instant::now();
+        let stats = tick::tick(&buffers)?;
+        total_time += start.elapsed();
+        for z_stats in stats {
+            let z = z_stats.z as usize;
+            if totals.len() <= z {
+                totals.resize(z + 1, ZLevelStats::default());
+                max_iterations.resize(z + 1, 0);
+            }
+            totals[z].z = z_stats.z;
+            totals[z].duration += z_stats.duration;
+            totals[z].flow_iterations += z_stats.flow_iterations;
+            totals[z].sanitized_tiles += z_stats.sanitized_tiles;
+            totals[z].interesting_tiles += z_stats.interesting_tiles;
+            totals[z].active_tiles += z_stats.active_tiles;
+            totals[z].skipped_tiles += z_stats.skipped_tiles;
+            max_iterations[z] = max_iterations[z].max(z_stats.flow_iterations);
+        }
+    }
+
+    let ticks = ticks.max(1);
+    println!(
+        "{} ticks, {:.3} ms/tick overall",
+        ticks,
+        total_time.as_secs_f64() * 1000.0 / ticks as f64
+    );
+    println!(
+        "    z   ms/tick  avg iters  max iters  sanitized  interesting  avg active  avg skipped"
+    );
+    for (z_stats, max) in totals.iter().zip(max_iterations) {
+        println!(
+            "{:>5} {:>9.3} {:>10.1} {:>6}/{:<3} {:>10} {:>12} {:>11.1} {:>12.1}",
+            z_stats.z + 1,
+            z_stats.duration.as_secs_f64() * 1000.0 / ticks as f64,
+            z_stats.flow_iterations as f64 / ticks as f64,
+            max,
+            MAX_ITERATIONS,
+            z_stats.sanitized_tiles,
+            z_stats.interesting_tiles,
+            z_stats.active_tiles as f64 / ticks as f64,
+            z_stats.skipped_tiles as f64 / ticks as f64,
+        );
+    }
+    if deterministic {
+        let active = buffers.get_active().read().unwrap();
+        for (z, z_level) in active.0.iter().enumerate() {
+            println!(
+                "z {} state hash: {:016x}",
+                z + 1,
+                z_level.read().unwrap().state_hash()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Breathable air at room temperature, filling one tile.
+fn breathable_air() -> Tile {
+    let mut tile = Tile::new();
+    let moles = ONE_ATMOSPHERE * TILE_VOLUME / (R_IDEAL_GAS_EQUATION * T20C);
+    tile.gases.set(GAS_OXYGEN, moles * 0.21);
+    tile.gases.set(GAS_NITROGEN, moles * 0.79);
+    tile.thermal_energy = tile.heat_capacity() * T20C;
+    tile
+}
+
+/// Fills a Z level from a text map, creating the Z level and any environments it needs.
+/// The Z level is sized to fit the map exactly.
+fn load_map(buffers: &Buffers, map: &str, z: i32) -> Result<()> {
+    let rows: Vec<&str> = map.lines().collect();
+    let width = rows
+        .iter()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    buffers.init_z_level(z as usize, width, rows.len())?;
+    if buffers.environments.read().unwrap().is_empty() {
+        buffers.create_environment(breathable_air())?;
+    }
+
+    let active = buffers.get_active().read().unwrap();
+    let mut z_level = active.0[z as usize].write().unwrap();
+    for (inv_y, row) in rows.iter().enumerate() {
+        // Reverse the Y direction, so +Y is up, like in the game.
+        let y = (rows.len() - inv_y - 1) as i32;
+        for (x, c) in row.chars().enumerate() {
+            let index = z_level.maybe_get_index(x as i32, y).unwrap();
+            let tile = z_level.edit_tile(index);
+            *tile = match c {
+                ' ' => Tile::new(),
+                '#' => {
+                    let mut wall = Tile::new();
+                    wall.mode = AtmosMode::Sealed;
+                    wall.airtight_directions = AirtightDirections::all();
+                    wall.superconductivity = Superconductivity {
+                        north: 0.0,
+                        east: 0.0,
+                        south: 0.0,
+                        west: 0.0,
+                    };
+                    wall
+                }
+                '0' => {
+                    let mut vacuum = Tile::new();
+                    vacuum.mode = AtmosMode::Sealed;
+                    vacuum
+                }
+                '.' => {
+                    let mut air = breathable_air();
+                    air.mode = AtmosMode::Sealed;
+                    air
+                }
+                'E' => {
+                    let mut air = breathable_air();
+                    air.mode = AtmosMode::ExposedTo { environment_id: 0 };
+                    air
+                }
+                'P' => {
+                    let mut fire = breathable_air();
+                    fire.mode = AtmosMode::Sealed;
+                    fire.gases.set(GAS_TOXINS, 50.0);
+                    fire.thermal_energy = fire.heat_capacity() * 1000.0;
+                    fire.hotspot_temperature = 1000.0;
+                    fire.hotspot_volume = 1.0;
+                    fire
+                }
+                _ => return Err(eyre!("Unknown map character {:?} at ({}, {}).", c, x, y)),
+            };
+        }
+    }
+    Ok(())
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Maps should load with +Y going up, and tick without trouble.
+    #[test]
+    fn load_and_tick() {
+        let buffers = Buffers::new();
+        load_map(&buffers, "#####\n#.P.#\n#0E #\n#####", 0).unwrap();
+        {
+            let active = buffers.get_active().read().unwrap();
+            let z_level = active.0[0].read().unwrap();
+            assert_eq!((z_level.width(), z_level.height()), (5, 4));
+            let fire = z_level.get_tile(z_level.maybe_get_index(2, 2).unwrap());
+            assert!(fire.gases.get(GAS_TOXINS) > 0.0);
+            let exposed = z_level.get_tile(z_level.maybe_get_index(2, 1).unwrap());
+            assert_eq!(exposed.mode, AtmosMode::ExposedTo { environment_id: 0 });
+        }
+
+        let stats = tick::tick(&buffers).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert!(stats[0].flow_iterations >= 1);
+        assert!(stats[0].flow_iterations <= MAX_ITERATIONS);
+    }
+
+    // Unknown characters and bad arguments should be rejected.
+    #[test]
+    fn bad_input() {
+        assert!(load_map(&Buffers::new(), "#?#", 0).is_err());
+        assert!(run(&["--ticks".to_string()]).is_err());
+        assert!(run(&["--bogus".to_string()]).is_err());
+        assert!(run(&[]).is_err());
+    }
+}
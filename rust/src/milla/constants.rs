@@ -1,8 +1,18 @@
 /// How many Z levels we allow before being suspicious that the wrong number was sent.
-pub(crate) const MAX_Z_LEVELS: i32 = 15;
+/// Levels that were never initialized cost nothing, so this is only a sanity check. The real
+/// limit is MAX_TOTAL_TILES.
+pub(crate) const MAX_Z_LEVELS: i32 = 255;
 
-/// How big is the map? Assumed square.
-pub(crate) const MAP_SIZE: usize = 255;
+/// How big a Z level is if BYOND doesn't say.
+pub(crate) const DEFAULT_MAP_SIZE: usize = 255;
+
+/// How wide or tall we allow a Z level to be before being suspicious that the wrong number was
+/// sent.
+pub(crate) const MAX_MAP_SIZE: usize = 2000;
+
+/// How many tiles we allow across all Z levels, since each one costs memory in both buffers.
+/// Matches 15 full-size levels, which can be spent on more small levels or fewer big ones.
+pub(crate) const MAX_TOTAL_TILES: usize = DEFAULT_MAP_SIZE * DEFAULT_MAP_SIZE * 15;
 
 /// One atmosphere, in kPa.
 pub(crate) const ONE_ATMOSPHERE: f32 = 101.325;
@@ -35,32 +45,29 @@ pub(crate) const SLEEPING_GAS_VISIBILITY_MOLES: f32 = 1.0;
 /// How much stuff needs to react before we think hotspots and BYOND care.
 pub(crate) const REACTION_SIGNIFICANCE_MOLES: f32 = 0.1;
 
-/// How hot does it need to be for Agent B to work?
-pub(crate) const AGENT_B_CONVERSION_TEMP: f32 = 900.0;
-
-/// How hot does it need to be for sleeping gass to break down?
-pub(crate) const SLEEPING_GAS_BREAKDOWN_TEMP: f32 = 1400.0;
-
-/// Index for oxygen in gas list.
+/// Registry index for oxygen, which is always registered first.
 pub(crate) const GAS_OXYGEN: usize = 0;
 
-/// Index for carbon dioxide in gas list.
+/// Registry index for carbon dioxide.
 pub(crate) const GAS_CARBON_DIOXIDE: usize = 1;
 
-/// Index for nitrogen in gas list.
+/// Registry index for nitrogen.
 pub(crate) const GAS_NITROGEN: usize = 2;
 
-/// Index for toxins in gas list.
+/// Registry index for toxins.
 pub(crate) const GAS_TOXINS: usize = 3;
 
-/// Index for sleeping agent in gas list.
+/// Registry index for sleeping agent.
 pub(crate) const GAS_SLEEPING_AGENT: usize = 4;
 
-/// Index for agent b in gas list.
+/// Registry index for agent b.
 pub(crate) const GAS_AGENT_B: usize = 5;
 
-/// How many gases are there?
-pub(crate) const GAS_COUNT: usize = GAS_AGENT_B + 1;
+/// How many gases are registered before BYOND gets a chance to add its own?
+pub(crate) const BUILTIN_GAS_COUNT: usize = GAS_AGENT_B + 1;
+
+/// How many gases can be registered in total? Every tile reserves space for this many.
+pub(crate) const MAX_GAS_COUNT: usize = 16;
 
 /// The two axes, Y and X. The order is arbitrary, but may break things if changed.
 pub(crate) const AXES: [(i32, i32); 2] = [(1, 0), (0, 1)];
@@ -103,15 +110,28 @@ pub(crate) const SPECIFIC_HEAT_SLEEPING_AGENT: f32 = 40.0;
 // The specific heat of agent b, in joules per kelvin-mole.
 pub(crate) const SPECIFIC_HEAT_AGENT_B: f32 = 300.0;
 
-// Convenience array, so we can add loop through gases and calculate heat capacity.
-pub(crate) const SPECIFIC_HEATS: [f32; GAS_COUNT] = [
-    SPECIFIC_HEAT_OXYGEN,
-    SPECIFIC_HEAT_CARBON_DIOXIDE,
-    SPECIFIC_HEAT_NITROGEN,
-    SPECIFIC_HEAT_TOXINS,
-    SPECIFIC_HEAT_SLEEPING_AGENT,
-    SPECIFIC_HEAT_AGENT_B,
-];
+// The molar masses below are only reported back to BYOND, the simulation doesn't use them.
+
+/// The molar mass of oxygen, in grams per mole.
+pub(crate) const MOLAR_MASS_OXYGEN: f32 = 32.0;
+
+/// The molar mass of carbon dioxide, in grams per mole.
+pub(crate) const MOLAR_MASS_CARBON_DIOXIDE: f32 = 44.0;
+
+/// The molar mass of nitrogen, in grams per mole.
+pub(crate) const MOLAR_MASS_NITROGEN: f32 = 28.0;
+
+/// The molar mass of toxins, in grams per mole.
+pub(crate) const MOLAR_MASS_TOXINS: f32 = 64.0;
+
+/// The molar mass of sleeping agent, in grams per mole.
+pub(crate) const MOLAR_MASS_SLEEPING_AGENT: f32 = 44.0;
+
+/// The molar mass of agent b, in grams per mole.
+pub(crate) const MOLAR_MASS_AGENT_B: f32 = 96.0;
+
+// The reactions themselves are defined in reactions.toml. These two are still used for hotspots
+// and deciding which tiles are interesting, and should match plasma_fire there.
 
 /// How hot does it need to be for a plasma fire to start?
 pub(crate) const PLASMA_BURN_MIN_TEMP: f32 = 100.0 + T0C;
@@ -119,26 +139,25 @@ pub(crate) const PLASMA_BURN_MIN_TEMP: f32 = 100.0 + T0C;
 /// How hot does it need to be for a plasma fire to work as well as possible?
 pub(crate) const PLASMA_BURN_OPTIMAL_TEMP: f32 = 1370.0 + T0C;
 
-/// How much of the plasma are we willing to burn each tick?
-pub(crate) const PLASMA_BURN_MAX_RATIO: f32 = 0.01;
-
-/// How much of the plasma do we burn anyway if the ratio would make it really small?
-pub(crate) const PLASMA_BURN_MIN_MOLES: f32 = 0.001;
+/// How many moles of oxygen are needed for a fire to keep burning, or spread.
+pub(crate) const FIRE_MIN_OXYGEN_MOLES: f32 = 0.5;
 
-/// How much of a boost to burn ratio do we give to hotspots?
-pub(crate) const PLASMA_BURN_HOTSPOT_RATIO_BOOST: f32 = 10.0;
+/// How much of a tile catches fire when a fire spreads into it.
+/// (0.0, 1.0]
+pub(crate) const FIRE_SPREAD_VOLUME: f32 = 0.1;
 
-/// How much oxygen do we use per plasma?
-pub(crate) const PLASMA_BURN_OXYGEN_PER_PLASMA: f32 = 0.4;
+/// How hot solid fuel needs to get before it burns, in kelvin. Hotspots need to be this hot to
+/// light it, and air this hot lights it on its own.
+pub(crate) const SOLID_FUEL_IGNITION_TEMP: f32 = 300.0 + T0C;
 
-/// How much thermal energy is produced, in joules per mole of agent b.
-pub(crate) const AGENT_B_CONVERSION_ENERGY: f32 = 20_000.0;
+/// The most solid fuel a tile that's entirely on fire burns every tick, in moles of oxygen.
+pub(crate) const SOLID_FUEL_BURN_RATE: f32 = 1.0;
 
-/// How much thermal energy is produced, in joules per mole of sleeping agent.
-pub(crate) const NITROUS_BREAKDOWN_ENERGY: f32 = 200_000.0;
+/// How much energy burning solid fuel releases, in joules per mole of oxygen.
+pub(crate) const SOLID_FUEL_BURN_ENERGY: f32 = 200_000.0;
 
-/// How much thermal energy is produced, in joules per mole of sleeping toxins.
-pub(crate) const PLASMA_BURN_ENERGY: f32 = 3_000_000.0;
+/// The most solid fuel a tile can hold, in moles of oxygen it can burn.
+pub(crate) const MAX_SOLID_FUEL: f32 = 1000.0;
 
 /// We allow small deviations in tests as our spring chain solution is not exact.
 #[cfg(test)]
@@ -173,6 +192,26 @@ pub(crate) const THERMAL_CHANGE_SIGNIFICANCE: f32 = 0.1;
 /// the thermal energy.
 pub(crate) const THERMAL_CHANGE_SIGNIFICANCE_FRACTION: f32 = 0.001;
 
+/// How many tiles wide and tall each chunk is when tracking which parts of a Z level are active.
+/// Chunks where nothing significant is happening are skipped entirely.
+pub(crate) const ACTIVE_CHUNK_SIZE: usize = 16;
+
+/// How many columns wide each strip is when splitting a Z level between threads.
+/// Must be a multiple of ACTIVE_CHUNK_SIZE, so that no chunk is shared between strips.
+pub(crate) const STRIP_WIDTH: usize = ACTIVE_CHUNK_SIZE;
+
+/// The most threads BYOND can ask MILLA to tick on.
+pub(crate) const MAX_THREAD_COUNT: usize = 256;
+
+/// How many of the worst offending tiles audit mode keeps for each Z level.
+pub(crate) const AUDIT_WORST_TILES: usize = 10;
+
+/// How big the telemetry log gets before it's rotated, in bytes.
+pub(crate) const TELEMETRY_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How many rotated telemetry logs we keep, not counting the current one.
+pub(crate) const TELEMETRY_LOG_FILES: usize = 5;
+
 /// Controls how strongly each type of gas moves towards an even spread, ignoring wind.
 /// [0.0, f32::INFINITY]
 pub(crate) const DIFFUSION_SPEED: f32 = 0.2;
@@ -197,6 +236,85 @@ pub(crate) const MAX_WIND: f32 = f32::INFINITY;
 /// [0.0, f32::INFINITY]
 pub(crate) const TEMPERATURE_FLOW_RATE: f32 = 0.2;
 
+/// How much of each tile's air moves through an open vertical connection every tick.
+/// [0.0, 0.5], a value of 0.5 fully mixes the two tiles every tick.
+pub(crate) const VERTICAL_FLOW_RATE: f32 = 0.1;
+
+/// How much of each gas it filters a scrubber removes from its tile every tick.
+/// [0.0, 1.0]
+pub(crate) const SCRUBBER_RATE: f32 = 0.5;
+
+/// Scrubbers stop once their pipenet is at this pressure, in kPa.
+pub(crate) const SCRUBBER_MAX_PRESSURE: f32 = 50.0 * ONE_ATMOSPHERE;
+
+/// The highest target pressure a pump can be set to, in kPa.
+pub(crate) const MAX_PUMP_PRESSURE: f32 = 4500.0;
+
+/// How much of the temperature difference between a tile and its air evens out every tick.
+/// [0.0, 1.0]
+pub(crate) const INNATE_HEAT_TRANSFER_COEFFICIENT: f32 = 0.02;
+
+/// Tiles need at least this much innate heat capacity to have any thermal mass of their own.
+/// Keeps the default /turf heat capacity of 1 from turning every tile into a conductor.
+pub(crate) const MINIMUM_INNATE_HEAT_CAPACITY: f32 = 100.0;
+
+/// How many environments BYOND can define.
+pub(crate) const MAX_ENVIRONMENTS: usize = u16::MAX as usize + 1;
+
+/// How many values milla_set_tiles takes for each tile before the gases.
+pub(crate) const SET_TILES_FIXED_VALUES: usize = 10;
+
+/// The most moles of each gas a gas emitter can add or remove every tick.
+pub(crate) const MAX_EMITTER_RATE: f32 = 1000.0;
+
+/// The hottest a gas emitter's gas can be, in kelvin.
+pub(crate) const MAX_EMITTER_TEMPERATURE: f32 = 1e6;
+
+/// The most energy a heater can add or remove every tick, in joules.
+pub(crate) const MAX_HEATER_POWER: f32 = 1e7;
+
+/// How much an explosion weakens with every tile it travels.
+pub(crate) const EXPLOSION_FALLOFF: f32 = 1.0;
+
+/// How much an explosion weakens with every tile it travels down a corridor, where there's
+/// nowhere else for the blast to go.
+pub(crate) const EXPLOSION_CORRIDOR_FALLOFF: f32 = 0.5;
+
+/// How much stronger an explosion gets when it's channelled from the open into a corridor.
+/// Must be less than EXPLOSION_FALLOFF + EXPLOSION_CORRIDOR_FALLOFF, so that leaving a corridor
+/// and coming back always costs more than it gains, and the blast can't build up forever.
+pub(crate) const EXPLOSION_CORRIDOR_GAIN: f32 = 1.0;
+
+/// How much an explosion weakens when it crosses something airtight, like a wall or a closed
+/// door, on top of the usual falloff.
+pub(crate) const EXPLOSION_BARRIER_FALLOFF: f32 = 4.0;
+
+/// How long a finished explosion waits for BYOND to collect it before it's thrown away, in
+/// seconds.
+pub(crate) const EXPLOSION_COLLECTION_TIMEOUT: u64 = 60;
+
+/// The most powerful explosion we're willing to work out.
+pub(crate) const MAX_EXPLOSION_POWER: f32 = 200.0;
+
+/// How wide a tile is, in meters. Along with TILE_VOLUME, this gives a 2.5 meter ceiling.
+pub(crate) const TILE_WIDTH: f32 = 1.0;
+
+/// How hard gravity pulls things down, in meters per second squared.
+pub(crate) const STANDARD_GRAVITY: f32 = 9.81;
+
+/// How much area the wind pushes on, in square meters. This is a typical object's cross-section
+/// times its drag coefficient, tuned so that breaches throw people around and drafts don't.
+pub(crate) const WIND_DRAG_AREA: f32 = 0.02;
+
+/// How much friction holds things in place against the wind, as a fraction of their weight.
+pub(crate) const WIND_FRICTION: f32 = 0.5;
+
+/// How long the wind pushes on things each tick, in seconds.
+pub(crate) const WIND_PUSH_TIME: f32 = 0.5;
+
+/// The furthest the wind can move something in one tick, in tiles.
+pub(crate) const MAX_WIND_DISPLACEMENT: f32 = 10.0;
+
 /// Direct multiplier on strength of wind reported to BYOND.
 /// [0.0, f32::INFINITY]
 pub(crate) const BYOND_WIND_MULTIPLIER: f32 = 0.5;
@@ -28,6 +28,23 @@ pub(crate) fn bounded_byond_to_option_f32(
     }
 }
 
+/// Turns a BYOND list of numbers into a Vec<Option<f32>>, clamping each to the specified bounds.
+/// A null list becomes an empty Vec, and null or NaN entries become None.
+pub(crate) fn bounded_byond_list_to_option_f32s(
+    value: ByondValue,
+    min_value: f32,
+    max_value: f32,
+) -> Result<Vec<Option<f32>>, Error> {
+    if value.is_null() {
+        return Ok(Vec::new());
+    }
+    value
+        .get_list_values()?
+        .into_iter()
+        .map(|entry| bounded_byond_to_option_f32(entry, min_value, max_value))
+        .collect()
+}
+
 /// Wraps an f32 into an Option<f32> by converting NaN into None.
 pub(crate) fn f32_to_option_f32(value: f32) -> Option<f32> {
     if value.is_nan() {
@@ -0,0 +1,331 @@
+//! Things that keep adding or removing gas and heat on their own, so BYOND doesn't have to poke
+//! MILLA every tick to keep them going.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::statics::gas_registry;
+use eyre::eyre;
+use eyre::Result;
+
+/// Something that adds gas to a tile or removes it every tick, like a leaking canister or a
+/// plasma vent. Coordinates are 0-indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GasEmitter {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) z: i32,
+    /// Moles of each gas added every tick, by gas index. Negative rates remove gas instead.
+    pub(crate) rates: [f32; MAX_GAS_COUNT],
+    /// The temperature of the gas being added, in kelvin.
+    pub(crate) temperature: f32,
+    /// Stop adding gas once the tile reaches this pressure, in kPa.
+    /// Doesn't affect removing gas.
+    pub(crate) pressure_cap: Option<f32>,
+}
+
+impl GasEmitter {
+    /// Adds and removes gas from a tile for one tick.
+    /// Returns whether anything actually changed.
+    pub(crate) fn apply(&self, tile: &mut Tile) -> bool {
+        let gas_count = gas_registry().count();
+
+        // Removed gas takes its share of the tile's heat with it.
+        // We can't remove more than there is.
+        let temperature = tile.temperature();
+        let mut removed = GasSet::new();
+        for gas in 0..gas_count {
+            if self.rates[gas] < 0.0 {
+                removed.set(gas, (-self.rates[gas]).min(tile.gases.get(gas)));
+            }
+        }
+        let removed_moles = removed.moles();
+        if removed_moles > 0.0 {
+            for gas in 0..gas_count {
+                tile.gases.set(gas, tile.gases.get(gas) - removed.get(gas));
+            }
+            tile.thermal_energy -= (removed.heat_capacity() * temperature).min(tile.thermal_energy);
+        }
+
+        let mut added = GasSet::new();
+        for gas in 0..gas_count {
+            if self.rates[gas] > 0.0 {
+                added.set(gas, self.rates[gas]);
+            }
+        }
+        let mut added_moles = added.moles();
+        if added_moles <= 0.0 {
+            return removed_moles > 0.0;
+        }
+        if let Some(cap) = self.pressure_cap {
+            // How many moles fit under the cap? Assume the mix ends up at whichever temperature
+            // is hotter, so we never overshoot.
+            let mix_temperature = tile
+                .temperature()
+                .max(self.temperature)
+                .max(MINIMUM_TEMPERATURE_FOR_PRESSURE);
+            let room =
+                (cap - tile.pressure()) * TILE_VOLUME / (R_IDEAL_GAS_EQUATION * mix_temperature);
+            let fraction = (room / added_moles).clamp(0.0, 1.0);
+            if fraction < 1.0 {
+                for gas in 0..gas_count {
+                    added.set(gas, added.get(gas) * fraction);
+                }
+                added_moles *= fraction;
+            }
+        }
+        if added_moles <= 0.0 {
+            return removed_moles > 0.0;
+        }
+        tile.gases.add_gases(&added);
+        tile.thermal_energy += added.heat_capacity() * self.temperature;
+        true
+    }
+}
+
+/// Every gas emitter BYOND has registered.
+/// IDs are positions in the list, and are reused once removed.
+#[derive(Default)]
+pub(crate) struct GasEmitters {
+    emitters: Vec<Option<GasEmitter>>,
+}
+
+impl GasEmitters {
+    /// Adds an emitter, and returns its ID.
+    /// Rates and temperatures are clamped to sane limits, so one bad emitter can't flood a tile
+    /// with infinite gas.
+    pub(crate) fn add(&mut self, mut emitter: GasEmitter) -> Result<usize> {
+        if emitter.rates.iter().any(|rate| !rate.is_finite()) {
+            return Err(eyre!("Invalid gas emitter rates {:?}", emitter.rates));
+        }
+        if !emitter.temperature.is_finite() {
+            return Err(eyre!(
+                "Invalid gas emitter temperature {}",
+                emitter.temperature
+            ));
+        }
+        if let Some(cap) = emitter.pressure_cap {
+            if cap.is_nan() || cap < 0.0 {
+                return Err(eyre!("Invalid gas emitter pressure cap {}", cap));
+            }
+        }
+        for rate in emitter.rates.iter_mut() {
+            *rate = rate.clamp(-MAX_EMITTER_RATE, MAX_EMITTER_RATE);
+        }
+        emitter.temperature = emitter.temperature.clamp(TCMB, MAX_EMITTER_TEMPERATURE);
+        Ok(insert_into_free_slot(&mut self.emitters, emitter))
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) -> Result<()> {
+        match self.emitters.get_mut(id) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(eyre!("No gas emitter with ID {}", id)),
+        }
+    }
+
+    /// The emitters on a Z level, in ID order.
+    pub(crate) fn on_z_level(&self, z: i32) -> Vec<GasEmitter> {
+        self.emitters
+            .iter()
+            .flatten()
+            .filter(|emitter| emitter.z == z)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Something that heats or cools a tile every tick, like a space heater or a freezer.
+/// Coordinates are 0-indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Heater {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) z: i32,
+    /// How much thermal energy is added every tick, in joules. Negative power cools instead.
+    pub(crate) power: f32,
+    /// Stop heating above this temperature, or cooling below it, in kelvin.
+    pub(crate) target_temperature: Option<f32>,
+}
+
+impl Heater {
+    /// Heats or cools a tile for one tick.
+    /// Heaters warm the air if there is any, and the tile itself if not.
+    /// Returns whether anything actually changed.
+    pub(crate) fn apply(&self, tile: &mut Tile) -> bool {
+        let (thermal_energy, heat_capacity) = if tile.heat_capacity() > 0.0 {
+            let heat_capacity = tile.heat_capacity();
+            (&mut tile.thermal_energy, heat_capacity)
+        } else if tile.has_thermal_mass() {
+            (&mut tile.innate_thermal_energy, tile.innate_heat_capacity)
+        } else {
+            return false;
+        };
+        let target = match self.target_temperature {
+            Some(target) => target,
+            None if self.power > 0.0 => f32::INFINITY,
+            None => TCMB,
+        };
+        // Don't go past the target, and don't cool below the background temperature of space.
+        let target_energy = target.max(TCMB) * heat_capacity;
+        let energy = if self.power > 0.0 {
+            self.power.min(target_energy - *thermal_energy)
+        } else {
+            self.power.max(target_energy - *thermal_energy)
+        };
+        if energy == 0.0 || energy.signum() != self.power.signum() {
+            return false;
+        }
+        *thermal_energy += energy;
+        true
+    }
+}
+
+/// Every heater BYOND has registered.
+/// IDs are positions in the list, and are reused once removed.
+#[derive(Default)]
+pub(crate) struct Heaters {
+    heaters: Vec<Option<Heater>>,
+}
+
+impl Heaters {
+    /// Adds a heater, and returns its ID.
+    /// Power is clamped to a sane limit.
+    pub(crate) fn add(&mut self, mut heater: Heater) -> Result<usize> {
+        if !heater.power.is_finite() {
+            return Err(eyre!("Invalid heater power {}", heater.power));
+        }
+        if let Some(target) = heater.target_temperature {
+            if target.is_nan() || target < 0.0 {
+                return Err(eyre!("Invalid heater target temperature {}", target));
+            }
+        }
+        heater.power = heater.power.clamp(-MAX_HEATER_POWER, MAX_HEATER_POWER);
+        Ok(insert_into_free_slot(&mut self.heaters, heater))
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) -> Result<()> {
+        match self.heaters.get_mut(id) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(eyre!("No heater with ID {}", id)),
+        }
+    }
+
+    /// The heaters on a Z level, in ID order.
+    pub(crate) fn on_z_level(&self, z: i32) -> Vec<Heater> {
+        self.heaters
+            .iter()
+            .flatten()
+            .filter(|heater| heater.z == z)
+            .cloned()
+            .collect()
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitter(rates: &[(usize, f32)], pressure_cap: Option<f32>) -> GasEmitter {
+        let mut emitter = GasEmitter {
+            x: 0,
+            y: 0,
+            z: 0,
+            rates: [0.0; MAX_GAS_COUNT],
+            temperature: T20C,
+            pressure_cap,
+        };
+        for &(gas, rate) in rates {
+            emitter.rates[gas] = rate;
+        }
+        emitter
+    }
+
+    // Sources should add gas at their temperature, sinks should stop when the gas runs out, and
+    // pressure caps should be respected.
+    #[test]
+    fn sources_and_sinks() {
+        let mut tile = Tile::new();
+        tile.mode = AtmosMode::Sealed;
+        assert!(emitter(&[(GAS_TOXINS, 10.0)], None).apply(&mut tile));
+        assert_eq!(tile.gases.get(GAS_TOXINS), 10.0);
+        assert!((tile.temperature() - T20C).abs() < 0.01);
+
+        assert!(emitter(&[(GAS_TOXINS, -4.0), (GAS_OXYGEN, -1.0)], None).apply(&mut tile));
+        assert_eq!(tile.gases.get(GAS_TOXINS), 6.0);
+        assert_eq!(tile.gases.get(GAS_OXYGEN), 0.0);
+        assert!((tile.temperature() - T20C).abs() < 0.01);
+        assert!(emitter(&[(GAS_TOXINS, -100.0)], None).apply(&mut tile));
+        assert_eq!(tile.gases.get(GAS_TOXINS), 0.0);
+        assert!(!emitter(&[(GAS_TOXINS, -100.0)], None).apply(&mut tile));
+
+        let capped = emitter(&[(GAS_NITROGEN, MAX_EMITTER_RATE)], Some(ONE_ATMOSPHERE));
+        for _ in 0..10 {
+            capped.apply(&mut tile);
+        }
+        assert!(tile.pressure() <= ONE_ATMOSPHERE * 1.001);
+        assert!(tile.pressure() >= ONE_ATMOSPHERE * 0.99);
+        assert!(!capped.apply(&mut tile));
+    }
+
+    // Bad emitters should be rejected or clamped, and IDs should be reused.
+    #[test]
+    fn add_and_remove() {
+        let mut emitters = GasEmitters::default();
+        assert!(emitters
+            .add(emitter(&[(GAS_OXYGEN, f32::NAN)], None))
+            .is_err());
+        assert!(emitters.add(emitter(&[], Some(-1.0))).is_err());
+        let id = emitters.add(emitter(&[(GAS_OXYGEN, 1e9)], None)).unwrap();
+        let mut other = emitter(&[], None);
+        other.z = 1;
+        emitters.add(other).unwrap();
+        let on_z = emitters.on_z_level(0);
+        assert_eq!(on_z.len(), 1);
+        assert_eq!(on_z[0].rates[GAS_OXYGEN], MAX_EMITTER_RATE);
+
+        emitters.remove(id).unwrap();
+        assert!(emitters.remove(id).is_err());
+        assert!(emitters.on_z_level(0).is_empty());
+        assert_eq!(emitters.add(emitter(&[], None)).unwrap(), id);
+    }
+
+    // Heaters should stop at their target, and fall back to heating the tile itself when there's
+    // no air.
+    #[test]
+    fn heaters() {
+        let heater = |power, target_temperature| Heater {
+            x: 0,
+            y: 0,
+            z: 0,
+            power,
+            target_temperature,
+        };
+        let mut tile = Tile::new();
+        assert!(!heater(1000.0, None).apply(&mut tile));
+
+        tile.innate_heat_capacity = 100.0;
+        tile.innate_thermal_energy = 100.0 * T20C;
+        assert!(heater(1000.0, None).apply(&mut tile));
+        assert!((tile.innate_temperature() - (T20C + 10.0)).abs() < 0.01);
+
+        tile.gases.set(GAS_NITROGEN, 10.0);
+        tile.thermal_energy = tile.heat_capacity() * T20C;
+        let freezer = heater(-1e6, Some(T0C));
+        assert!(freezer.apply(&mut tile));
+        assert!((tile.temperature() - T0C).abs() < 0.01);
+        assert!(!freezer.apply(&mut tile));
+        assert!((tile.innate_temperature() - (T20C + 10.0)).abs() < 0.01);
+
+        let mut heaters = Heaters::default();
+        assert!(heaters.add(heater(f32::NAN, None)).is_err());
+        let id = heaters.add(heater(1e20, None)).unwrap();
+        assert_eq!(heaters.on_z_level(0)[0].power, MAX_HEATER_POWER);
+        heaters.remove(id).unwrap();
+        assert!(heaters.remove(id).is_err());
+    }
+}
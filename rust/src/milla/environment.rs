@@ -0,0 +1,245 @@
+//! Environments that ExposedTo tiles are held at, like a planet's atmosphere.
+//!
+//! BYOND can change an environment at any time, either straight away or gradually over a number
+//! of ticks, for weather and day/night cycles. Changes only take effect at the start of a tick,
+//! so every Z level sees the same environments for the whole tick.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::statics::gas_registry;
+use eyre::eyre;
+use eyre::Result;
+
+/// An environment, and where it's heading.
+#[derive(Debug, Clone)]
+pub(crate) struct Environment {
+    /// What tiles exposed to this environment are held at this tick.
+    pub(crate) tile: Tile,
+    transition: Option<Transition>,
+}
+
+/// A gradual change from one environment to another.
+#[derive(Debug, Clone)]
+struct Transition {
+    from: Tile,
+    to: Tile,
+    /// How many ticks of the transition have happened.
+    elapsed: u32,
+    /// How many ticks the whole transition takes. 0 means it happens in one go.
+    duration: u32,
+}
+
+impl Environment {
+    /// What this environment will end up as, once any transition finishes.
+    pub(crate) fn target(&self) -> &Tile {
+        match &self.transition {
+            Some(transition) => &transition.to,
+            None => &self.tile,
+        }
+    }
+
+    /// How many more ticks until the environment stops changing.
+    pub(crate) fn ticks_left(&self) -> u32 {
+        match &self.transition {
+            Some(transition) => transition.duration.max(1) - transition.elapsed,
+            None => 0,
+        }
+    }
+
+    /// Moves one tick along the transition, if there is one.
+    /// Returns whether the environment changed.
+    fn advance(&mut self) -> bool {
+        let Some(transition) = &mut self.transition else {
+            return false;
+        };
+        transition.elapsed += 1;
+        let progress = if transition.elapsed >= transition.duration {
+            1.0
+        } else {
+            transition.elapsed as f32 / transition.duration as f32
+        };
+        self.tile = interpolate(&transition.from, &transition.to, progress);
+        if progress >= 1.0 {
+            self.transition = None;
+        }
+        true
+    }
+}
+
+/// The environment `progress` of the way from `from` to `to`.
+/// Gases and temperature change linearly.
+fn interpolate(from: &Tile, to: &Tile, progress: f32) -> Tile {
+    let mut tile = to.clone();
+    for gas in 0..gas_registry().count() {
+        let start = from.gases.get(gas);
+        tile.gases
+            .set(gas, start + (to.gases.get(gas) - start) * progress);
+    }
+    let temperature = from.temperature() + (to.temperature() - from.temperature()) * progress;
+    tile.thermal_energy = temperature * tile.heat_capacity();
+    tile.gases.recalculate();
+    tile
+}
+
+/// Every environment BYOND has defined. IDs are positions in the list.
+#[derive(Debug, Default)]
+pub(crate) struct Environments {
+    environments: Vec<Environment>,
+}
+
+impl Environments {
+    /// Defines a new environment, and returns its ID.
+    pub(crate) fn create(&mut self, mut tile: Tile) -> Result<u16> {
+        if self.environments.len() >= MAX_ENVIRONMENTS {
+            return Err(eyre!(
+                "Too many environments, the limit is {}",
+                MAX_ENVIRONMENTS
+            ));
+        }
+        let id = self.environments.len() as u16;
+        tile.mode = AtmosMode::ExposedTo { environment_id: id };
+        tile.gases.recalculate();
+        self.environments.push(Environment {
+            tile,
+            transition: None,
+        });
+        Ok(id)
+    }
+
+    /// Changes an environment to match `tile`, gradually over `duration` ticks, starting next
+    /// tick. Replaces any transition that was already happening.
+    pub(crate) fn update(&mut self, id: u16, mut tile: Tile, duration: u32) -> Result<()> {
+        let environment = self.get_mut(id)?;
+        tile.mode = AtmosMode::ExposedTo { environment_id: id };
+        tile.gases.recalculate();
+        environment.transition = Some(Transition {
+            from: environment.tile.clone(),
+            to: tile,
+            elapsed: 0,
+            duration,
+        });
+        Ok(())
+    }
+
+    /// Moves every transition along by a tick.
+    /// Returns the IDs of the environments that changed.
+    pub(crate) fn advance(&mut self) -> Vec<u16> {
+        let mut changed = Vec::new();
+        for (id, environment) in self.environments.iter_mut().enumerate() {
+            if environment.advance() {
+                changed.push(id as u16);
+            }
+        }
+        changed
+    }
+
+    pub(crate) fn get(&self, id: u16) -> Result<&Environment> {
+        self.environments
+            .get(id as usize)
+            .ok_or(eyre!("No environment with ID {}", id))
+    }
+
+    fn get_mut(&mut self, id: u16) -> Result<&mut Environment> {
+        self.environments
+            .get_mut(id as usize)
+            .ok_or(eyre!("No environment with ID {}", id))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Environment> {
+        self.environments.iter()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.environments.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.environments.is_empty()
+    }
+
+    /// What each environment is this tick, by ID.
+    pub(crate) fn tiles(&self) -> Box<[Tile]> {
+        self.environments
+            .iter()
+            .map(|environment| environment.tile.clone())
+            .collect()
+    }
+
+    /// Replaces every environment, dropping any transitions.
+    pub(crate) fn replace(&mut self, tiles: Vec<Tile>) {
+        self.environments = tiles
+            .into_iter()
+            .map(|tile| Environment {
+                tile,
+                transition: None,
+            })
+            .collect();
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_TOLERANCE: f32 = 0.001;
+
+    /// An environment tile with some oxygen at `temperature`.
+    fn oxygen_at(moles: f32, temperature: f32) -> Tile {
+        let mut tile = Tile::new();
+        tile.gases.set(GAS_OXYGEN, moles);
+        tile.thermal_energy = temperature * tile.heat_capacity();
+        tile
+    }
+
+    // Updates should only happen when the tick advances, gradually if asked.
+    #[test]
+    fn transitions() {
+        let mut environments = Environments::default();
+        let id = environments.create(oxygen_at(100.0, T20C)).unwrap();
+        assert!(environments.advance().is_empty());
+
+        environments.update(id, oxygen_at(50.0, 100.0), 4).unwrap();
+        let environment = environments.get(id).unwrap();
+        assert_eq!(environment.tile.gases.get(GAS_OXYGEN), 100.0);
+        assert_eq!(environment.ticks_left(), 4);
+        assert_eq!(environment.target().gases.get(GAS_OXYGEN), 50.0);
+
+        assert_eq!(environments.advance(), vec![id]);
+        let tile = &environments.get(id).unwrap().tile;
+        assert!((tile.gases.get(GAS_OXYGEN) - 87.5).abs() < TEST_TOLERANCE);
+        assert!((tile.temperature() - (T20C - (T20C - 100.0) / 4.0)).abs() < TEST_TOLERANCE);
+
+        for _ in 0..3 {
+            assert_eq!(environments.advance(), vec![id]);
+        }
+        let environment = environments.get(id).unwrap();
+        assert_eq!(environment.ticks_left(), 0);
+        assert!((environment.tile.temperature() - 100.0).abs() < TEST_TOLERANCE);
+        assert!(environments.advance().is_empty());
+
+        // A duration of 0 happens all at once, next tick.
+        environments.update(id, oxygen_at(10.0, T20C), 0).unwrap();
+        assert_eq!(environments.advance(), vec![id]);
+        assert_eq!(
+            environments.get(id).unwrap().tile.gases.get(GAS_OXYGEN),
+            10.0
+        );
+        assert!(environments.update(id + 1, Tile::new(), 0).is_err());
+    }
+
+    // IDs should go beyond what a u8 could hold.
+    #[test]
+    fn many_ids() {
+        let mut environments = Environments::default();
+        for _ in 0..300 {
+            environments.create(Tile::new()).unwrap();
+        }
+        assert_eq!(environments.create(Tile::new()).unwrap(), 300);
+        assert_eq!(
+            environments.get(300).unwrap().tile.mode,
+            AtmosMode::ExposedTo {
+                environment_id: 300
+            }
+        );
+    }
+}
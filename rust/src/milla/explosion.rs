@@ -0,0 +1,290 @@
+//! Explosions, worked out with the airtightness MILLA already knows about.
+//!
+//! Blasts spread out from their epicenter, losing strength with every tile they cross. Airtight
+//! boundaries soak up a lot of that strength. Corridors channel the blast: it gets stronger as it
+//! enters one from the open, and loses less strength per tile along it, so it reaches further
+//! than it would in the open. No tile is ever hit harder than the epicenter.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::zones::NEIGHBORS;
+use byondapi::map::{byond_locatexyz, ByondXYZ};
+use byondapi::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// A tile an explosion reached.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExplosionTile {
+    /// 0-indexed.
+    pub(crate) coords: (i32, i32, i32),
+    /// How hard the explosion hit this tile. The epicenter gets the explosion's full power.
+    pub(crate) severity: f32,
+}
+
+impl From<&ExplosionTile> for Vec<ByondValue> {
+    /// Converts an explosion tile into BYOND values.
+    /// Must match the order in code/__DEFINES/rust.dm
+    fn from(value: &ExplosionTile) -> Self {
+        let (x, y, z) = value.coords;
+        vec![
+            // +1 here to convert from our 0-indexing to BYOND's 1-indexing.
+            byond_locatexyz(ByondXYZ::with_coords((
+                x as i16 + 1,
+                y as i16 + 1,
+                z as i16 + 1,
+            )))
+            .unwrap(),
+            ByondValue::from(value.severity),
+        ]
+    }
+}
+
+/// What an explosion needs to know about a Z level.
+/// Copied out of the model, so the explosion can be worked out without holding any locks.
+pub(crate) struct BlastMap {
+    z: i32,
+    width: usize,
+    height: usize,
+    airtight: Vec<AirtightDirections>,
+}
+
+impl BlastMap {
+    pub(crate) fn of_z_level(z_level: &ZLevel, z: i32) -> Self {
+        BlastMap {
+            z,
+            width: z_level.width(),
+            height: z_level.height(),
+            airtight: (0..z_level.tile_count())
+                .map(|index| z_level.get_tile(index).airtight_directions)
+                .collect(),
+        }
+    }
+
+    /// Same layout as ZLevel::maybe_get_index().
+    fn maybe_get_index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(x as usize * self.height + y as usize)
+    }
+
+    fn get_coords(&self, index: usize) -> (i32, i32) {
+        ((index / self.height) as i32, (index % self.height) as i32)
+    }
+
+    /// Whether air can move between two neighboring tiles.
+    fn is_open(
+        &self,
+        my_index: usize,
+        their_index: usize,
+        my_direction: AirtightDirections,
+        their_direction: AirtightDirections,
+    ) -> bool {
+        !self.airtight[my_index].contains(my_direction)
+            && !self.airtight[their_index].contains(their_direction)
+    }
+
+    /// The neighbors air can move to from a tile.
+    fn open_neighbors(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.get_coords(index);
+        NEIGHBORS
+            .iter()
+            .filter_map(move |(dx, dy, my_direction, their_direction)| {
+                let neighbor = self.maybe_get_index(x + dx, y + dy)?;
+                self.is_open(index, neighbor, *my_direction, *their_direction)
+                    .then_some(neighbor)
+            })
+    }
+
+    /// Whether a tile is part of a corridor: it has only one or two ways out, and so do the
+    /// tiles they lead to. The second part keeps the corners and edges of rooms out.
+    fn is_corridor(&self, index: usize) -> bool {
+        let narrow = |index| (1..=2).contains(&self.open_neighbors(index).count());
+        narrow(index) && self.open_neighbors(index).all(narrow)
+    }
+
+    /// Works out how hard an explosion hits every tile it reaches, strongest first.
+    pub(crate) fn explode(&self, x: i32, y: i32, power: f32) -> Vec<ExplosionTile> {
+        let Some(epicenter) = self.maybe_get_index(x, y) else {
+            return Vec::new();
+        };
+        if power.is_nan() || power <= 0.0 {
+            return Vec::new();
+        }
+        let power = power.min(MAX_EXPLOSION_POWER);
+
+        // Like Dijkstra's algorithm, but looking for the strongest blast instead of the shortest
+        // path. Corridors can make a blast stronger, but EXPLOSION_CORRIDOR_GAIN is small enough
+        // that going around any loop still weakens it, so this still finishes.
+        let corridors: Vec<bool> = (0..self.airtight.len())
+            .map(|index| self.is_corridor(index))
+            .collect();
+        let mut severity = vec![0.0; self.airtight.len()];
+        let mut queue = BinaryHeap::new();
+        severity[epicenter] = power;
+        queue.push(Blast(power, epicenter));
+        while let Some(Blast(my_severity, my_index)) = queue.pop() {
+            if my_severity < severity[my_index] {
+                // We already got here with a stronger blast.
+                continue;
+            }
+            let (my_x, my_y) = self.get_coords(my_index);
+            let in_corridor = corridors[my_index];
+            for (dx, dy, my_direction, their_direction) in NEIGHBORS {
+                let Some(their_index) = self.maybe_get_index(my_x + dx, my_y + dy) else {
+                    continue;
+                };
+                let open = self.is_open(my_index, their_index, my_direction, their_direction);
+                let mut falloff = if corridors[their_index] {
+                    if open && !in_corridor {
+                        // Funnelled in from the open.
+                        EXPLOSION_CORRIDOR_FALLOFF - EXPLOSION_CORRIDOR_GAIN
+                    } else {
+                        EXPLOSION_CORRIDOR_FALLOFF
+                    }
+                } else {
+                    EXPLOSION_FALLOFF
+                };
+                if !open {
+                    falloff += EXPLOSION_BARRIER_FALLOFF;
+                }
+                let their_severity = (my_severity - falloff).min(power);
+                if their_severity > severity[their_index] {
+                    severity[their_index] = their_severity;
+                    queue.push(Blast(their_severity, their_index));
+                }
+            }
+        }
+
+        let mut tiles: Vec<ExplosionTile> = severity
+            .iter()
+            .enumerate()
+            .filter(|(_, severity)| **severity > 0.0)
+            .map(|(index, severity)| {
+                let (x, y) = self.get_coords(index);
+                ExplosionTile {
+                    coords: (x, y, self.z),
+                    severity: *severity,
+                }
+            })
+            .collect();
+        tiles.sort_by(|a, b| b.severity.total_cmp(&a.severity));
+        tiles
+    }
+}
+
+/// A blast waiting to spread from a tile, ordered by severity.
+struct Blast(f32, usize);
+
+impl PartialEq for Blast {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Blast {}
+
+impl PartialOrd for Blast {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Blast {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .total_cmp(&other.0)
+            .then_with(|| self.1.cmp(&other.1))
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a blast map from a pattern, with +Y going up. `#` is a wall, anything else is open.
+    fn blast_map(pattern: &[&str]) -> BlastMap {
+        let mut z_level = ZLevel::new(pattern[0].len(), pattern.len());
+        for (inv_y, row) in pattern.iter().enumerate() {
+            let y = (pattern.len() - inv_y - 1) as i32;
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    let index = z_level.maybe_get_index(x as i32, y).unwrap();
+                    z_level.edit_tile(index).airtight_directions = AirtightDirections::all();
+                }
+            }
+        }
+        BlastMap::of_z_level(&z_level, 0)
+    }
+
+    fn severity_at(tiles: &[ExplosionTile], x: i32, y: i32) -> f32 {
+        tiles
+            .iter()
+            .find(|tile| tile.coords == (x, y, 0))
+            .map_or(0.0, |tile| tile.severity)
+    }
+
+    // In the open, explosions should fall off by distance.
+    #[test]
+    fn open_falloff() {
+        let map = blast_map(&[".........", ".........", "........."]);
+        let tiles = map.explode(4, 1, 3.0);
+        assert_eq!(tiles[0].coords, (4, 1, 0));
+        assert_eq!(tiles[0].severity, 3.0);
+        assert_eq!(severity_at(&tiles, 6, 1), 1.0);
+        assert_eq!(severity_at(&tiles, 7, 1), 0.0);
+        assert!(map.explode(100, 100, 3.0).is_empty());
+        assert!(map.explode(0, 0, f32::NAN).is_empty());
+    }
+
+    // Walls should soak up a blast, and corridors should carry it further.
+    #[test]
+    fn walls_and_corridors() {
+        let map = blast_map(&[
+            "###########", //
+            "..........#",
+            "###########",
+            "...#.......",
+        ]);
+        let tiles = map.explode(0, 2, 12.0);
+        // Down the corridor, it only loses half as much per tile.
+        assert_eq!(severity_at(&tiles, 4, 2), 10.0);
+        // Through the wall below, it loses a lot more.
+        let through_wall = severity_at(&tiles, 0, 0);
+        assert!(through_wall < 12.0 - 2.0 * EXPLOSION_FALLOFF);
+        assert!(through_wall > 0.0);
+        // And it doesn't get out the far side of the room below.
+        assert_eq!(severity_at(&tiles, 10, 0), 0.0);
+    }
+
+    // A blast funnelled from a room into a corridor should get stronger, and hit harder than it
+    // would at the same distance in the open, without ever beating the epicenter.
+    #[test]
+    fn corridor_channelling() {
+        let map = blast_map(&[
+            "...######", //
+            ".........",
+            "...######",
+        ]);
+        let tiles = map.explode(0, 1, 10.0);
+        // The room's corners aren't corridors, so they don't get a boost.
+        assert_eq!(severity_at(&tiles, 2, 2), 7.0);
+        let mouth = severity_at(&tiles, 3, 1);
+        let corridor = severity_at(&tiles, 4, 1);
+        assert_eq!(mouth, 7.0);
+        assert_eq!(
+            corridor,
+            7.0 - EXPLOSION_CORRIDOR_FALLOFF + EXPLOSION_CORRIDOR_GAIN
+        );
+        assert!(corridor > mouth);
+
+        let open = blast_map(&[".........", ".........", "........."]).explode(0, 1, 10.0);
+        assert!(corridor > severity_at(&open, 4, 1));
+        assert!(tiles.iter().all(|tile| tile.severity <= 10.0));
+
+        // Right next to the epicenter, the gain can't push it past the explosion's power.
+        let tiles = map.explode(3, 1, 10.0);
+        assert_eq!(severity_at(&tiles, 4, 1), 10.0);
+    }
+}
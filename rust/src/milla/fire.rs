@@ -0,0 +1,245 @@
+//! Fires spreading, burning solid fuel, and going out, all without waiting for BYOND.
+//!
+//! Fires live in hotspots. A hotspot spreads to neighboring tiles that have both fuel and
+//! oxygen, burns any solid fuel in its tile, and goes out once either runs out.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::reactions::Reaction;
+use crate::milla::simulate;
+use crate::milla::zones::NEIGHBORS;
+
+/// Whether a tile is on fire.
+pub(crate) fn is_burning(tile: &Tile) -> bool {
+    tile.hotspot_volume > 0.0 || tile.fuel_burnt > REACTION_SIGNIFICANCE_MOLES
+}
+
+/// Whether a tile has anything to burn: solid fuel, or enough of a gas that one of `reactions`
+/// burns with oxygen.
+pub(crate) fn has_fuel(tile: &Tile, reactions: &[Reaction]) -> bool {
+    if tile.solid_fuel > 0.0 {
+        return true;
+    }
+    reactions
+        .iter()
+        .filter(|reaction| reaction.is_fire())
+        .flat_map(|reaction| reaction.reactants.iter())
+        .any(|(gas, per_unit)| {
+            *gas != GAS_OXYGEN
+                && *per_unit > 0.0
+                && tile.gases.get(*gas) > REACTION_SIGNIFICANCE_MOLES
+        })
+}
+
+/// Whether a tile has enough oxygen for a fire.
+pub(crate) fn has_oxidizer(tile: &Tile) -> bool {
+    tile.gases.get(GAS_OXYGEN) >= FIRE_MIN_OXYGEN_MOLES
+}
+
+/// Lets a fire next door spread into the tile at (x, y).
+/// Neighbors are read from `prev`, so it doesn't matter which order tiles are processed in.
+/// Returns whether the tile caught fire.
+pub(crate) fn catch_fire(
+    prev: &ZLevel,
+    x: i32,
+    y: i32,
+    my_next_tile: &mut Tile,
+    reactions: &[Reaction],
+) -> bool {
+    if my_next_tile.hotspot_volume > 0.0
+        || !has_fuel(my_next_tile, reactions)
+        || !has_oxidizer(my_next_tile)
+    {
+        return false;
+    }
+    let Some(my_index) = prev.maybe_get_index(x, y) else {
+        return false;
+    };
+    let my_tile = prev.get_tile(my_index);
+
+    // Find the hottest fire we're open to.
+    let mut flame_temperature: f32 = 0.0;
+    for (dx, dy, my_direction, their_direction) in NEIGHBORS {
+        let Some(their_index) = prev.maybe_get_index(x + dx, y + dy) else {
+            continue;
+        };
+        let their_tile = prev.get_tile(their_index);
+        if my_tile.airtight_directions.contains(my_direction)
+            || their_tile.airtight_directions.contains(their_direction)
+            || !is_burning(their_tile)
+        {
+            continue;
+        }
+        if their_tile.hotspot_volume > 0.0 {
+            flame_temperature = flame_temperature.max(their_tile.hotspot_temperature);
+        } else {
+            flame_temperature = flame_temperature.max(their_tile.temperature());
+        }
+    }
+    if flame_temperature < PLASMA_BURN_MIN_TEMP || flame_temperature <= my_next_tile.temperature() {
+        return false;
+    }
+
+    my_next_tile.hotspot_temperature = flame_temperature;
+    my_next_tile.hotspot_volume = FIRE_SPREAD_VOLUME;
+    simulate::normalise_hotspot(my_next_tile);
+    my_next_tile.hotspot_volume > 0.0
+}
+
+/// Burns some of the tile's solid fuel, if a hotspot or the air is past its ignition temperature.
+/// Solid fuel burns with oxygen into carbon dioxide, and heats the hotspot if there is one.
+pub(crate) fn burn_solid_fuel(my_next_tile: &mut Tile) {
+    if my_next_tile.solid_fuel <= 0.0 {
+        return;
+    }
+    let intensity = if my_next_tile.hotspot_volume > 0.0
+        && my_next_tile.hotspot_temperature >= SOLID_FUEL_IGNITION_TEMP
+    {
+        my_next_tile.hotspot_volume
+    } else if my_next_tile.temperature() >= SOLID_FUEL_IGNITION_TEMP {
+        1.0
+    } else {
+        return;
+    };
+    let burnt = (SOLID_FUEL_BURN_RATE * intensity)
+        .min(my_next_tile.solid_fuel)
+        .min(my_next_tile.gases.get(GAS_OXYGEN));
+    if burnt <= 0.0 {
+        return;
+    }
+
+    my_next_tile.solid_fuel -= burnt;
+    my_next_tile
+        .gases
+        .set(GAS_OXYGEN, my_next_tile.gases.get(GAS_OXYGEN) - burnt);
+    my_next_tile.gases.set(
+        GAS_CARBON_DIOXIDE,
+        my_next_tile.gases.get(GAS_CARBON_DIOXIDE) + burnt,
+    );
+    my_next_tile.gases.set_dirty();
+    my_next_tile.fuel_burnt += burnt;
+
+    let energy = burnt * SOLID_FUEL_BURN_ENERGY;
+    if my_next_tile.hotspot_volume > 0.0 {
+        simulate::adjust_hotspot(my_next_tile, energy);
+    } else {
+        my_next_tile.thermal_energy += energy;
+    }
+}
+
+/// Puts out the tile's hotspot if it's run out of fuel or oxygen, returning its heat to the air.
+pub(crate) fn smother(my_next_tile: &mut Tile, reactions: &[Reaction]) {
+    if my_next_tile.hotspot_volume <= 0.0
+        || (has_fuel(my_next_tile, reactions) && has_oxidizer(my_next_tile))
+    {
+        return;
+    }
+    let temperature = my_next_tile.temperature();
+    if my_next_tile.hotspot_temperature > temperature {
+        my_next_tile.thermal_energy += my_next_tile.hotspot_volume
+            * (my_next_tile.hotspot_temperature - temperature)
+            * my_next_tile.heat_capacity();
+    }
+    my_next_tile.hotspot_temperature = 0.0;
+    my_next_tile.hotspot_volume = 0.0;
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::gases::GasRegistry;
+    use crate::milla::reactions::default_reactions;
+
+    /// A room-temperature tile with air and some solid fuel.
+    fn fuelled_tile(solid_fuel: f32) -> Tile {
+        let mut tile = Tile::new();
+        tile.mode = AtmosMode::Sealed;
+        tile.gases.set(GAS_OXYGEN, 20.0);
+        tile.gases.set(GAS_NITROGEN, 80.0);
+        tile.gases.set_dirty();
+        tile.thermal_energy = tile.heat_capacity() * T20C;
+        tile.solid_fuel = solid_fuel;
+        tile
+    }
+
+    // Fire should spread through open boundaries into fuelled tiles, and nowhere else.
+    #[test]
+    fn spreads_into_fuel() {
+        let reactions = default_reactions(&GasRegistry::new());
+        let mut prev = ZLevel::new(4, 1);
+        for x in 0..4 {
+            *prev.get_tile_mut(prev.maybe_get_index(x, 0).unwrap()) = fuelled_tile(10.0);
+        }
+        let burning = prev.get_tile_mut(prev.maybe_get_index(1, 0).unwrap());
+        burning.hotspot_temperature = PLASMA_BURN_OPTIMAL_TEMP;
+        burning.hotspot_volume = 0.5;
+        // No fuel to the right.
+        prev.get_tile_mut(prev.maybe_get_index(2, 0).unwrap())
+            .solid_fuel = 0.0;
+
+        let mut left = fuelled_tile(10.0);
+        assert!(catch_fire(&prev, 0, 0, &mut left, &reactions));
+        assert!(left.hotspot_volume > 0.0);
+
+        let mut right = fuelled_tile(0.0);
+        assert!(!catch_fire(&prev, 2, 0, &mut right, &reactions));
+
+        // Walls stop it, too.
+        prev.get_tile_mut(prev.maybe_get_index(0, 0).unwrap())
+            .airtight_directions = AirtightDirections::EAST;
+        let mut walled = fuelled_tile(10.0);
+        assert!(!catch_fire(&prev, 0, 0, &mut walled, &reactions));
+    }
+
+    // Burning solid fuel should use up fuel and oxygen and make heat, until the fire is smothered.
+    #[test]
+    fn burns_and_smothers() {
+        let reactions = default_reactions(&GasRegistry::new());
+        let mut tile = fuelled_tile(1.5);
+        let temperature = tile.temperature();
+        tile.hotspot_temperature = PLASMA_BURN_OPTIMAL_TEMP;
+        tile.hotspot_volume = 0.5;
+
+        burn_solid_fuel(&mut tile);
+        assert_eq!(tile.solid_fuel, 1.0);
+        assert_eq!(tile.fuel_burnt, 0.5);
+        assert_eq!(tile.gases.get(GAS_OXYGEN), 19.5);
+        assert_eq!(tile.gases.get(GAS_CARBON_DIOXIDE), 0.5);
+        smother(&mut tile, &reactions);
+        assert!(tile.hotspot_volume > 0.0);
+
+        tile.solid_fuel = 0.0;
+        smother(&mut tile, &reactions);
+        assert_eq!(tile.hotspot_volume, 0.0);
+        assert!(tile.temperature() > temperature);
+    }
+
+    // Fuel should come from the fire reactions, with the same threshold hotspots use.
+    #[test]
+    fn fuel_from_reactions() {
+        let reactions = default_reactions(&GasRegistry::new());
+        let mut tile = fuelled_tile(0.0);
+        assert!(!has_fuel(&tile, &reactions));
+        tile.gases
+            .set(GAS_TOXINS, REACTION_SIGNIFICANCE_MOLES * 2.0);
+        assert!(has_fuel(&tile, &reactions));
+        // Without a fire reaction, toxins are just another gas.
+        assert!(!has_fuel(&tile, &[]));
+        tile.gases
+            .set(GAS_TOXINS, REACTION_SIGNIFICANCE_MOLES / 2.0);
+        assert!(!has_fuel(&tile, &reactions));
+    }
+
+    // Solid fuel shouldn't burn just because the room is warm.
+    #[test]
+    fn solid_fuel_needs_ignition() {
+        let mut tile = fuelled_tile(10.0);
+        tile.thermal_energy = tile.heat_capacity() * PLASMA_BURN_MIN_TEMP;
+        burn_solid_fuel(&mut tile);
+        assert_eq!(tile.solid_fuel, 10.0);
+
+        tile.thermal_energy = tile.heat_capacity() * SOLID_FUEL_IGNITION_TEMP;
+        burn_solid_fuel(&mut tile);
+        assert!(tile.solid_fuel < 10.0);
+    }
+}
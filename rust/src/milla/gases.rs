@@ -0,0 +1,278 @@
+use crate::milla::constants::*;
+use atomic_float::AtomicF32;
+use eyre::eyre;
+use std::sync::{atomic::AtomicUsize, atomic::Ordering::Relaxed, RwLock};
+
+/// Describes one kind of gas that MILLA can simulate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GasInfo {
+    /// The ID BYOND uses to refer to this gas.
+    pub(crate) id: String,
+    /// A human-readable name.
+    pub(crate) name: String,
+    /// The specific heat of this gas, in joules per kelvin-mole.
+    pub(crate) specific_heat: f32,
+    /// How many moles are needed for this gas to be visible, if it's ever visible.
+    pub(crate) visibility_moles: Option<f32>,
+    /// The molar mass of this gas, in grams per mole.
+    pub(crate) molar_mass: f32,
+}
+
+impl GasInfo {
+    pub(crate) fn new(
+        id: &str,
+        name: &str,
+        specific_heat: f32,
+        visibility_moles: Option<f32>,
+        molar_mass: f32,
+    ) -> Self {
+        GasInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            specific_heat,
+            visibility_moles,
+            molar_mass,
+        }
+    }
+}
+
+/// The set of gases MILLA knows about, indexed by their position in every GasSet.
+///
+/// The six builtin gases are always registered first, in the order of the GAS_* constants, so
+/// that reactions can find them. BYOND may register more at init, up to MAX_GAS_COUNT.
+///
+/// Specific heats and visibility thresholds are read for every tile, every tick, so they're
+/// mirrored into atomics rather than requiring a lock.
+pub(crate) struct GasRegistry {
+    gases: RwLock<Vec<GasInfo>>,
+    count: AtomicUsize,
+    specific_heats: [AtomicF32; MAX_GAS_COUNT],
+    visibility_moles: [AtomicF32; MAX_GAS_COUNT],
+}
+
+impl GasRegistry {
+    /// Creates a registry containing only the builtin gases.
+    pub(crate) fn new() -> Self {
+        let registry = GasRegistry {
+            gases: RwLock::new(Vec::new()),
+            count: AtomicUsize::new(0),
+            specific_heats: std::array::from_fn(|_| AtomicF32::new(0.0)),
+            visibility_moles: std::array::from_fn(|_| AtomicF32::new(f32::INFINITY)),
+        };
+        for (index, info) in [
+            (
+                GAS_OXYGEN,
+                GasInfo::new(
+                    "oxygen",
+                    "Oxygen",
+                    SPECIFIC_HEAT_OXYGEN,
+                    None,
+                    MOLAR_MASS_OXYGEN,
+                ),
+            ),
+            (
+                GAS_CARBON_DIOXIDE,
+                GasInfo::new(
+                    "carbon_dioxide",
+                    "Carbon Dioxide",
+                    SPECIFIC_HEAT_CARBON_DIOXIDE,
+                    None,
+                    MOLAR_MASS_CARBON_DIOXIDE,
+                ),
+            ),
+            (
+                GAS_NITROGEN,
+                GasInfo::new(
+                    "nitrogen",
+                    "Nitrogen",
+                    SPECIFIC_HEAT_NITROGEN,
+                    None,
+                    MOLAR_MASS_NITROGEN,
+                ),
+            ),
+            (
+                GAS_TOXINS,
+                GasInfo::new(
+                    "toxins",
+                    "Plasma",
+                    SPECIFIC_HEAT_TOXINS,
+                    Some(TOXINS_MIN_VISIBILITY_MOLES),
+                    MOLAR_MASS_TOXINS,
+                ),
+            ),
+            (
+                GAS_SLEEPING_AGENT,
+                GasInfo::new(
+                    "sleeping_agent",
+                    "Nitrous Oxide",
+                    SPECIFIC_HEAT_SLEEPING_AGENT,
+                    Some(SLEEPING_GAS_VISIBILITY_MOLES),
+                    MOLAR_MASS_SLEEPING_AGENT,
+                ),
+            ),
+            (
+                GAS_AGENT_B,
+                GasInfo::new(
+                    "agent_b",
+                    "Agent B",
+                    SPECIFIC_HEAT_AGENT_B,
+                    None,
+                    MOLAR_MASS_AGENT_B,
+                ),
+            ),
+        ] {
+            assert_eq!(registry.register(info).unwrap(), index);
+        }
+        registry
+    }
+
+    /// Registers a gas, or updates it if a gas with the same ID already exists.
+    /// Returns the gas's index.
+    /// Builtin gases can't be changed, and neither can the specific heat of a registered gas,
+    /// since every GasSet caches its heat capacity.
+    pub(crate) fn register(&self, info: GasInfo) -> eyre::Result<usize> {
+        if !info.specific_heat.is_finite() || info.specific_heat < 0.0 {
+            return Err(eyre!(
+                "Invalid specific heat {} for gas {}",
+                info.specific_heat,
+                info.id
+            ));
+        }
+        let mut gases = self.gases.write().unwrap();
+        let index = match gases.iter().position(|gas| gas.id == info.id) {
+            Some(index) if index < BUILTIN_GAS_COUNT => {
+                return Err(eyre!("Can't redefine builtin gas {}", info.id));
+            }
+            Some(index) if gases[index].specific_heat != info.specific_heat => {
+                return Err(eyre!(
+                    "Can't change the specific heat of gas {} from {} to {}",
+                    info.id,
+                    gases[index].specific_heat,
+                    info.specific_heat
+                ));
+            }
+            Some(index) => index,
+            None => {
+                if gases.len() >= MAX_GAS_COUNT {
+                    return Err(eyre!(
+                        "Too many gases registered, can't add {}. Update MAX_GAS_COUNT if this is intentional.",
+                        info.id
+                    ));
+                }
+                gases.push(info.clone());
+                gases.len() - 1
+            }
+        };
+        self.specific_heats[index].store(info.specific_heat, Relaxed);
+        self.visibility_moles[index].store(info.visibility_moles.unwrap_or(f32::INFINITY), Relaxed);
+        gases[index] = info;
+        self.count.store(gases.len(), Relaxed);
+        Ok(index)
+    }
+
+    /// How many gases are registered.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+
+    /// The specific heat of the given gas, in joules per kelvin-mole.
+    pub(crate) fn specific_heat(&self, gas: usize) -> f32 {
+        self.specific_heats[gas].load(Relaxed)
+    }
+
+    /// How many moles of the given gas are needed for it to be visible.
+    /// Invisible gases report infinity.
+    pub(crate) fn visibility_moles(&self, gas: usize) -> f32 {
+        self.visibility_moles[gas].load(Relaxed)
+    }
+
+    /// Finds a gas by its ID.
+    pub(crate) fn find(&self, id: &str) -> Option<usize> {
+        self.gases
+            .read()
+            .unwrap()
+            .iter()
+            .position(|gas| gas.id == id)
+    }
+
+    /// A copy of every registered gas, in index order.
+    pub(crate) fn all(&self) -> Vec<GasInfo> {
+        self.gases.read().unwrap().clone()
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The builtin gases should be in the slots the GAS_* constants expect.
+    #[test]
+    fn builtins_in_order() {
+        let registry = GasRegistry::new();
+        assert_eq!(registry.count(), BUILTIN_GAS_COUNT);
+        assert_eq!(registry.find("oxygen"), Some(GAS_OXYGEN));
+        assert_eq!(registry.find("carbon_dioxide"), Some(GAS_CARBON_DIOXIDE));
+        assert_eq!(registry.find("nitrogen"), Some(GAS_NITROGEN));
+        assert_eq!(registry.find("toxins"), Some(GAS_TOXINS));
+        assert_eq!(registry.find("sleeping_agent"), Some(GAS_SLEEPING_AGENT));
+        assert_eq!(registry.find("agent_b"), Some(GAS_AGENT_B));
+        assert_eq!(registry.specific_heat(GAS_TOXINS), SPECIFIC_HEAT_TOXINS);
+        assert_eq!(registry.visibility_moles(GAS_OXYGEN), f32::INFINITY);
+    }
+
+    // New gases are appended, and re-registering an ID updates it in place, as long as the
+    // specific heat stays the same. Builtins can't be touched.
+    #[test]
+    fn register_and_update() {
+        let registry = GasRegistry::new();
+        let hydrogen = registry
+            .register(GasInfo::new("hydrogen", "Hydrogen", 15.0, None, 2.0))
+            .unwrap();
+        assert_eq!(hydrogen, BUILTIN_GAS_COUNT);
+        assert_eq!(registry.count(), BUILTIN_GAS_COUNT + 1);
+        assert_eq!(registry.specific_heat(hydrogen), 15.0);
+
+        assert!(registry
+            .register(GasInfo::new("hydrogen", "Hydrogen", 25.0, None, 2.0))
+            .is_err());
+        assert_eq!(registry.specific_heat(hydrogen), 15.0);
+        let again = registry
+            .register(GasInfo::new("hydrogen", "Hydrogen", 15.0, Some(2.0), 2.0))
+            .unwrap();
+        assert_eq!(again, hydrogen);
+        assert_eq!(registry.count(), BUILTIN_GAS_COUNT + 1);
+        assert_eq!(registry.visibility_moles(hydrogen), 2.0);
+
+        assert!(registry
+            .register(GasInfo::new(
+                "oxygen",
+                "Oxygen",
+                SPECIFIC_HEAT_OXYGEN,
+                Some(1.0),
+                MOLAR_MASS_OXYGEN
+            ))
+            .is_err());
+        assert_eq!(registry.visibility_moles(GAS_OXYGEN), f32::INFINITY);
+    }
+
+    // The registry should refuse to grow past MAX_GAS_COUNT.
+    #[test]
+    fn registry_full() {
+        let registry = GasRegistry::new();
+        for i in BUILTIN_GAS_COUNT..MAX_GAS_COUNT {
+            registry
+                .register(GasInfo::new(
+                    &format!("gas_{}", i),
+                    "Filler",
+                    1.0,
+                    None,
+                    1.0,
+                ))
+                .unwrap();
+        }
+        assert!(registry
+            .register(GasInfo::new("one_too_many", "Filler", 1.0, None, 1.0))
+            .is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! Renders a Z level to a PNG, for looking at atmos without clicking around with an analyzer.
+//!
+//! Each tile is one pixel, with +Y going up like in game. Most channels are scaled from zero to
+//! the highest value on the Z level, and that scale is written into the PNG's description.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::statics::gas_registry;
+use eyre::eyre;
+use eyre::Result;
+use png::{ColorType, Encoder};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// What a heatmap shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum HeatmapChannel {
+    /// Pressure, in kPa.
+    Pressure,
+    /// Temperature, in kelvin.
+    Temperature,
+    /// Moles of the gas with this index.
+    Gas(usize),
+    /// Wind, with brightness for strength and hue for direction.
+    Wind,
+    /// How much of each tile is on fire.
+    Hotspots,
+    /// How well each tile conducts heat, averaged over its four sides.
+    Superconductivity,
+}
+
+impl HeatmapChannel {
+    /// Looks up a channel by name. Anything that isn't a channel name is treated as a gas ID.
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "pressure" => HeatmapChannel::Pressure,
+            "temperature" => HeatmapChannel::Temperature,
+            "wind" => HeatmapChannel::Wind,
+            "hotspots" => HeatmapChannel::Hotspots,
+            "superconductivity" => HeatmapChannel::Superconductivity,
+            gas_id => HeatmapChannel::Gas(
+                gas_registry()
+                    .find(gas_id)
+                    .ok_or(eyre!("Unknown heatmap channel {}", gas_id))?,
+            ),
+        })
+    }
+
+    /// The value this channel shows for a tile.
+    fn value(&self, tile: &Tile) -> f32 {
+        match self {
+            HeatmapChannel::Pressure => tile.pressure(),
+            HeatmapChannel::Temperature => tile.temperature(),
+            HeatmapChannel::Gas(gas) => tile.gases.get(*gas),
+            HeatmapChannel::Wind => tile.wind[0].hypot(tile.wind[1]),
+            HeatmapChannel::Hotspots => tile.hotspot_volume,
+            HeatmapChannel::Superconductivity => {
+                (tile.superconductivity.north
+                    + tile.superconductivity.east
+                    + tile.superconductivity.south
+                    + tile.superconductivity.west)
+                    / 4.0
+            }
+        }
+    }
+}
+
+/// A rendered heatmap, as RGB pixels with the top row first.
+pub(crate) struct Heatmap {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<u8>,
+    /// The value shown at full brightness.
+    pub(crate) scale: f32,
+    pub(crate) channel: HeatmapChannel,
+}
+
+impl Heatmap {
+    /// Renders a Z level.
+    pub(crate) fn render(z_level: &ZLevel, channel: HeatmapChannel) -> Self {
+        let width = z_level.width();
+        let height = z_level.height();
+        let scale = match channel {
+            HeatmapChannel::Hotspots => 1.0,
+            HeatmapChannel::Superconductivity => OPEN_HEAT_TRANSFER_COEFFICIENT,
+            _ => (0..z_level.tile_count())
+                .map(|index| channel.value(z_level.get_tile(index)))
+                .filter(|value| value.is_finite())
+                .fold(0.0, f32::max),
+        };
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for inv_y in 0..height {
+            // Reverse the Y direction, so +Y is up, like in the game.
+            let y = (height - inv_y - 1) as i32;
+            for x in 0..width as i32 {
+                let tile = z_level.get_tile(z_level.maybe_get_index(x, y).unwrap());
+                pixels.extend(tile_color(tile, channel, scale));
+            }
+        }
+
+        Heatmap {
+            width,
+            height,
+            pixels,
+            scale,
+            channel,
+        }
+    }
+
+    /// Writes the heatmap to a PNG file.
+    pub(crate) fn write_png(&self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.add_text_chunk(
+            "Description".to_string(),
+            format!("MILLA {:?}, from 0 to {}", self.channel, self.scale),
+        )?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+/// The color of one tile.
+fn tile_color(tile: &Tile, channel: HeatmapChannel, scale: f32) -> [u8; 3] {
+    if channel != HeatmapChannel::Superconductivity
+        && tile.airtight_directions.contains(
+            AirtightDirections::NORTH
+                | AirtightDirections::EAST
+                | AirtightDirections::SOUTH
+                | AirtightDirections::WEST,
+        )
+    {
+        // Show walls, so there's something to recognise the map by.
+        return WALL_COLOR;
+    }
+    let value = channel.value(tile);
+    if !value.is_finite() {
+        return BROKEN_COLOR;
+    }
+    let fraction = if scale > 0.0 {
+        (value / scale).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    if channel == HeatmapChannel::Wind {
+        let hue = tile.wind[1].atan2(tile.wind[0]) / std::f32::consts::TAU;
+        return hsv_to_rgb(hue.rem_euclid(1.0), 1.0, fraction);
+    }
+    ramp(fraction)
+}
+
+const WALL_COLOR: [u8; 3] = [64, 64, 64];
+
+/// Used for NaN and infinity, which shouldn't ever make it out of a tick.
+const BROKEN_COLOR: [u8; 3] = [0, 255, 0];
+
+/// The colors a heatmap goes through, from zero to full.
+const RAMP: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 160.0],
+    [160.0, 0.0, 160.0],
+    [255.0, 64.0, 0.0],
+    [255.0, 220.0, 0.0],
+    [255.0, 255.0, 255.0],
+];
+
+/// Picks a color along RAMP. `fraction` must be in [0.0, 1.0].
+fn ramp(fraction: f32) -> [u8; 3] {
+    let position = fraction * (RAMP.len() - 1) as f32;
+    let low = (position as usize).min(RAMP.len() - 2);
+    let mix = position - low as f32;
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        *value = (RAMP[low][channel] * (1.0 - mix) + RAMP[low + 1][channel] * mix).round() as u8;
+    }
+    color
+}
+
+/// Converts hue, saturation and value, all in [0.0, 1.0], to RGB.
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+    let sector = hue * 6.0;
+    let chroma = value * saturation;
+    let second = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as usize {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let offset = value - chroma;
+    [
+        ((r + offset) * 255.0).round() as u8,
+        ((g + offset) * 255.0).round() as u8,
+        ((b + offset) * 255.0).round() as u8,
+    ]
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use png::Decoder;
+
+    // Heatmaps should have +Y up, scale to the highest value, and survive a trip through PNG.
+    #[test]
+    fn render_and_write() {
+        let mut z_level = ZLevel::new(3, 2);
+        for index in 0..z_level.tile_count() {
+            z_level.edit_tile(index).mode = AtmosMode::Sealed;
+        }
+        let full = z_level.maybe_get_index(0, 1).unwrap();
+        z_level.edit_tile(full).gases.set(GAS_OXYGEN, 100.0);
+        let half = z_level.maybe_get_index(1, 1).unwrap();
+        z_level.edit_tile(half).gases.set(GAS_OXYGEN, 50.0);
+        let wall = z_level.maybe_get_index(2, 0).unwrap();
+        z_level.edit_tile(wall).airtight_directions = AirtightDirections::all();
+
+        let heatmap = Heatmap::render(&z_level, HeatmapChannel::parse("oxygen").unwrap());
+        assert_eq!(heatmap.scale, 100.0);
+        // The top row is y=1.
+        assert_eq!(&heatmap.pixels[0..3], &[255, 255, 255]);
+        assert_eq!(&heatmap.pixels[3..6], &ramp(0.5));
+        assert_eq!(&heatmap.pixels[6..9], &[0, 0, 0]);
+        assert_eq!(&heatmap.pixels[15..18], &WALL_COLOR);
+        assert!(HeatmapChannel::parse("not a gas").is_err());
+
+        let path = std::env::temp_dir().join("milla_heatmap_test.png");
+        heatmap.write_png(&path).unwrap();
+        let mut reader = Decoder::new(File::open(&path).unwrap())
+            .read_info()
+            .unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(&buffer[..info.buffer_size()], heatmap.pixels.as_slice());
+        let _ = std::fs::remove_file(&path);
+    }
+}
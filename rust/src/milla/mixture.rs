@@ -0,0 +1,267 @@
+//! Gas that isn't on the map, like the air in tanks, canisters and lungs.
+//!
+//! BYOND refers to each mixture by ID, and frees it once it's done with it. Mixtures don't have a
+//! volume of their own; anything that depends on one takes it as an argument.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::reactions::Reaction;
+use crate::milla::simulate;
+use crate::milla::statics::gas_registry;
+use eyre::eyre;
+use eyre::Result;
+
+/// A single, evenly mixed body of gas.
+#[derive(Debug, Clone)]
+pub(crate) struct GasMixture {
+    pub(crate) gases: GasSet,
+    pub(crate) thermal_energy: f32,
+}
+
+impl GasMixture {
+    pub(crate) fn new() -> Self {
+        GasMixture {
+            gases: GasSet::new(),
+            thermal_energy: 0.0,
+        }
+    }
+
+    /// The temperature of the gas, in kelvin.
+    pub(crate) fn temperature(&self) -> f32 {
+        let heat_capacity = self.gases.heat_capacity();
+        if heat_capacity <= 0.0 {
+            0.0
+        } else {
+            self.thermal_energy / heat_capacity
+        }
+    }
+
+    /// The pressure of the gas if it filled `volume` liters, in kPa.
+    pub(crate) fn pressure(&self, volume: f32) -> f32 {
+        if volume <= 0.0 {
+            return 0.0;
+        }
+        self.gases.moles()
+            * self.temperature().max(MINIMUM_TEMPERATURE_FOR_PRESSURE)
+            * R_IDEAL_GAS_EQUATION
+            / volume
+    }
+
+    /// Adds gases, or removes them with negative amounts, by gas index.
+    /// Removed gas takes its share of the heat with it, and we can't remove more than there is.
+    /// Added gas arrives at `temperature`, or the mixture's temperature if that's None.
+    pub(crate) fn add_moles(&mut self, deltas: &[f32], temperature: Option<f32>) {
+        let old_temperature = self.temperature();
+        let added_temperature = temperature.unwrap_or(old_temperature);
+        let registry = gas_registry();
+        for (gas, &delta) in deltas.iter().enumerate() {
+            let current = self.gases.get(gas);
+            let change = delta.max(-current);
+            if change < 0.0 {
+                self.thermal_energy += change * registry.specific_heat(gas) * old_temperature;
+            } else {
+                self.thermal_energy += change * registry.specific_heat(gas) * added_temperature;
+            }
+            self.gases.set(gas, current + change);
+        }
+        self.thermal_energy = self.thermal_energy.max(0.0);
+    }
+
+    /// Moves all of `other` into this mixture, leaving it empty.
+    pub(crate) fn merge(&mut self, other: &mut GasMixture) {
+        self.gases.add_gases(&other.gases);
+        self.thermal_energy += other.thermal_energy;
+        other.gases.clear();
+        other.thermal_energy = 0.0;
+    }
+
+    /// Takes `ratio` of this mixture out into a new one, at the same temperature.
+    pub(crate) fn split(&mut self, ratio: f32) -> GasMixture {
+        let ratio = ratio.clamp(0.0, 1.0);
+        let mut removed = GasMixture::new();
+        for gas in 0..gas_registry().count() {
+            let moles = self.gases.get(gas);
+            removed.gases.set(gas, moles * ratio);
+            self.gases.set(gas, moles * (1.0 - ratio));
+        }
+        removed.thermal_energy = self.thermal_energy * ratio;
+        self.thermal_energy -= removed.thermal_energy;
+        removed
+    }
+
+    /// Lets two mixtures, filling `my_volume` and `their_volume` liters, even out.
+    /// Afterwards, they have the same temperature, pressure and proportions of each gas.
+    pub(crate) fn equalize(&mut self, my_volume: f32, other: &mut GasMixture, their_volume: f32) {
+        let total_volume = my_volume + their_volume;
+        if total_volume <= 0.0 {
+            return;
+        }
+        self.merge(other);
+        *other = self.split(their_volume / total_volume);
+    }
+
+    /// Runs reactions on the mixture, as if it were a tile with no hotspot.
+    /// Returns how much fuel was burnt.
+    pub(crate) fn react(&mut self, reactions: &[Reaction]) -> f32 {
+        let mut tile = Tile::new();
+        tile.gases.copy_from(&self.gases);
+        tile.thermal_energy = self.thermal_energy;
+        simulate::react(&mut tile, false, reactions);
+        self.gases.copy_from(&tile.gases);
+        self.thermal_energy = tile.thermal_energy;
+        tile.fuel_burnt
+    }
+}
+
+/// Every gas mixture BYOND is holding on to.
+/// IDs are positions in the list, and are reused once freed.
+#[derive(Default)]
+pub(crate) struct GasMixtures {
+    mixtures: Vec<Option<GasMixture>>,
+}
+
+impl GasMixtures {
+    pub(crate) const fn new() -> Self {
+        GasMixtures {
+            mixtures: Vec::new(),
+        }
+    }
+
+    /// Stores a mixture, and returns its ID.
+    pub(crate) fn create(&mut self, mixture: GasMixture) -> usize {
+        insert_into_free_slot(&mut self.mixtures, mixture)
+    }
+
+    pub(crate) fn free(&mut self, id: usize) -> Result<()> {
+        self.get(id)?;
+        self.mixtures[id] = None;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, id: usize) -> Result<&GasMixture> {
+        self.mixtures
+            .get(id)
+            .and_then(Option::as_ref)
+            .ok_or(eyre!("No gas mixture with ID {}", id))
+    }
+
+    pub(crate) fn get_mut(&mut self, id: usize) -> Result<&mut GasMixture> {
+        self.mixtures
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .ok_or(eyre!("No gas mixture with ID {}", id))
+    }
+
+    /// Fetches two different mixtures at once.
+    pub(crate) fn get_pair_mut(
+        &mut self,
+        first: usize,
+        second: usize,
+    ) -> Result<(&mut GasMixture, &mut GasMixture)> {
+        if first == second {
+            return Err(eyre!("Gas mixture {} can't be paired with itself.", first));
+        }
+        self.get(first)?;
+        self.get(second)?;
+        let (low, high) = (first.min(second), first.max(second));
+        let (left, right) = self.mixtures.split_at_mut(high);
+        let low_mixture = left[low].as_mut().unwrap();
+        let high_mixture = right[0].as_mut().unwrap();
+        if first < second {
+            Ok((low_mixture, high_mixture))
+        } else {
+            Ok((high_mixture, low_mixture))
+        }
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::reactions::default_reactions;
+
+    const TEST_TOLERANCE: f32 = 0.001;
+
+    /// A mixture of air at room temperature.
+    fn air(moles: f32) -> GasMixture {
+        let mut mixture = GasMixture::new();
+        let mut deltas = [0.0; MAX_GAS_COUNT];
+        deltas[GAS_OXYGEN] = moles * 0.2;
+        deltas[GAS_NITROGEN] = moles * 0.8;
+        mixture.add_moles(&deltas, Some(T20C));
+        mixture
+    }
+
+    // Splitting and merging should conserve gas and heat, and keep the temperature.
+    #[test]
+    fn split_and_merge() {
+        let mut mixture = air(100.0);
+        let mut removed = mixture.split(0.25);
+        assert!((removed.gases.moles() - 25.0).abs() < TEST_TOLERANCE);
+        assert!((mixture.gases.moles() - 75.0).abs() < TEST_TOLERANCE);
+        assert!((removed.temperature() - T20C).abs() < TEST_TOLERANCE);
+        assert!((mixture.temperature() - T20C).abs() < TEST_TOLERANCE);
+
+        mixture.merge(&mut removed);
+        assert!((mixture.gases.moles() - 100.0).abs() < TEST_TOLERANCE);
+        assert!((mixture.temperature() - T20C).abs() < TEST_TOLERANCE);
+        assert_eq!(removed.gases.moles(), 0.0);
+    }
+
+    // Adding hot gas should warm the mixture, and we can't remove more than there is.
+    #[test]
+    fn add_and_remove() {
+        let mut mixture = air(100.0);
+        let mut deltas = [0.0; MAX_GAS_COUNT];
+        deltas[GAS_NITROGEN] = 80.0;
+        mixture.add_moles(&deltas, Some(T20C * 2.0));
+        assert!(mixture.temperature() > T20C);
+
+        deltas[GAS_NITROGEN] = -1000.0;
+        mixture.add_moles(&deltas, None);
+        assert_eq!(mixture.gases.get(GAS_NITROGEN), 0.0);
+        assert!((mixture.gases.moles() - 20.0).abs() < TEST_TOLERANCE);
+    }
+
+    // Equalized mixtures should end up at the same pressure, sharing gas by volume.
+    #[test]
+    fn equalize() {
+        let mut tank = air(100.0);
+        let mut canister = GasMixture::new();
+        tank.equalize(70.0, &mut canister, 1000.0 - 70.0);
+        assert!((tank.pressure(70.0) - canister.pressure(930.0)).abs() < TEST_TOLERANCE);
+        assert!((tank.gases.moles() - 7.0).abs() < TEST_TOLERANCE);
+        assert!((canister.temperature() - T20C).abs() < TEST_TOLERANCE);
+    }
+
+    // Mixtures should react just like tiles do.
+    #[test]
+    fn reacts() {
+        let mut mixture = GasMixture::new();
+        let mut deltas = [0.0; MAX_GAS_COUNT];
+        deltas[GAS_OXYGEN] = 50.0;
+        deltas[GAS_TOXINS] = 50.0;
+        mixture.add_moles(&deltas, Some(PLASMA_BURN_OPTIMAL_TEMP));
+        let fuel_burnt = mixture.react(&default_reactions(gas_registry()));
+        assert!(fuel_burnt > 0.0);
+        assert!(mixture.gases.get(GAS_TOXINS) < 50.0);
+        assert!(mixture.temperature() > PLASMA_BURN_OPTIMAL_TEMP);
+    }
+
+    // IDs should start at 1, freed IDs should be reused, and pairs have to be different mixtures.
+    #[test]
+    fn ids() {
+        let mut mixtures = GasMixtures::new();
+        let first = mixtures.create(air(1.0));
+        let second = mixtures.create(air(2.0));
+        assert_eq!(first, 1);
+        assert!(mixtures.get(0).is_err());
+        assert!(mixtures.get_pair_mut(first, first).is_err());
+        let (a, b) = mixtures.get_pair_mut(second, first).unwrap();
+        assert!((a.gases.moles() - 2.0).abs() < TEST_TOLERANCE);
+        assert!((b.gases.moles() - 1.0).abs() < TEST_TOLERANCE);
+        mixtures.free(first).unwrap();
+        assert!(mixtures.get(first).is_err());
+        assert_eq!(mixtures.create(air(3.0)), first);
+    }
+}
@@ -7,9 +7,26 @@
 //! It stores its own model of the air distribution, and BYOND will call in to view and make
 //! adjustments, as well as to trigger atmos ticks.
 mod api;
+mod audit;
+pub mod bench;
 mod constants;
 mod conversion;
+mod emitters;
+mod environment;
+mod explosion;
+mod fire;
+mod gases;
+mod heatmap;
+mod mixture;
 mod model;
+mod pipenet;
+mod queue;
+mod reactions;
 mod simulate;
+mod snapshot;
 mod statics;
+mod telemetry;
 mod tick;
+mod watch;
+mod wind;
+mod zones;
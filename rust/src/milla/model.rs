@@ -1,16 +1,31 @@
 use crate::milla::constants::*;
+use crate::milla::emitters::{GasEmitters, Heaters};
+use crate::milla::environment::Environments;
+use crate::milla::pipenet::PipeNetworks;
+use crate::milla::queue::{WriteQueue, ZLevelWrite};
+use crate::milla::statics::gas_registry;
+use crate::milla::watch::WatchRules;
+use crate::milla::zones::Zones;
 use atomic_float::AtomicF32;
 use bitflags::bitflags;
 use byondapi::map::{byond_locatexyz, ByondXYZ};
 use byondapi::prelude::*;
-use std::collections::HashSet;
-use std::ops::Add;
-use std::sync::{atomic::AtomicBool, atomic::Ordering::Relaxed, RwLock};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Add, Range};
+use std::sync::{
+    atomic::AtomicBool, atomic::AtomicUsize, atomic::Ordering::Relaxed, Arc, Mutex, MutexGuard,
+    RwLock,
+};
 
 /// Represents a collection of gases, with amounts in moles.
+/// Indexed by each gas's position in the gas registry.
 #[derive(Debug)]
 pub(crate) struct GasSet {
-    pub(crate) values: [f32; GAS_COUNT],
+    pub(crate) values: [f32; MAX_GAS_COUNT],
     moles_cache: AtomicF32,
     heat_capacity_cache: AtomicF32,
     dirty: AtomicBool,
@@ -20,70 +35,37 @@ pub(crate) struct GasSet {
 impl GasSet {
     pub(crate) fn new() -> Self {
         GasSet {
-            values: [0.0; GAS_COUNT],
+            values: [0.0; MAX_GAS_COUNT],
             moles_cache: 0.0.into(),
             heat_capacity_cache: 0.0.into(),
             dirty: true.into(),
         }
     }
-    pub(crate) fn oxygen(&self) -> f32 {
-        self.values[GAS_OXYGEN]
+    /// How many moles of the given gas there are.
+    pub(crate) fn get(&self, gas: usize) -> f32 {
+        self.values[gas]
     }
-    pub(crate) fn set_oxygen(&mut self, value: f32) {
-        self.values[GAS_OXYGEN] = value;
-        self.dirty.store(true, Relaxed);
-    }
-    pub(crate) fn carbon_dioxide(&self) -> f32 {
-        self.values[GAS_CARBON_DIOXIDE]
-    }
-    pub(crate) fn set_carbon_dioxide(&mut self, value: f32) {
-        self.values[GAS_CARBON_DIOXIDE] = value;
-        self.dirty.store(true, Relaxed);
-    }
-    pub(crate) fn nitrogen(&self) -> f32 {
-        self.values[GAS_NITROGEN]
-    }
-    pub(crate) fn set_nitrogen(&mut self, value: f32) {
-        self.values[GAS_NITROGEN] = value;
-        self.dirty.store(true, Relaxed);
-    }
-    pub(crate) fn toxins(&self) -> f32 {
-        self.values[GAS_TOXINS]
-    }
-    pub(crate) fn set_toxins(&mut self, value: f32) {
-        self.values[GAS_TOXINS] = value;
-        self.dirty.store(true, Relaxed);
-    }
-    pub(crate) fn sleeping_agent(&self) -> f32 {
-        self.values[GAS_SLEEPING_AGENT]
-    }
-    pub(crate) fn set_sleeping_agent(&mut self, value: f32) {
-        self.values[GAS_SLEEPING_AGENT] = value;
-        self.dirty.store(true, Relaxed);
-    }
-    pub(crate) fn agent_b(&self) -> f32 {
-        self.values[GAS_AGENT_B]
-    }
-    pub(crate) fn set_agent_b(&mut self, value: f32) {
-        self.values[GAS_AGENT_B] = value;
+    /// Sets how many moles of the given gas there are.
+    pub(crate) fn set(&mut self, gas: usize, value: f32) {
+        self.values[gas] = value;
         self.dirty.store(true, Relaxed);
     }
     pub(crate) fn set_dirty(&mut self) {
         self.dirty.store(true, Relaxed);
     }
     pub(crate) fn recalculate(&self) {
+        let registry = gas_registry();
         let mut moles = 0.0;
         let mut heat_capacity = 0.0;
-        for i in 0..GAS_COUNT {
+        for i in 0..registry.count() {
             moles += self.values[i];
-            heat_capacity += self.values[i] * SPECIFIC_HEATS[i];
+            heat_capacity += self.values[i] * registry.specific_heat(i);
         }
         self.moles_cache.store(moles, Relaxed);
         self.heat_capacity_cache.store(heat_capacity, Relaxed);
         self.dirty.store(false, Relaxed);
     }
     /// The heat capacity of this set of gases, in joules per kelvin.
-    #[allow(clippy::needless_range_loop)]
     pub(crate) fn heat_capacity(&self) -> f32 {
         if self.dirty.load(Relaxed) {
             self.recalculate();
@@ -91,7 +73,6 @@ impl GasSet {
         self.heat_capacity_cache.load(Relaxed)
     }
     /// The total number of moles of gas.
-    #[allow(clippy::needless_range_loop)]
     pub(crate) fn moles(&self) -> f32 {
         if self.dirty.load(Relaxed) {
             self.recalculate();
@@ -99,30 +80,26 @@ impl GasSet {
         self.moles_cache.load(Relaxed)
     }
     pub(crate) fn copy_from(&mut self, other: &GasSet) {
-        for i in 0..GAS_COUNT {
-            self.values[i] = other.values[i];
-            let dirty = other.dirty.load(Relaxed);
-            if dirty {
-                self.dirty.store(true, Relaxed);
-            } else {
-                self.heat_capacity_cache
-                    .store(other.heat_capacity_cache.load(Relaxed), Relaxed);
-                self.moles_cache
-                    .store(other.moles_cache.load(Relaxed), Relaxed);
-                self.dirty.store(false, Relaxed);
-            }
+        self.values = other.values;
+        if other.dirty.load(Relaxed) {
+            self.dirty.store(true, Relaxed);
+        } else {
+            self.heat_capacity_cache
+                .store(other.heat_capacity_cache.load(Relaxed), Relaxed);
+            self.moles_cache
+                .store(other.moles_cache.load(Relaxed), Relaxed);
+            self.dirty.store(false, Relaxed);
         }
     }
     pub(crate) fn add_gases(&mut self, other: &Self) {
-        for i in 0..GAS_COUNT {
+        for i in 0..MAX_GAS_COUNT {
             self.values[i] += other.values[i];
         }
+        self.dirty.store(true, Relaxed);
     }
-    #[allow(dead_code)]
     pub(crate) fn clear(&mut self) {
-        for i in 0..GAS_COUNT {
-            self.values[i] = 0.0;
-        }
+        self.values = [0.0; MAX_GAS_COUNT];
+        self.dirty.store(true, Relaxed);
     }
 }
 
@@ -152,7 +129,7 @@ pub(crate) enum AtmosMode {
     /// Tile has no special behavior.
     Sealed,
     /// Tile is exposed to the given environment.
-    ExposedTo { environment_id: u8 },
+    ExposedTo { environment_id: u16 },
     /// Prevents hot tiles from automatically decaying towards T20C
     NoDecay,
 }
@@ -202,6 +179,8 @@ bitflags! {
         const EAST = 1 << 1;
         const SOUTH = 1 << 2;
         const WEST = 1 << 3;
+        const UP = 1 << 4;
+        const DOWN = 1 << 5;
     }
 }
 
@@ -210,6 +189,7 @@ bitflags! {
 #[derive(Debug, Clone)]
 pub(crate) struct Tile {
     /// Which directions this tile cannot transmit gases in.
+    /// Tiles start airtight up and down, so Z levels only share air where BYOND opens them up.
     pub(crate) airtight_directions: AirtightDirections,
     /// The gases this tile holds.
     pub(crate) gases: GasSet,
@@ -220,7 +200,11 @@ pub(crate) struct Tile {
     /// How well this tile conducts heat in each direction
     pub(crate) superconductivity: Superconductivity,
     /// How much heat capacity the tile itself has, in joules per kelvin.
+    /// The tile's floor or walls keep their own temperature, separate from the air, and slowly
+    /// exchange heat with it.
     pub(crate) innate_heat_capacity: f32,
+    /// How much thermal energy the tile itself has, in joules.
+    pub(crate) innate_thermal_energy: f32,
     /// How hot the tile's hotspot is. A hotspot is a sub-tile reagion that's caught fire.
     pub(crate) hotspot_temperature: f32,
     /// How much of the tile the hotspot covers. 1.0 would be the entire tile.
@@ -229,33 +213,39 @@ pub(crate) struct Tile {
     pub(crate) wind: [f32; AXES.len()],
     /// Is there a wall in this direction?
     pub(crate) wall: [bool; AXES.len()],
-    pub(crate) gas_flow: [[[f32; 2]; GAS_COUNT]; AXES.len()],
+    /// How strongly each gas flows in and out along each axis.
+    pub(crate) gas_flow: [[[f32; 2]; MAX_GAS_COUNT]; AXES.len()],
     /// How much fuel was burnt this tick?
     pub(crate) fuel_burnt: f32,
+    /// How much solid fuel, like burnable floors and objects, the tile has left, in moles of
+    /// oxygen it can burn.
+    pub(crate) solid_fuel: f32,
 }
 
 impl Tile {
     pub(crate) fn new() -> Self {
         Tile {
-            airtight_directions: AirtightDirections::empty(),
+            airtight_directions: AirtightDirections::UP | AirtightDirections::DOWN,
             gases: GasSet::new(),
             thermal_energy: 0.0,
             mode: AtmosMode::Space,
             superconductivity: Superconductivity::new(),
             innate_heat_capacity: 0.0,
+            innate_thermal_energy: 0.0,
             hotspot_temperature: 0.0,
             hotspot_volume: 0.0,
             wind: [0.0, 0.0],
             wall: [false, false],
-            gas_flow: [[[0.0; 2]; GAS_COUNT]; AXES.len()],
+            gas_flow: [[[0.0; 2]; MAX_GAS_COUNT]; AXES.len()],
             fuel_burnt: 0.0,
+            solid_fuel: 0.0,
         }
     }
-    /// The total heat capacity of this tile and its gases, in joules per kelvin.
+    /// The heat capacity of this tile's gases, in joules per kelvin.
     pub(crate) fn heat_capacity(&self) -> f32 {
-        self.gases.heat_capacity() + self.innate_heat_capacity
+        self.gases.heat_capacity()
     }
-    /// The temperature of this tile, in kelvin.
+    /// The temperature of this tile's air, in kelvin.
     pub(crate) fn temperature(&self) -> f32 {
         let heat_capacity = self.heat_capacity();
         if heat_capacity <= 0.0 {
@@ -269,8 +259,7 @@ impl Tile {
         if let AtmosMode::Space = self.mode {
             return 0.0;
         }
-        let mut heat_capacity = self.gases.heat_capacity();
-        heat_capacity += self.innate_heat_capacity;
+        let heat_capacity = self.gases.heat_capacity();
         if heat_capacity <= 0.0 {
             return 0.0;
         }
@@ -280,6 +269,47 @@ impl Tile {
             * R_IDEAL_GAS_EQUATION
             / TILE_VOLUME
     }
+    /// Whether the tile itself has enough heat capacity to hold and exchange heat.
+    pub(crate) fn has_thermal_mass(&self) -> bool {
+        self.innate_heat_capacity >= MINIMUM_INNATE_HEAT_CAPACITY
+    }
+    /// The temperature of the tile itself, in kelvin.
+    pub(crate) fn innate_temperature(&self) -> f32 {
+        if self.innate_heat_capacity <= 0.0 {
+            0.0
+        } else {
+            self.innate_thermal_energy / self.innate_heat_capacity
+        }
+    }
+    /// Whether heat conducts to neighboring tiles through the tile itself, rather than its air.
+    /// Anything with its own thermal mass, like a wall, conducts through that.
+    pub(crate) fn conducts_innately(&self) -> bool {
+        self.has_thermal_mass()
+    }
+    /// The heat capacity of whatever conducts heat to neighboring tiles.
+    pub(crate) fn conducting_heat_capacity(&self) -> f32 {
+        if self.conducts_innately() {
+            self.innate_heat_capacity
+        } else {
+            self.heat_capacity()
+        }
+    }
+    /// The temperature of whatever conducts heat to neighboring tiles.
+    pub(crate) fn conducting_temperature(&self) -> f32 {
+        if self.conducts_innately() {
+            self.innate_temperature()
+        } else {
+            self.temperature()
+        }
+    }
+    /// The thermal energy of whatever conducts heat to neighboring tiles.
+    pub(crate) fn conducting_thermal_energy_mut(&mut self) -> &mut f32 {
+        if self.conducts_innately() {
+            &mut self.innate_thermal_energy
+        } else {
+            &mut self.thermal_energy
+        }
+    }
     /// Calculates the partial pressure of a gas in a tile.
     pub(crate) fn partial_pressure(&self, gas: usize) -> f32 {
         if self.gases.values[gas] <= 0.0 {
@@ -300,53 +330,73 @@ impl Tile {
         self.mode = other.mode;
         self.superconductivity.copy_from(&other.superconductivity);
         self.innate_heat_capacity = other.innate_heat_capacity;
+        self.innate_thermal_energy = other.innate_thermal_energy;
         self.hotspot_temperature = other.hotspot_temperature;
         self.hotspot_volume = other.hotspot_volume;
         for axis in 0..AXES.len() {
             self.wind[axis] = other.wind[axis];
             self.wall[axis] = other.wall[axis];
-            for gas in 0..GAS_COUNT {
+            for gas in 0..MAX_GAS_COUNT {
                 self.gas_flow[axis][gas][GAS_FLOW_IN] = other.gas_flow[axis][gas][GAS_FLOW_IN];
                 self.gas_flow[axis][gas][GAS_FLOW_OUT] = other.gas_flow[axis][gas][GAS_FLOW_OUT];
             }
         }
         self.fuel_burnt = other.fuel_burnt;
+        self.solid_fuel = other.solid_fuel;
     }
 }
 
-/// Converts a tile into BYOND values.
-/// Must match the order in code/__DEFINES/milla.dm
-impl From<&Tile> for Vec<ByondValue> {
-    fn from(value: &Tile) -> Self {
-        let mut environment_id: u8 = 0;
+impl Tile {
+    /// Converts a tile into BYOND values, with only the builtin gases.
+    /// Must match the order in code/__DEFINES/milla.dm
+    pub(crate) fn to_builtin_byond_values(&self) -> Vec<ByondValue> {
+        let mut environment_id: u16 = 0;
         if let AtmosMode::ExposedTo {
             environment_id: env,
-        } = value.mode
+        } = self.mode
         {
             environment_id = env;
         }
-        vec![
-            ByondValue::from(value.airtight_directions.bits() as f32),
-            ByondValue::from(value.gases.oxygen()),
-            ByondValue::from(value.gases.carbon_dioxide()),
-            ByondValue::from(value.gases.nitrogen()),
-            ByondValue::from(value.gases.toxins()),
-            ByondValue::from(value.gases.sleeping_agent()),
-            ByondValue::from(value.gases.agent_b()),
-            ByondValue::from(value.mode),
+        let mut ret = vec![ByondValue::from(self.airtight_directions.bits() as f32)];
+        ret.extend(
+            self.gases.values[0..BUILTIN_GAS_COUNT]
+                .iter()
+                .map(|moles| ByondValue::from(*moles)),
+        );
+        ret.extend(vec![
+            ByondValue::from(self.mode),
             ByondValue::from(environment_id as f32),
-            ByondValue::from(value.superconductivity.north),
-            ByondValue::from(value.superconductivity.east),
-            ByondValue::from(value.superconductivity.south),
-            ByondValue::from(value.superconductivity.west),
-            ByondValue::from(value.innate_heat_capacity),
-            ByondValue::from(value.temperature()),
-            ByondValue::from(value.hotspot_temperature),
-            ByondValue::from(value.hotspot_volume),
-            ByondValue::from(value.wind[AXIS_X]),
-            ByondValue::from(value.wind[AXIS_Y]),
-            ByondValue::from(value.fuel_burnt),
-        ]
+            ByondValue::from(self.superconductivity.north),
+            ByondValue::from(self.superconductivity.east),
+            ByondValue::from(self.superconductivity.south),
+            ByondValue::from(self.superconductivity.west),
+            ByondValue::from(self.innate_heat_capacity),
+            ByondValue::from(self.temperature()),
+            ByondValue::from(self.hotspot_temperature),
+            ByondValue::from(self.hotspot_volume),
+            ByondValue::from(self.wind[AXIS_X]),
+            ByondValue::from(self.wind[AXIS_Y]),
+            ByondValue::from(self.fuel_burnt),
+            ByondValue::from(self.innate_temperature()),
+            ByondValue::from(self.solid_fuel),
+        ]);
+        ret
+    }
+}
+
+/// Converts a tile into BYOND values.
+/// The builtin gases keep their fixed positions, and any gases BYOND registered are appended at
+/// the end, in registry order.
+/// Must match the order in code/__DEFINES/milla.dm
+impl From<&Tile> for Vec<ByondValue> {
+    fn from(value: &Tile) -> Self {
+        let mut ret = value.to_builtin_byond_values();
+        ret.extend(
+            value.gases.values[BUILTIN_GAS_COUNT..gas_registry().count()]
+                .iter()
+                .map(|moles| ByondValue::from(*moles)),
+        );
+        ret
     }
 }
 
@@ -356,6 +406,138 @@ bitflags! {
         const DISPLAY = 1 << 0;
         const HOT = 1 << 1;
         const WIND = 1 << 2;
+        const IGNITED = 1 << 3;
+    }
+}
+
+/// A 64-bit FNV-1a hasher. Unlike DefaultHasher, its output is fixed, so hashes stay comparable
+/// between runs and builds.
+pub(crate) struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Puts a value in the first free slot of a list of things BYOND refers to by ID, and returns
+/// its position.
+/// Slot 0 is never used, because 0 is falsy in DM and `if(id)` checks would treat it as missing.
+pub(crate) fn insert_into_free_slot<T>(slots: &mut Vec<Option<T>>, value: T) -> usize {
+    if slots.is_empty() {
+        slots.push(None);
+    }
+    match slots.iter().skip(1).position(Option::is_none) {
+        Some(position) => {
+            let id = position + 1;
+            slots[id] = Some(value);
+            id
+        }
+        None => {
+            slots.push(Some(value));
+            slots.len() - 1
+        }
+    }
+}
+
+/// Atmos stats summed up over a group of tiles.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RegionStats {
+    /// How many tiles were included.
+    pub(crate) tiles: usize,
+    /// Total moles of each gas, in gas registry order.
+    pub(crate) moles: [f32; MAX_GAS_COUNT],
+    pub(crate) total_temperature: f32,
+    pub(crate) min_temperature: f32,
+    pub(crate) max_temperature: f32,
+    pub(crate) total_pressure: f32,
+    pub(crate) min_pressure: f32,
+    pub(crate) max_pressure: f32,
+    /// How many tiles had a hotspot.
+    pub(crate) hotspots: usize,
+    /// Total fuel burnt last tick.
+    pub(crate) fuel_burnt: f32,
+}
+
+impl RegionStats {
+    /// Adds a tile to the stats.
+    pub(crate) fn add(&mut self, tile: &Tile) {
+        let temperature = tile.temperature();
+        let pressure = tile.pressure();
+        if self.tiles == 0 {
+            self.min_temperature = temperature;
+            self.max_temperature = temperature;
+            self.min_pressure = pressure;
+            self.max_pressure = pressure;
+        } else {
+            self.min_temperature = self.min_temperature.min(temperature);
+            self.max_temperature = self.max_temperature.max(temperature);
+            self.min_pressure = self.min_pressure.min(pressure);
+            self.max_pressure = self.max_pressure.max(pressure);
+        }
+        self.tiles += 1;
+        for gas in 0..gas_registry().count() {
+            self.moles[gas] += tile.gases.get(gas);
+        }
+        self.total_temperature += temperature;
+        self.total_pressure += pressure;
+        if tile.hotspot_volume > 0.0 {
+            self.hotspots += 1;
+        }
+        self.fuel_burnt += tile.fuel_burnt;
+    }
+
+    /// The average temperature of the tiles, in kelvin.
+    pub(crate) fn mean_temperature(&self) -> f32 {
+        if self.tiles == 0 {
+            0.0
+        } else {
+            self.total_temperature / self.tiles as f32
+        }
+    }
+
+    /// The average pressure of the tiles, in kPa.
+    pub(crate) fn mean_pressure(&self) -> f32 {
+        if self.tiles == 0 {
+            0.0
+        } else {
+            self.total_pressure / self.tiles as f32
+        }
+    }
+}
+
+impl From<&RegionStats> for Vec<ByondValue> {
+    /// Converts region stats into BYOND values.
+    /// Must match the order in code/__DEFINES/rust.dm
+    fn from(value: &RegionStats) -> Self {
+        let mut ret = vec![
+            ByondValue::from(value.tiles as f32),
+            ByondValue::from(value.mean_temperature()),
+            ByondValue::from(value.min_temperature),
+            ByondValue::from(value.max_temperature),
+            ByondValue::from(value.mean_pressure()),
+            ByondValue::from(value.min_pressure),
+            ByondValue::from(value.max_pressure),
+            ByondValue::from(value.hotspots as f32),
+            ByondValue::from(value.fuel_burnt),
+        ];
+        for gas in 0..gas_registry().count() {
+            ret.push(ByondValue::from(value.moles[gas]));
+        }
+        ret
     }
 }
 
@@ -382,10 +564,12 @@ pub(crate) struct InterestingTile {
 /// It'd be friendlier to make these BYOND lists or even datums, but this way is faster than lists,
 /// and datums isn't possible.
 ///
+/// Interesting tiles only carry the builtin gases, so that every one is the same size.
+///
 /// Must match the order in code/__DEFINES/milla.dm
 impl From<&InterestingTile> for Vec<ByondValue> {
     fn from(value: &InterestingTile) -> Self {
-        let mut ret: Vec<ByondValue> = (&value.tile).into();
+        let mut ret: Vec<ByondValue> = value.tile.to_builtin_byond_values();
         ret.extend(vec![
             byond_locatexyz(value.coords).unwrap(),
             ByondValue::from(value.reasons.bits() as f32),
@@ -398,66 +582,456 @@ impl From<&InterestingTile> for Vec<ByondValue> {
 }
 
 /// A single Z level in the atmos model.
+/// Tiles are stored column by column, so the index of (x, y) is x * height + y.
+///
+/// Tiles are also grouped into square chunks of ACTIVE_CHUNK_SIZE, which track where things are
+/// happening, so that ticks can skip the rest.
 pub(crate) struct ZLevel {
-    tiles: Box<[Tile; MAP_SIZE * MAP_SIZE]>,
+    width: usize,
+    height: usize,
+    tiles: Box<[Tile]>,
+    /// Chunks that need to be simulated next tick, because something in them changed.
+    active_chunks: Box<[bool]>,
+    /// Chunks that may differ from this Z level in the other buffer.
+    dirty_chunks: Box<[bool]>,
     pub(crate) active_pressure_chunks: HashSet<(u8, u8)>,
     pub(crate) frozen: bool,
 }
 
+/// Which chunk the tile at `index` is in, for a Z level `height` tiles tall.
+fn chunk_of(index: usize, height: usize) -> usize {
+    (index / height / ACTIVE_CHUNK_SIZE) * height.div_ceil(ACTIVE_CHUNK_SIZE)
+        + index % height / ACTIVE_CHUNK_SIZE
+}
+
 impl ZLevel {
-    pub(crate) fn new() -> Self {
-        let mut unbuilt: Vec<Tile> = Vec::with_capacity(MAP_SIZE * MAP_SIZE);
-        for _ in 0..MAP_SIZE * MAP_SIZE {
+    /// Creates a Z level full of space. A 0x0 level is a placeholder that costs nothing.
+    /// Every chunk starts out active.
+    pub(crate) fn new(width: usize, height: usize) -> Self {
+        let mut unbuilt: Vec<Tile> = Vec::with_capacity(width * height);
+        for _ in 0..width * height {
             unbuilt.push(Tile::new());
         }
+        let chunk_count = width.div_ceil(ACTIVE_CHUNK_SIZE) * height.div_ceil(ACTIVE_CHUNK_SIZE);
         ZLevel {
-            tiles: unbuilt.into_boxed_slice().try_into().unwrap(),
+            width,
+            height,
+            tiles: unbuilt.into_boxed_slice(),
+            active_chunks: vec![true; chunk_count].into_boxed_slice(),
+            dirty_chunks: vec![true; chunk_count].into_boxed_slice(),
             active_pressure_chunks: HashSet::new(),
-			frozen: false,
+            frozen: false,
         }
     }
 
-    pub(crate) fn maybe_get_index(x: i32, y: i32) -> Option<usize> {
-        if x < 0 || x >= MAP_SIZE as i32 || y < 0 || y >= MAP_SIZE as i32 {
+    /// How many tiles wide this Z level is.
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    /// How many tiles tall this Z level is.
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    /// How many tiles this Z level has.
+    pub(crate) fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub(crate) fn maybe_get_index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
             None
         } else {
-            Some(x as usize * MAP_SIZE + y as usize)
+            Some(x as usize * self.height + y as usize)
         }
     }
 
+    /// The coordinates of the tile at `index`.
+    pub(crate) fn get_coords(&self, index: usize) -> (i32, i32) {
+        ((index / self.height) as i32, (index % self.height) as i32)
+    }
+
     pub(crate) fn get_tile(&self, index: usize) -> &Tile {
         &self.tiles[index]
     }
 
     #[allow(dead_code)]
     pub(crate) fn maybe_get_tile(&self, x: i32, y: i32) -> Option<&Tile> {
-        Some(&self.tiles[ZLevel::maybe_get_index(x, y)?])
+        Some(&self.tiles[self.maybe_get_index(x, y)?])
     }
 
+    /// Fetches a tile for the simulation to change.
+    /// Anything else should use edit_tile(), or the change may be ignored.
     pub(crate) fn get_tile_mut(&mut self, index: usize) -> &mut Tile {
         &mut self.tiles[index]
     }
 
-    pub(crate) fn get_pair_mut(&mut self, index1: usize, index2: usize) -> (&mut Tile, &mut Tile) {
-        // Split borrow to get two mutable tiles at the same time.
-        // Ref: https://doc.rust-lang.org/nomicon/borrow-splitting.html
-        let ptr = self.tiles.as_mut_ptr();
-        unsafe {
-            assert!(index1 != index2);
+    /// Fetches a tile to change from outside the simulation, waking up its chunk.
+    pub(crate) fn edit_tile(&mut self, index: usize) -> &mut Tile {
+        self.wake(index);
+        &mut self.tiles[index]
+    }
+
+    /// Marks the chunk containing `index` as changed, and needing to be simulated next tick.
+    pub(crate) fn wake(&mut self, index: usize) {
+        let chunk = chunk_of(index, self.height);
+        self.active_chunks[chunk] = true;
+        self.dirty_chunks[chunk] = true;
+    }
+
+    /// Marks every chunk as changed, and needing to be simulated next tick.
+    pub(crate) fn wake_all(&mut self) {
+        self.active_chunks.fill(true);
+        self.dirty_chunks.fill(true);
+    }
+
+    /// Wakes every tile exposed to one of the given environments, so they pick up its changes.
+    pub(crate) fn wake_exposed_to(&mut self, environment_ids: &[u16]) {
+        for index in 0..self.tiles.len() {
+            if let AtmosMode::ExposedTo { environment_id } = self.tiles[index].mode {
+                if environment_ids.contains(&environment_id) {
+                    self.wake(index);
+                }
+            }
+        }
+    }
+
+    /// Marks the chunk containing `index` as changed, without waking it up.
+    pub(crate) fn mark_dirty(&mut self, index: usize) {
+        self.dirty_chunks[chunk_of(index, self.height)] = true;
+    }
+
+    /// Whether the chunk containing `index` may differ from the other buffer.
+    pub(crate) fn is_dirty(&self, index: usize) -> bool {
+        self.dirty_chunks[chunk_of(index, self.height)]
+    }
+
+    /// Hashes everything about the tiles that carries over from one tick to the next.
+    /// Two Z levels with the same hash almost certainly hold exactly the same air.
+    /// The hash doesn't depend on the Rust version or the run, so it can be compared across
+    /// builds.
+    pub(crate) fn state_hash(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        (self.width as u64).hash(&mut hasher);
+        (self.height as u64).hash(&mut hasher);
+        for tile in self.tiles.iter() {
+            tile.airtight_directions.hash(&mut hasher);
+            match tile.mode {
+                AtmosMode::Space => (0u8, 0u16),
+                AtmosMode::Sealed => (1, 0),
+                AtmosMode::ExposedTo { environment_id } => (2, environment_id),
+                AtmosMode::NoDecay => (3, 0),
+            }
+            .hash(&mut hasher);
+            for value in tile.gases.values.iter().chain([
+                &tile.thermal_energy,
+                &tile.superconductivity.north,
+                &tile.superconductivity.east,
+                &tile.superconductivity.south,
+                &tile.superconductivity.west,
+                &tile.innate_heat_capacity,
+                &tile.innate_thermal_energy,
+                &tile.hotspot_temperature,
+                &tile.hotspot_volume,
+                &tile.wind[0],
+                &tile.wind[1],
+                &tile.fuel_burnt,
+                &tile.solid_fuel,
+            ]) {
+                value.to_bits().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Every tile in a chunk, column by column.
+    fn chunk_tiles(&self, chunk: usize) -> impl Iterator<Item = usize> {
+        let height = self.height;
+        let chunks_high = height.div_ceil(ACTIVE_CHUNK_SIZE);
+        let min_x = chunk / chunks_high * ACTIVE_CHUNK_SIZE;
+        let min_y = chunk % chunks_high * ACTIVE_CHUNK_SIZE;
+        let max_x = (min_x + ACTIVE_CHUNK_SIZE).min(self.width);
+        let max_y = (min_y + ACTIVE_CHUNK_SIZE).min(height);
+        (min_x..max_x).flat_map(move |x| (min_y..max_y).map(move |y| x * height + y))
+    }
+
+    /// Works out what needs to be simulated this tick: the active chunks, plus the chunks around
+    /// them, so that changes can spread.
+    pub(crate) fn active_region(&self) -> ActiveRegion {
+        let chunks_wide = self.width.div_ceil(ACTIVE_CHUNK_SIZE) as i32;
+        let chunks_high = self.height.div_ceil(ACTIVE_CHUNK_SIZE) as i32;
+        let mut chunks = vec![false; self.active_chunks.len()].into_boxed_slice();
+        for chunk in 0..self.active_chunks.len() {
+            if !self.active_chunks[chunk] {
+                continue;
+            }
+            let chunk_x = chunk as i32 / chunks_high;
+            let chunk_y = chunk as i32 % chunks_high;
+            for x in (chunk_x - 1).max(0)..(chunk_x + 2).min(chunks_wide) {
+                for y in (chunk_y - 1).max(0)..(chunk_y + 2).min(chunks_high) {
+                    chunks[(x * chunks_high + y) as usize] = true;
+                }
+            }
+        }
+
+        let mut tiles = Vec::new();
+        for chunk in 0..chunks.len() {
+            if chunks[chunk] {
+                tiles.extend(self.chunk_tiles(chunk));
+            }
+        }
+        ActiveRegion {
+            height: self.height,
+            chunks,
+            tiles,
+        }
+    }
+
+    /// Prepares for simulating `region` into this Z level.
+    /// Everything in the region is about to change, and nothing is active until the simulation
+    /// says otherwise.
+    pub(crate) fn mark_simulated(&mut self, region: &ActiveRegion) {
+        self.active_chunks.fill(false);
+        for (dirty, simulated) in self.dirty_chunks.iter_mut().zip(region.chunks.iter()) {
+            *dirty |= *simulated;
+        }
+    }
 
-            (
-                ptr.add(index1).as_mut().unwrap(),
-                ptr.add(index2).as_mut().unwrap(),
-            )
+    /// Lets the simulation work on the whole Z level through a ZLevelPart.
+    pub(crate) fn as_part(&mut self) -> ZLevelPart<'_> {
+        let tile_count = self.tiles.len();
+        ZLevelPart {
+            width: self.width,
+            height: self.height,
+            tiles: self.tiles.as_mut_ptr(),
+            active_chunks: self.active_chunks.as_mut_ptr(),
+            dirty_chunks: self.dirty_chunks.as_mut_ptr(),
+            owned: 0..tile_count,
+            reach: 0..tile_count,
+            z_level: PhantomData,
         }
     }
 
+    /// Splits the Z level into strips STRIP_WIDTH columns wide, and sorts `tiles` into them,
+    /// keeping their order.
+    pub(crate) fn split_into_strips(&self, tiles: &[usize]) -> Vec<Strip> {
+        let mut strips: Vec<Strip> = (0..self.width.div_ceil(STRIP_WIDTH))
+            .map(|strip| Strip {
+                min_x: strip * STRIP_WIDTH,
+                max_x: ((strip + 1) * STRIP_WIDTH).min(self.width),
+                tiles: Vec::new(),
+            })
+            .collect();
+        for &index in tiles {
+            strips[index / self.height / STRIP_WIDTH].tiles.push(index);
+        }
+        strips
+    }
+
+    /// Runs `f` on every strip, spread across the worker pool, and returns what it gave back for
+    /// each one, in order.
+    /// Each strip gets a part of the Z level that reaches one column past it on either side, so
+    /// neighbouring strips never run at the same time: the even strips all go first, then the odd
+    /// ones. That makes the results the same no matter how many threads there are.
+    pub(crate) fn for_each_strip<F, R>(&mut self, strips: &[Strip], f: F) -> Vec<R>
+    where
+        F: Fn(&mut ZLevelPart, &Strip) -> R + Sync,
+        R: Send,
+    {
+        let width = self.width;
+        assert!(
+            strips.len() == width.div_ceil(STRIP_WIDTH)
+                && strips.iter().enumerate().all(|(number, strip)| {
+                    strip.min_x == number * STRIP_WIDTH
+                        && strip.max_x == ((number + 1) * STRIP_WIDTH).min(width)
+                }),
+            "Strips must come from split_into_strips()"
+        );
+        let whole = self.as_part();
+        let mut results: Vec<Option<R>> = strips.iter().map(|_| None).collect();
+        for first in [0, 1] {
+            let parts: Vec<(usize, ZLevelPart)> = (first..strips.len())
+                .step_by(2)
+                .map(|number| {
+                    let strip = &strips[number];
+                    // SAFETY: Strips are at least two columns wide, so every other strip is far
+                    // enough apart that their parts can't reach the same columns.
+                    (number, unsafe { whole.columns(strip.min_x, strip.max_x) })
+                })
+                .collect();
+            let finished: Vec<(usize, R)> = parts
+                .into_par_iter()
+                .map(|(number, mut part)| (number, f(&mut part, &strips[number])))
+                .collect();
+            for (number, result) in finished {
+                results[number] = Some(result);
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Makes this Z level match `other`, copying only the chunks that may differ.
+    /// Active chunks carry over, but nothing is dirty afterward, since the two now match.
     pub(crate) fn copy_from(&mut self, other: &ZLevel) {
-        for i in 0..self.tiles.len() {
-            self.tiles[i].copy_from(&other.tiles[i]);
+        if self.width != other.width || self.height != other.height {
+            self.width = other.width;
+            self.height = other.height;
+            self.tiles = other.tiles.clone();
+            self.active_chunks = other.active_chunks.clone();
+            self.dirty_chunks = other.dirty_chunks.clone();
+        } else {
+            for chunk in 0..other.dirty_chunks.len() {
+                if !other.dirty_chunks[chunk] {
+                    continue;
+                }
+                for index in other.chunk_tiles(chunk) {
+                    self.tiles[index].copy_from(&other.tiles[index]);
+                }
+            }
+            self.active_chunks.copy_from_slice(&other.active_chunks);
         }
+        self.dirty_chunks.fill(false);
         self.active_pressure_chunks = other.active_pressure_chunks.clone();
-		self.frozen = other.frozen;
+        self.frozen = other.frozen;
+    }
+}
+
+/// The part of a Z level that gets simulated during a tick.
+pub(crate) struct ActiveRegion {
+    height: usize,
+    chunks: Box<[bool]>,
+    /// Every tile in the region, chunk by chunk.
+    pub(crate) tiles: Vec<usize>,
+}
+
+impl ActiveRegion {
+    /// Whether the tile at `index` is being simulated.
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.chunks[chunk_of(index, self.height)]
+    }
+
+    /// How many chunks are being simulated.
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| **chunk).count()
+    }
+}
+
+// Strips have to leave a column between the ones that run at the same time, and can't share chunks.
+const _: () = assert!(STRIP_WIDTH >= 2 && STRIP_WIDTH.is_multiple_of(ACTIVE_CHUNK_SIZE));
+
+/// A strip of whole columns of a Z level, and the tiles in it that need work.
+/// See ZLevel::for_each_strip().
+pub(crate) struct Strip {
+    /// The first column in the strip.
+    pub(crate) min_x: usize,
+    /// The column just past the end of the strip.
+    pub(crate) max_x: usize,
+    /// The tiles to work on.
+    pub(crate) tiles: Vec<usize>,
+}
+
+/// Part of a Z level, which one thread can simulate while others work on the rest of it.
+/// A part owns a range of columns, and can also reach the column either side of them, since
+/// tiles look at (and sometimes change) their neighbours. It can only wake the chunks it owns.
+pub(crate) struct ZLevelPart<'a> {
+    width: usize,
+    height: usize,
+    tiles: *mut Tile,
+    active_chunks: *mut bool,
+    dirty_chunks: *mut bool,
+    /// The indexes of the tiles in the columns this part owns.
+    owned: Range<usize>,
+    /// The indexes of the tiles this part can read and change.
+    reach: Range<usize>,
+    z_level: PhantomData<&'a mut ZLevel>,
+}
+
+// SAFETY: Parts only reach tiles that no other part in use can, see ZLevelPart::columns().
+unsafe impl Send for ZLevelPart<'_> {}
+
+impl<'a> ZLevelPart<'a> {
+    /// A part that owns columns `min_x..max_x`.
+    ///
+    /// # Safety
+    /// Parts in use at the same time must not reach any of the same columns.
+    unsafe fn columns(&self, min_x: usize, max_x: usize) -> ZLevelPart<'a> {
+        let tile_count = self.width * self.height;
+        ZLevelPart {
+            width: self.width,
+            height: self.height,
+            tiles: self.tiles,
+            active_chunks: self.active_chunks,
+            dirty_chunks: self.dirty_chunks,
+            owned: min_x * self.height..max_x * self.height,
+            reach: min_x.saturating_sub(1) * self.height
+                ..((max_x + 1) * self.height).min(tile_count),
+            z_level: PhantomData,
+        }
+    }
+
+    /// Same as ZLevel::maybe_get_index(), for the whole Z level.
+    pub(crate) fn maybe_get_index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
+            None
+        } else {
+            Some(x as usize * self.height + y as usize)
+        }
+    }
+
+    /// The coordinates of the tile at `index`.
+    pub(crate) fn get_coords(&self, index: usize) -> (i32, i32) {
+        ((index / self.height) as i32, (index % self.height) as i32)
+    }
+
+    pub(crate) fn get_tile(&self, index: usize) -> &Tile {
+        assert!(
+            self.reach.contains(&index),
+            "Tile {} is out of reach",
+            index
+        );
+        // SAFETY: Nothing else can reach this tile while we're using it.
+        unsafe { &*self.tiles.add(index) }
+    }
+
+    pub(crate) fn get_tile_mut(&mut self, index: usize) -> &mut Tile {
+        assert!(
+            self.reach.contains(&index),
+            "Tile {} is out of reach",
+            index
+        );
+        // SAFETY: Nothing else can reach this tile while we're using it.
+        unsafe { &mut *self.tiles.add(index) }
+    }
+
+    pub(crate) fn get_pair_mut(&mut self, index1: usize, index2: usize) -> (&mut Tile, &mut Tile) {
+        assert!(index1 != index2);
+        assert!(
+            self.reach.contains(&index1),
+            "Tile {} is out of reach",
+            index1
+        );
+        assert!(
+            self.reach.contains(&index2),
+            "Tile {} is out of reach",
+            index2
+        );
+        // Split borrow to get two mutable tiles at the same time.
+        // Ref: https://doc.rust-lang.org/nomicon/borrow-splitting.html
+        // SAFETY: Nothing else can reach these tiles while we're using them, and they're different
+        // tiles.
+        unsafe { (&mut *self.tiles.add(index1), &mut *self.tiles.add(index2)) }
+    }
+
+    /// Same as ZLevel::wake(), for a tile this part owns.
+    pub(crate) fn wake(&mut self, index: usize) {
+        assert!(self.owned.contains(&index), "Tile {} isn't owned", index);
+        let chunk = chunk_of(index, self.height);
+        // SAFETY: Chunks don't cross strips, so nothing else can own a tile in this chunk.
+        unsafe {
+            *self.active_chunks.add(chunk) = true;
+            *self.dirty_chunks.add(chunk) = true;
+        }
     }
 }
 
@@ -476,6 +1050,7 @@ impl Model {
 ///
 /// During tick(), things change:
 /// * The active buffer is now read-only, as it represents the values from the previous tick.
+///   BYOND's writes wait in the write queue until the flip.
 /// * The inactive buffer is now available read-write, as it represents the values of the next tick,
 ///   the ones we're currently computing.
 /// Note that we do read some values from the inactive buffer here, as they are intermediate results
@@ -485,7 +1060,34 @@ pub(crate) struct Buffers {
     buffer_b: RwLock<Model>,
     /// The atomic boolean that's used to determine which buffer is active.
     flipper: AtomicBool,
-    pub(crate) environments: RwLock<Vec<Tile>>,
+    /// What ExposedTo tiles are held at. Not double-buffered, ticks move them along in place.
+    pub(crate) environments: RwLock<Environments>,
+    /// Pairs of (lower, upper) Z levels that are stacked and can exchange air, sorted.
+    pub(crate) z_connections: RwLock<Vec<(usize, usize)>>,
+    /// Pipes and gas machinery. These aren't double-buffered, ticks update them in place.
+    pub(crate) pipenets: RwLock<PipeNetworks>,
+    /// Things that add or remove gas every tick.
+    pub(crate) emitters: RwLock<GasEmitters>,
+    /// Things that heat or cool a tile every tick.
+    pub(crate) heaters: RwLock<Heaters>,
+    /// Thresholds BYOND wants to be told about when tiles cross them.
+    pub(crate) watch_rules: RwLock<WatchRules>,
+    /// Whether ticks must give exactly the same results every time they're given the same
+    /// model. Costs some speed, so it's off unless someone needs it.
+    pub(crate) deterministic: AtomicBool,
+    /// Whether ticks should audit where their gas and heat go. Slows ticks down a lot.
+    pub(crate) audit: AtomicBool,
+    /// How many threads ticks run on. 0 means one for each CPU core.
+    thread_count: AtomicUsize,
+    /// The threads ticks run on, once they've been started.
+    /// Kept between ticks, and replaced when thread_count changes.
+    worker_pool: Mutex<Option<Arc<ThreadPool>>>,
+    /// Which tiles share air with each other. Not double-buffered, as it only depends on things
+    /// BYOND sets.
+    pub(crate) zones: RwLock<Zones>,
+    /// Writes BYOND made while a tick was running. Also decides whether a tick is running, so
+    /// it must be locked before either buffer.
+    pub(crate) write_queue: Mutex<WriteQueue>,
 }
 
 /// Readability constant for flipper's value.
@@ -497,20 +1099,53 @@ impl Buffers {
             buffer_a: RwLock::new(Model::new()),
             buffer_b: RwLock::new(Model::new()),
             flipper: AtomicBool::new(true),
-            environments: RwLock::new(Vec::new()),
+            environments: RwLock::new(Environments::default()),
+            z_connections: RwLock::new(Vec::new()),
+            pipenets: RwLock::new(PipeNetworks::default()),
+            emitters: RwLock::new(GasEmitters::default()),
+            heaters: RwLock::new(Heaters::default()),
+            watch_rules: RwLock::new(WatchRules::default()),
+            deterministic: AtomicBool::new(false),
+            audit: AtomicBool::new(false),
+            thread_count: AtomicUsize::new(0),
+            worker_pool: Mutex::new(None),
+            zones: RwLock::new(Zones::default()),
+            write_queue: Mutex::new(WriteQueue::default()),
         }
     }
 
-    /// Ensures that all buffers up to ZLevel `z` exist.
-    pub(crate) fn init_to(&self, z: i32) {
+    /// Ensures that ZLevel `z` exists in all buffers, with the given size.
+    /// Any lower Z levels that don't exist yet are added as empty placeholders.
+    /// Resizing an existing Z level resets it to space.
+    pub(crate) fn init_z_level(&self, z: usize, width: usize, height: usize) -> eyre::Result<()> {
         let mut a_levels = self.buffer_a.write().unwrap();
-        while z >= a_levels.0.len() as i32 {
-            a_levels.0.push(RwLock::new(ZLevel::new()));
-        }
         let mut b_levels = self.buffer_b.write().unwrap();
-        while z >= b_levels.0.len() as i32 {
-            b_levels.0.push(RwLock::new(ZLevel::new()));
+        let other_tiles: usize = a_levels
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(other_z, _)| *other_z != z)
+            .map(|(_, level)| level.read().unwrap().tile_count())
+            .sum();
+        if other_tiles + width * height > MAX_TOTAL_TILES {
+            return Err(eyre::eyre!(
+                "Initializing Z level {} at {}x{} would exceed MAX_TOTAL_TILES.",
+                z + 1,
+                width,
+                height
+            ));
         }
+        for levels in [&mut a_levels.0, &mut b_levels.0] {
+            while z >= levels.len() {
+                levels.push(RwLock::new(ZLevel::new(0, 0)));
+            }
+            let level = levels[z].get_mut().unwrap();
+            if level.width != width || level.height != height {
+                *level = ZLevel::new(width, height);
+            }
+        }
+        self.zones.write().unwrap().level_mut(z).reset();
+        Ok(())
     }
 
     /// Fetches the active buffer map, which could be either buffer_a or buffer_b.
@@ -529,20 +1164,213 @@ impl Buffers {
         }
     }
 
-    /// Flips wether buffer_a or buffer_b is active.
+    /// Starts a tick. Until the next flip, writes are queued rather than applied.
+    pub(crate) fn begin_tick(&self) {
+        self.write_queue.lock().unwrap().ticking = true;
+    }
+
+    /// Flips wether buffer_a or buffer_b is active, then applies any writes that were queued
+    /// during the tick to the newly active buffer.
+    /// The caller must not be holding either buffer.
     pub(crate) fn flip(&self) {
+        let mut queue = self.write_queue.lock().unwrap();
         self.flipper
             .fetch_xor(true, std::sync::atomic::Ordering::Relaxed);
+        self.apply_writes(queue.finish_tick());
+    }
+
+    /// Swaps which buffer is active, without ending a tick or applying queued writes.
+    /// For replacing the whole model between ticks, like loading a snapshot. The caller must be
+    /// holding this Buffers' write queue, so a tick can't start, and must not be holding either
+    /// buffer.
+    pub(crate) fn swap(&self, queue: &MutexGuard<'_, WriteQueue>) {
+        // A guard for some other queue wouldn't stop anything, so make sure it's ours.
+        let own_queue = &self.write_queue as *const Mutex<WriteQueue> as usize;
+        let held_queue = &**queue as *const WriteQueue as usize;
+        assert!(
+            (own_queue..own_queue + std::mem::size_of::<Mutex<WriteQueue>>()).contains(&held_queue),
+            "Buffers::swap() needs this Buffers' own write queue."
+        );
+        self.flipper
+            .fetch_xor(true, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Ends a tick that failed without flipping, applying any queued writes to the buffer that
+    /// stayed active.
+    /// The caller must not be holding either buffer.
+    pub(crate) fn cancel_tick(&self) {
+        let mut queue = self.write_queue.lock().unwrap();
+        self.apply_writes(queue.finish_tick());
+    }
+
+    /// Applies `write` to Z level `z` of the active buffer, or queues it if a tick is running.
+    pub(crate) fn write_z_level(&self, z: usize, write: ZLevelWrite) {
+        let mut queue = self.write_queue.lock().unwrap();
+        if queue.ticking {
+            queue.writes.push((z, write));
+        } else {
+            // Keep holding the queue, so a tick can't start halfway through.
+            self.apply_writes(vec![(z, write)]);
+        }
+    }
+
+    /// Applies `write` to the tile at (x, y, z) of the active buffer, or queues it if a tick is
+    /// running. Bad coordinates are caught straight away, rather than when the write is applied.
+    pub(crate) fn write_tile(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        write: impl FnOnce(&mut Tile) + Send + 'static,
+    ) -> eyre::Result<()> {
+        self.write_tiles(vec![((x, y, z), write)])
+    }
+
+    /// Like write_tile, but for many tiles at once, with one write per Z level.
+    /// If any coordinates are bad, nothing is written.
+    pub(crate) fn write_tiles<F: FnOnce(&mut Tile) + Send + 'static>(
+        &self,
+        writes: Vec<((i32, i32, i32), F)>,
+    ) -> eyre::Result<()> {
+        let mut by_z: BTreeMap<usize, Vec<(usize, F)>> = BTreeMap::new();
+        {
+            let active = self.get_active().read().unwrap();
+            for ((x, y, z), write) in writes {
+                let z_level = active
+                    .0
+                    .get(z as usize)
+                    .ok_or(eyre::eyre!("Z level {} not initialized.", z + 1))?
+                    .read()
+                    .unwrap();
+                let index = z_level.maybe_get_index(x, y).ok_or(eyre::eyre!(
+                    "Bad coordinates ({}, {}, {})",
+                    x + 1,
+                    y + 1,
+                    z + 1
+                ))?;
+                by_z.entry(z as usize).or_default().push((index, write));
+            }
+        }
+        for (z, tile_writes) in by_z {
+            self.write_z_level(
+                z,
+                Box::new(move |z_level| {
+                    let mut changed_shape = Vec::new();
+                    for (index, write) in tile_writes {
+                        let tile = z_level.edit_tile(index);
+                        let old_shape = (tile.airtight_directions, tile.mode == AtmosMode::Space);
+                        write(tile);
+                        // Zones only care about walls and space.
+                        if (tile.airtight_directions, tile.mode == AtmosMode::Space) != old_shape {
+                            changed_shape.push(index);
+                        }
+                    }
+                    changed_shape
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Applies writes to the active buffer, in order.
+    fn apply_writes(&self, writes: Vec<(usize, ZLevelWrite)>) {
+        if writes.is_empty() {
+            return;
+        }
+        let active = self.get_active().read().unwrap();
+        for (z, write) in writes {
+            let Some(level) = active.0.get(z) else {
+                continue;
+            };
+            let changed_shape = write(&mut level.write().unwrap());
+            if !changed_shape.is_empty() {
+                let mut zones = self.zones.write().unwrap();
+                for index in changed_shape {
+                    zones.level_mut(z).mark_changed(index);
+                }
+            }
+        }
+    }
+
+    /// Connects or disconnects two Z levels, with `upper` directly above `lower`.
+    /// Each Z level can have at most one level above it and one below it.
+    pub(crate) fn set_z_connected(
+        &self,
+        lower: usize,
+        upper: usize,
+        connected: bool,
+    ) -> eyre::Result<()> {
+        if lower == upper {
+            return Err(eyre::eyre!(
+                "Can't connect Z level {} to itself.",
+                lower + 1
+            ));
+        }
+        let mut z_connections = self.z_connections.write().unwrap();
+        if !connected {
+            z_connections.retain(|&pair| pair != (lower, upper));
+            return Ok(());
+        }
+        if z_connections.contains(&(lower, upper)) {
+            return Ok(());
+        }
+        for (other_lower, other_upper) in z_connections.iter() {
+            if *other_lower == lower || *other_upper == upper {
+                return Err(eyre::eyre!(
+                    "Z level {} already has a level above or Z level {} already has a level below.",
+                    lower + 1,
+                    upper + 1
+                ));
+            }
+        }
+        // Air can start moving anywhere on either level, so wake them both up.
+        for z in [lower, upper] {
+            self.write_z_level(
+                z,
+                Box::new(|z_level| {
+                    z_level.wake_all();
+                    Vec::new()
+                }),
+            );
+        }
+        z_connections.push((lower, upper));
+        z_connections.sort();
+        Ok(())
     }
 
     /// Create an environment for ExposedTo.
-    pub(crate) fn create_environment(&self, mut tile: Tile) -> u8 {
-        let mut environments = self.environments.write().unwrap();
-        let id = environments.len() as u8;
-        tile.mode = AtmosMode::ExposedTo { environment_id: id };
-        tile.gases.recalculate();
-        environments.push(tile);
-        id
+    pub(crate) fn create_environment(&self, tile: Tile) -> eyre::Result<u16> {
+        self.environments.write().unwrap().create(tile)
+    }
+
+    /// Sets how many threads ticks run on, from the next tick onward. 0 means one for each CPU
+    /// core.
+    pub(crate) fn set_thread_count(&self, count: usize) {
+        self.thread_count.store(count, Relaxed);
+        // The next tick starts a new pool. A tick that's already running finishes on the old one.
+        *self.worker_pool.lock().unwrap() = None;
+    }
+
+    /// The threads that ticks run on, starting them if needed.
+    /// They stick around between ticks, until the thread count changes.
+    pub(crate) fn worker_pool(&self) -> eyre::Result<Arc<ThreadPool>> {
+        let mut worker_pool = self.worker_pool.lock().unwrap();
+        if let Some(pool) = worker_pool.as_ref() {
+            return Ok(pool.clone());
+        }
+        let pool = Arc::new(
+            ThreadPoolBuilder::new()
+                .num_threads(self.thread_count.load(Relaxed))
+                .thread_name(|index| format!("milla-worker-{}", index))
+                .start_handler(|_| {
+                    // Running at low priority is best-effort. Panicking here would abort the
+                    // whole server.
+                    let _ = thread_priority::ThreadPriority::Min.set_for_current();
+                })
+                .build()?,
+        );
+        *worker_pool = Some(pool.clone());
+        Ok(pool)
     }
 }
 
@@ -555,13 +1383,18 @@ mod tests {
     #[test]
     fn initial_zero() {
         let buffers = Buffers::new();
-        buffers.init_to(0);
+        buffers
+            .init_z_level(0, DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE)
+            .unwrap();
 
         let active = buffers.get_active().read().unwrap();
         let z_level = active.0[0].read().unwrap();
-        let tile = z_level.get_tile(ZLevel::maybe_get_index(0, 0).unwrap());
-        assert_eq!(tile.airtight_directions, AirtightDirections::empty());
-        for i in 0..GAS_COUNT {
+        let tile = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
+        assert_eq!(
+            tile.airtight_directions,
+            AirtightDirections::UP | AirtightDirections::DOWN
+        );
+        for i in 0..MAX_GAS_COUNT {
             assert_eq!(tile.gases.values[i], 0.0, "{}", i);
         }
         assert_eq!(tile.thermal_energy, 0.0);
@@ -578,13 +1411,16 @@ mod tests {
     #[test]
     fn flip_works() {
         let buffers = Buffers::new();
-        buffers.init_to(0);
+        buffers
+            .init_z_level(0, DEFAULT_MAP_SIZE, DEFAULT_MAP_SIZE)
+            .unwrap();
 
         // Write some arbitrary data to the first buffer.
         {
             let active = buffers.get_active().read().unwrap();
             let mut z_level = active.0[0].write().unwrap();
-            let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(0, 0).unwrap());
+            let index = z_level.maybe_get_index(0, 0).unwrap();
+            let tile = z_level.get_tile_mut(index);
             tile.gases.values[0] = 1.0;
         }
 
@@ -594,7 +1430,7 @@ mod tests {
         {
             let active = buffers.get_active().read().unwrap();
             let z_level = active.0[0].write().unwrap();
-            let tile = z_level.get_tile(ZLevel::maybe_get_index(0, 0).unwrap());
+            let tile = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
             assert_eq!(tile.gases.values[0], 0.0);
         }
 
@@ -602,7 +1438,8 @@ mod tests {
         {
             let active = buffers.get_active().read().unwrap();
             let mut z_level = active.0[0].write().unwrap();
-            let tile = z_level.get_tile_mut(ZLevel::maybe_get_index(0, 0).unwrap());
+            let index = z_level.maybe_get_index(0, 0).unwrap();
+            let tile = z_level.get_tile_mut(index);
             tile.gases.values[0] = 2.0;
         }
 
@@ -612,7 +1449,7 @@ mod tests {
         {
             let active = buffers.get_active().read().unwrap();
             let z_level = active.0[0].write().unwrap();
-            let tile = z_level.get_tile(ZLevel::maybe_get_index(0, 0).unwrap());
+            let tile = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
             assert_eq!(tile.gases.values[0], 1.0);
         }
 
@@ -622,8 +1459,83 @@ mod tests {
         {
             let active = buffers.get_active().read().unwrap();
             let z_level = active.0[0].write().unwrap();
-            let tile = z_level.get_tile(ZLevel::maybe_get_index(0, 0).unwrap());
+            let tile = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
             assert_eq!(tile.gases.values[0], 2.0);
         }
     }
+
+    // Each Z level should have at most one level above and below it.
+    #[test]
+    fn z_connections() {
+        let buffers = Buffers::new();
+        buffers.set_z_connected(1, 2, true).unwrap();
+        buffers.set_z_connected(0, 1, true).unwrap();
+        assert_eq!(*buffers.z_connections.read().unwrap(), vec![(0, 1), (1, 2)]);
+        assert!(buffers.set_z_connected(1, 3, true).is_err());
+        assert!(buffers.set_z_connected(3, 2, true).is_err());
+        assert!(buffers.set_z_connected(3, 3, true).is_err());
+        buffers.set_z_connected(1, 2, false).unwrap();
+        buffers.set_z_connected(1, 3, true).unwrap();
+        assert_eq!(*buffers.z_connections.read().unwrap(), vec![(0, 1), (1, 3)]);
+    }
+
+    // Z levels should have their own sizes, with placeholders below them, within the tile budget.
+    #[test]
+    fn z_level_sizes() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(2, 10, 20).unwrap();
+        {
+            let active = buffers.get_active().read().unwrap();
+            assert_eq!(active.0.len(), 3);
+            assert_eq!(active.0[0].read().unwrap().tile_count(), 0);
+            let z_level = active.0[2].read().unwrap();
+            assert_eq!(z_level.tile_count(), 200);
+            assert_eq!(z_level.maybe_get_index(9, 19), Some(199));
+            assert_eq!(z_level.maybe_get_index(10, 0), None);
+            assert_eq!(z_level.maybe_get_index(0, 20), None);
+            assert_eq!(z_level.get_coords(199), (9, 19));
+        }
+        assert!(buffers.init_z_level(0, MAX_TOTAL_TILES, 1).is_err());
+    }
+
+    // Swapping should only work while holding this Buffers' own write queue.
+    #[test]
+    fn swap_needs_own_queue() {
+        let buffers = Buffers::new();
+        let other = Buffers::new();
+        let before = buffers.get_active() as *const RwLock<Model>;
+        {
+            let queue = other.write_queue.lock().unwrap();
+            let swapped =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| buffers.swap(&queue)));
+            assert!(swapped.is_err());
+        }
+        assert_eq!(buffers.get_active() as *const RwLock<Model>, before);
+        let queue = buffers.write_queue.lock().unwrap();
+        buffers.swap(&queue);
+        assert_ne!(buffers.get_active() as *const RwLock<Model>, before);
+    }
+
+    // State hashes should use plain FNV-1a, so they match across runs and builds.
+    #[test]
+    fn fixed_state_hash() {
+        let mut hasher = FnvHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 3, 2).unwrap();
+        let active = buffers.get_active().read().unwrap();
+        let hash = active.0[0].read().unwrap().state_hash();
+        assert_eq!(hash, active.0[0].read().unwrap().state_hash());
+        let other = Buffers::new();
+        other.init_z_level(0, 2, 3).unwrap();
+        assert_ne!(
+            hash,
+            other.get_active().read().unwrap().0[0]
+                .read()
+                .unwrap()
+                .state_hash()
+        );
+    }
 }
@@ -0,0 +1,493 @@
+use crate::milla::constants::*;
+use crate::milla::model::*;
+use crate::milla::simulate;
+use crate::milla::statics::gas_registry;
+use eyre::eyre;
+use eyre::Result;
+
+/// A connected set of pipes, holding a single, evenly mixed body of gas.
+#[derive(Debug, Clone)]
+pub(crate) struct PipeNet {
+    /// How much gas the pipes hold, in liters.
+    pub(crate) volume: f32,
+    pub(crate) gases: GasSet,
+    pub(crate) thermal_energy: f32,
+}
+
+impl PipeNet {
+    pub(crate) fn new(volume: f32) -> Self {
+        PipeNet {
+            volume,
+            gases: GasSet::new(),
+            thermal_energy: 0.0,
+        }
+    }
+    /// The temperature of the gas in the pipes, in kelvin.
+    pub(crate) fn temperature(&self) -> f32 {
+        let heat_capacity = self.gases.heat_capacity();
+        if heat_capacity <= 0.0 {
+            0.0
+        } else {
+            self.thermal_energy / heat_capacity
+        }
+    }
+    /// The pressure of the gas in the pipes, in kPa.
+    pub(crate) fn pressure(&self) -> f32 {
+        if self.volume <= 0.0 {
+            return 0.0;
+        }
+        self.gases.moles()
+            * self.temperature().max(MINIMUM_TEMPERATURE_FOR_PRESSURE)
+            * R_IDEAL_GAS_EQUATION
+            / self.volume
+    }
+}
+
+/// A piece of gas machinery. Coordinates are 0-indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Device {
+    /// Lets a pipenet and the tile it's on equalize.
+    Vent { net: usize, x: i32, y: i32, z: i32 },
+    /// Pulls some gases out of the tile it's on and into a pipenet.
+    Scrubber {
+        net: usize,
+        x: i32,
+        y: i32,
+        z: i32,
+        /// Which gases to remove, by gas index.
+        gases: Vec<usize>,
+    },
+    /// Moves gas from one pipenet to another, until the output reaches the target pressure.
+    Pump {
+        input: usize,
+        output: usize,
+        target_pressure: f32,
+    },
+    /// Lets two pipenets equalize, like a canister on a connector port.
+    Connector { first: usize, second: usize },
+}
+
+impl Device {
+    /// The pipenets this device is attached to.
+    fn nets(&self) -> Vec<usize> {
+        match self {
+            Device::Vent { net, .. } => vec![*net],
+            Device::Scrubber { net, .. } => vec![*net],
+            Device::Pump { input, output, .. } => vec![*input, *output],
+            Device::Connector { first, second } => vec![*first, *second],
+        }
+    }
+}
+
+/// Every pipenet and device MILLA is simulating.
+/// IDs are positions in the lists, and are reused once removed.
+#[derive(Default)]
+pub(crate) struct PipeNetworks {
+    nets: Vec<Option<PipeNet>>,
+    devices: Vec<Option<Device>>,
+}
+
+impl PipeNetworks {
+    /// Creates an empty pipenet, and returns its ID.
+    pub(crate) fn create_net(&mut self, volume: f32) -> Result<usize> {
+        if !volume.is_finite() || volume <= 0.0 {
+            return Err(eyre!("Invalid pipenet volume {}", volume));
+        }
+        Ok(insert_into_free_slot(&mut self.nets, PipeNet::new(volume)))
+    }
+
+    /// Removes a pipenet, along with any devices attached to it.
+    pub(crate) fn remove_net(&mut self, id: usize) -> Result<()> {
+        self.get_net(id)?;
+        self.nets[id] = None;
+        for slot in self.devices.iter_mut() {
+            if let Some(device) = slot {
+                if device.nets().contains(&id) {
+                    *slot = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_net(&self, id: usize) -> Result<&PipeNet> {
+        self.nets
+            .get(id)
+            .and_then(Option::as_ref)
+            .ok_or(eyre!("No pipenet with ID {}", id))
+    }
+
+    pub(crate) fn get_net_mut(&mut self, id: usize) -> Result<&mut PipeNet> {
+        self.nets
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .ok_or(eyre!("No pipenet with ID {}", id))
+    }
+
+    /// Attaches a device to its pipenets, and returns its ID.
+    pub(crate) fn add_device(&mut self, device: Device) -> Result<usize> {
+        let nets = device.nets();
+        for net in &nets {
+            self.get_net(*net)?;
+        }
+        if nets.len() == 2 && nets[0] == nets[1] {
+            return Err(eyre!("Can't connect pipenet {} to itself.", nets[0]));
+        }
+        Ok(insert_into_free_slot(&mut self.devices, device))
+    }
+
+    pub(crate) fn get_device_mut(&mut self, id: usize) -> Result<&mut Device> {
+        self.devices
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .ok_or(eyre!("No device with ID {}", id))
+    }
+
+    pub(crate) fn remove_device(&mut self, id: usize) -> Result<()> {
+        self.get_device_mut(id)?;
+        self.devices[id] = None;
+        Ok(())
+    }
+
+    /// Runs every device for one tick, in ID order, exchanging gas with the tiles in `next`.
+    pub(crate) fn process(&mut self, next: &Model) -> Vec<(usize, usize)> {
+        let all_gases: Vec<usize> = (0..gas_registry().count()).collect();
+        let mut touched = Vec::new();
+        let PipeNetworks { nets, devices } = self;
+        for device in devices.iter().flatten() {
+            match device {
+                Device::Vent { net, x, y, z } => {
+                    let Some(net) = nets[*net].as_mut() else {
+                        continue;
+                    };
+                    with_tile(next, *x, *y, *z, &mut touched, |tile| {
+                        equalize(
+                            &mut Mixture::of_net(net),
+                            &mut Mixture::of_tile(tile),
+                            &all_gases,
+                        );
+                    });
+                }
+                Device::Scrubber {
+                    net,
+                    x,
+                    y,
+                    z,
+                    gases,
+                } => {
+                    let Some(net) = nets[*net].as_mut() else {
+                        continue;
+                    };
+                    if net.pressure() >= SCRUBBER_MAX_PRESSURE {
+                        continue;
+                    }
+                    with_tile(next, *x, *y, *z, &mut touched, |tile| {
+                        let (removed, energy) = Mixture::of_tile(tile).remove(SCRUBBER_RATE, gases);
+                        Mixture::of_net(net).add(&removed, energy);
+                    });
+                }
+                Device::Pump {
+                    input,
+                    output,
+                    target_pressure,
+                } => {
+                    let Some((input, output)) = net_pair(nets, *input, *output) else {
+                        continue;
+                    };
+                    let output_pressure = output.pressure();
+                    let input_moles = input.gases.moles();
+                    if output_pressure >= *target_pressure || input_moles <= 0.0 {
+                        continue;
+                    }
+                    // How much gas would it take to bring the output up to the target, if it
+                    // arrives at the input's temperature?
+                    let wanted_moles = (*target_pressure - output_pressure) * output.volume
+                        / (R_IDEAL_GAS_EQUATION
+                            * input.temperature().max(MINIMUM_TEMPERATURE_FOR_PRESSURE));
+                    let (moved, energy) = Mixture::of_net(input)
+                        .remove((wanted_moles / input_moles).min(1.0), &all_gases);
+                    Mixture::of_net(output).add(&moved, energy);
+                }
+                Device::Connector { first, second } => {
+                    let Some((first, second)) = net_pair(nets, *first, *second) else {
+                        continue;
+                    };
+                    equalize(
+                        &mut Mixture::of_net(first),
+                        &mut Mixture::of_net(second),
+                        &all_gases,
+                    );
+                }
+            }
+        }
+        touched
+    }
+}
+
+/// Borrows two different pipenets at once.
+fn net_pair(
+    nets: &mut [Option<PipeNet>],
+    first: usize,
+    second: usize,
+) -> Option<(&mut PipeNet, &mut PipeNet)> {
+    if first == second || first.max(second) >= nets.len() {
+        return None;
+    }
+    let (low, high) = nets.split_at_mut(first.max(second));
+    let low_net = low[first.min(second)].as_mut()?;
+    let high_net = high[0].as_mut()?;
+    if first < second {
+        Some((low_net, high_net))
+    } else {
+        Some((high_net, low_net))
+    }
+}
+
+/// Lets a device change a tile in `next`, waking up its chunk if anything significant happened,
+/// and adds it to `touched` as (Z level, index) so it can be settled afterwards.
+/// Tiles that don't exist, or are on frozen Z levels, are left alone.
+fn with_tile<F>(next: &Model, x: i32, y: i32, z: i32, touched: &mut Vec<(usize, usize)>, f: F)
+where
+    F: FnOnce(&mut Tile),
+{
+    let Some(z_level_lock) = next.0.get(z as usize) else {
+        return;
+    };
+    let mut z_level = z_level_lock.write().unwrap();
+    if z_level.frozen {
+        return;
+    }
+    let Some(index) = z_level.maybe_get_index(x, y) else {
+        return;
+    };
+    touched.push((z as usize, index));
+    let before = z_level.get_tile(index).clone();
+    f(z_level.get_tile_mut(index));
+    if simulate::changed_significantly(&before, z_level.get_tile(index)) {
+        z_level.wake(index);
+    } else {
+        z_level.mark_dirty(index);
+    }
+}
+
+/// A body of gas that devices can move gas into and out of.
+struct Mixture<'a> {
+    gases: &'a mut GasSet,
+    thermal_energy: &'a mut f32,
+    /// In liters.
+    volume: f32,
+}
+
+impl<'a> Mixture<'a> {
+    fn of_net(net: &'a mut PipeNet) -> Self {
+        Mixture {
+            gases: &mut net.gases,
+            thermal_energy: &mut net.thermal_energy,
+            volume: net.volume,
+        }
+    }
+
+    fn of_tile(tile: &'a mut Tile) -> Self {
+        Mixture {
+            gases: &mut tile.gases,
+            thermal_energy: &mut tile.thermal_energy,
+            volume: TILE_VOLUME,
+        }
+    }
+
+    /// Removes a fraction of some gases, returning what was removed and the thermal energy it
+    /// carried.
+    fn remove(&mut self, fraction: f32, which: &[usize]) -> (GasSet, f32) {
+        let heat_capacity = self.gases.heat_capacity();
+        let temperature = if heat_capacity > 0.0 {
+            *self.thermal_energy / heat_capacity
+        } else {
+            0.0
+        };
+        let mut removed = GasSet::new();
+        for &gas in which {
+            let amount = self.gases.values[gas] * fraction;
+            removed.values[gas] = amount;
+            self.gases.values[gas] -= amount;
+        }
+        self.gases.set_dirty();
+        removed.set_dirty();
+        let energy = (removed.heat_capacity() * temperature).min(*self.thermal_energy);
+        *self.thermal_energy -= energy;
+        (removed, energy)
+    }
+
+    fn add(&mut self, gases: &GasSet, energy: f32) {
+        self.gases.add_gases(gases);
+        *self.thermal_energy += energy;
+    }
+}
+
+/// Mixes two bodies of gas evenly, in proportion to their volumes.
+fn equalize(first: &mut Mixture, second: &mut Mixture, all_gases: &[usize]) {
+    let total_volume = first.volume + second.volume;
+    let (from_first, first_energy) = first.remove(second.volume / total_volume, all_gases);
+    let (from_second, second_energy) = second.remove(first.volume / total_volume, all_gases);
+    first.add(&from_second, second_energy);
+    second.add(&from_first, first_energy);
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::tick;
+
+    fn filled_net(volume: f32, oxygen: f32, temperature: f32) -> PipeNet {
+        let mut net = PipeNet::new(volume);
+        net.gases.set(GAS_OXYGEN, oxygen);
+        net.thermal_energy = net.gases.heat_capacity() * temperature;
+        net
+    }
+
+    // IDs should be reused, and removing a pipenet should take its devices with it.
+    #[test]
+    fn ids_and_removal() {
+        let mut networks = PipeNetworks::default();
+        let first = networks.create_net(100.0).unwrap();
+        let second = networks.create_net(100.0).unwrap();
+        assert!(networks.create_net(0.0).is_err());
+        let connector = networks
+            .add_device(Device::Connector { first, second })
+            .unwrap();
+        assert!(networks
+            .add_device(Device::Connector {
+                first,
+                second: first
+            })
+            .is_err());
+        assert!(networks
+            .add_device(Device::Connector { first, second: 100 })
+            .is_err());
+
+        networks.remove_net(first).unwrap();
+        assert!(networks.get_device_mut(connector).is_err());
+        assert_eq!(networks.create_net(50.0).unwrap(), first);
+        assert!(networks.remove_net(100).is_err());
+    }
+
+    // Pumps should stop at their target, and connectors should mix by volume.
+    #[test]
+    fn pumps_and_connectors() {
+        let mut networks = PipeNetworks::default();
+        let input = networks.create_net(200.0).unwrap();
+        let output = networks.create_net(100.0).unwrap();
+        *networks.get_net_mut(input).unwrap() = filled_net(200.0, 100.0, T20C);
+        networks
+            .add_device(Device::Pump {
+                input,
+                output,
+                target_pressure: ONE_ATMOSPHERE,
+            })
+            .unwrap();
+        networks.process(&Model::new());
+        let pumped = networks.get_net(output).unwrap();
+        assert!((pumped.pressure() - ONE_ATMOSPHERE).abs() < 0.01);
+        assert!((pumped.temperature() - T20C).abs() < 0.01);
+        let total = pumped.gases.moles() + networks.get_net(input).unwrap().gases.moles();
+        assert!((total - 100.0).abs() < 0.001);
+
+        let mut networks = PipeNetworks::default();
+        let first = networks.create_net(300.0).unwrap();
+        let second = networks.create_net(100.0).unwrap();
+        *networks.get_net_mut(first).unwrap() = filled_net(300.0, 40.0, 300.0);
+        networks
+            .add_device(Device::Connector { first, second })
+            .unwrap();
+        networks.process(&Model::new());
+        assert!((networks.get_net(first).unwrap().gases.moles() - 30.0).abs() < 0.001);
+        assert!((networks.get_net(second).unwrap().gases.moles() - 10.0).abs() < 0.001);
+        assert!((networks.get_net(second).unwrap().temperature() - 300.0).abs() < 0.01);
+    }
+
+    // Devices should report the tiles they touched, so the tick can settle them, and skip tiles
+    // that don't exist.
+    #[test]
+    fn reports_touched_tiles() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 2, 1).unwrap();
+        let mut networks = PipeNetworks::default();
+        let net = networks.create_net(TILE_VOLUME).unwrap();
+        for x in [1, 5] {
+            networks
+                .add_device(Device::Vent { net, x, y: 0, z: 0 })
+                .unwrap();
+        }
+        let next = buffers.get_inactive().read().unwrap();
+        let index = next.0[0].read().unwrap().maybe_get_index(1, 0).unwrap();
+        assert_eq!(networks.process(&next), vec![(0, index)]);
+    }
+
+    // Vents and scrubbers should move gas in and out of tiles during a tick.
+    #[test]
+    fn vents_and_scrubbers() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 2, 1).unwrap();
+        {
+            let active = buffers.get_active().read().unwrap();
+            let mut z_level = active.0[0].write().unwrap();
+            for x in 0..2 {
+                let index = z_level.maybe_get_index(x, 0).unwrap();
+                let tile = z_level.edit_tile(index);
+                tile.mode = AtmosMode::Sealed;
+                tile.airtight_directions = AirtightDirections::all();
+                tile.gases.set(GAS_CARBON_DIOXIDE, 10.0);
+                tile.thermal_energy = tile.heat_capacity() * T20C;
+            }
+        }
+        let (vent_net, scrubber_net) = {
+            let mut networks = buffers.pipenets.write().unwrap();
+            let vent_net = networks.create_net(TILE_VOLUME).unwrap();
+            *networks.get_net_mut(vent_net).unwrap() = filled_net(TILE_VOLUME, 20.0, T20C);
+            networks
+                .add_device(Device::Vent {
+                    net: vent_net,
+                    x: 0,
+                    y: 0,
+                    z: 0,
+                })
+                .unwrap();
+            let scrubber_net = networks.create_net(TILE_VOLUME).unwrap();
+            networks
+                .add_device(Device::Scrubber {
+                    net: scrubber_net,
+                    x: 1,
+                    y: 0,
+                    z: 0,
+                    gases: vec![GAS_CARBON_DIOXIDE],
+                })
+                .unwrap();
+            (vent_net, scrubber_net)
+        };
+
+        tick::tick(&buffers).unwrap();
+
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        let vented = z_level.get_tile(z_level.maybe_get_index(0, 0).unwrap());
+        assert!((vented.gases.get(GAS_OXYGEN) - 10.0).abs() < 0.001);
+        assert!((vented.gases.get(GAS_CARBON_DIOXIDE) - 5.0).abs() < 0.001);
+        let scrubbed = z_level.get_tile(z_level.maybe_get_index(1, 0).unwrap());
+        assert!(
+            (scrubbed.gases.get(GAS_CARBON_DIOXIDE) - 10.0 * (1.0 - SCRUBBER_RATE)).abs() < 0.001
+        );
+
+        let networks = buffers.pipenets.read().unwrap();
+        assert!((networks.get_net(vent_net).unwrap().gases.get(GAS_OXYGEN) - 10.0).abs() < 0.001);
+        assert!(
+            (networks
+                .get_net(scrubber_net)
+                .unwrap()
+                .gases
+                .get(GAS_CARBON_DIOXIDE)
+                - 10.0 * SCRUBBER_RATE)
+                .abs()
+                < 0.001
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Writes BYOND makes while a tick is running.
+//!
+//! The active buffer is read-only while a tick runs. Rather than making BYOND wait for the tick,
+//! writes made then are queued, and applied in the order they were made as soon as the tick
+//! flips the buffers. Queued writes apply on top of whatever the tick did, so:
+//! * Absolute writes, like setting a tile's gases or temperature, replace what the tick did, and
+//!   any earlier write to the same values.
+//! * Relative writes, like adding gas or capping superconductivity, stack with the tick and with
+//!   each other.
+use crate::milla::model::*;
+
+/// A change to a single Z level, applied now or once the running tick finishes.
+/// Returns the indices of the tiles whose walls or space-ness it changed, so zones can catch up.
+pub(crate) type ZLevelWrite = Box<dyn FnOnce(&mut ZLevel) -> Vec<usize> + Send>;
+
+/// Writes waiting for the running tick to finish.
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    /// Whether a tick is running, so writes have to wait.
+    pub(crate) ticking: bool,
+    /// The queued writes, in the order they were made, along with their Z level.
+    pub(crate) writes: Vec<(usize, ZLevelWrite)>,
+    /// How many writes had to wait for the last tick.
+    pub(crate) last_tick_writes: usize,
+}
+
+impl WriteQueue {
+    /// How many writes are waiting right now.
+    pub(crate) fn depth(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Stops queueing, and hands back everything that was queued, oldest first.
+    pub(crate) fn finish_tick(&mut self) -> Vec<(usize, ZLevelWrite)> {
+        self.ticking = false;
+        self.last_tick_writes = self.writes.len();
+        std::mem::take(&mut self.writes)
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gas 0 of the tile at (1, 1) in the active buffer.
+    fn active_gas(buffers: &Buffers) -> f32 {
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        z_level
+            .get_tile(z_level.maybe_get_index(1, 1).unwrap())
+            .gases
+            .values[0]
+    }
+
+    // Writes outside a tick should apply straight away.
+    #[test]
+    fn immediate_outside_tick() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 4).unwrap();
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.values[0] = 3.0)
+            .unwrap();
+        assert_eq!(active_gas(&buffers), 3.0);
+        assert!(buffers.write_tile(4, 1, 0, |_| {}).is_err());
+        assert!(buffers.write_tile(1, 1, 1, |_| {}).is_err());
+    }
+
+    // Batched writes should cover every Z level, or nothing if any coordinates are bad.
+    #[test]
+    fn batched() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 4).unwrap();
+        buffers.init_z_level(1, 4, 4).unwrap();
+        let set = |moles: f32| move |tile: &mut Tile| tile.gases.values[0] = moles;
+        assert!(buffers
+            .write_tiles(vec![((1, 1, 0), set(1.0)), ((9, 9, 1), set(1.0))])
+            .is_err());
+        assert_eq!(active_gas(&buffers), 0.0);
+
+        buffers
+            .write_tiles(vec![((1, 1, 0), set(4.0)), ((1, 1, 1), set(5.0))])
+            .unwrap();
+        assert_eq!(active_gas(&buffers), 4.0);
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[1].read().unwrap();
+        assert_eq!(
+            z_level
+                .get_tile(z_level.maybe_get_index(1, 1).unwrap())
+                .gases
+                .values[0],
+            5.0
+        );
+    }
+
+    // Writes during a tick should wait for the flip, then apply in order.
+    #[test]
+    fn queued_until_flip() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 4).unwrap();
+        buffers.begin_tick();
+        // Set, then add: they stack.
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.values[0] = 5.0)
+            .unwrap();
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.values[0] += 1.0)
+            .unwrap();
+        assert_eq!(buffers.write_queue.lock().unwrap().depth(), 2);
+        assert_eq!(active_gas(&buffers), 0.0);
+        buffers.flip();
+        assert_eq!(active_gas(&buffers), 6.0);
+        assert_eq!(buffers.write_queue.lock().unwrap().depth(), 0);
+        assert_eq!(buffers.write_queue.lock().unwrap().last_tick_writes, 2);
+
+        // Add, then set: the set wins.
+        buffers.begin_tick();
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.values[0] += 1.0)
+            .unwrap();
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.values[0] = 2.0)
+            .unwrap();
+        buffers.cancel_tick();
+        assert_eq!(active_gas(&buffers), 2.0);
+    }
+}
@@ -0,0 +1,425 @@
+use crate::milla::constants::*;
+use crate::milla::gases::GasRegistry;
+use crate::milla::model::*;
+use eyre::eyre;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The reactions we use if BYOND doesn't load its own.
+const DEFAULT_REACTIONS: &str = include_str!("reactions.toml");
+
+/// A reaction file, as written in TOML or JSON.
+#[derive(Debug, Deserialize)]
+struct ReactionFile {
+    reaction: Vec<ReactionDefinition>,
+}
+
+/// A single reaction, as written in TOML or JSON. See reactions.toml for documentation.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReactionDefinition {
+    id: String,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    min_temperature: f32,
+    #[serde(default = "infinity")]
+    max_temperature: f32,
+    #[serde(default)]
+    requirements: BTreeMap<String, f32>,
+    rate: RateDefinition,
+    #[serde(default)]
+    reactants: BTreeMap<String, f32>,
+    #[serde(default)]
+    products: BTreeMap<String, f32>,
+    #[serde(default)]
+    energy: f32,
+    #[serde(default = "one")]
+    fuel: f32,
+}
+
+fn infinity() -> f32 {
+    f32::INFINITY
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// How fast a reaction happens, as written in TOML or JSON.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RateDefinition {
+    Limiting {
+        limits: BTreeMap<String, f32>,
+    },
+    TemperaturePolynomial {
+        gas: String,
+        coefficients: Vec<f32>,
+    },
+    TemperatureRamp {
+        gas: String,
+        optimal_temperature: f32,
+        max_ratio: f32,
+        #[serde(default)]
+        min_moles: f32,
+        #[serde(default = "one")]
+        hotspot_boost: f32,
+    },
+}
+
+/// How fast a reaction happens, with gas IDs resolved to registry indices.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ReactionRate {
+    /// The scarcest of these gases decides, each gas's moles multiplied by its limit.
+    Limiting { limits: Vec<(usize, f32)> },
+    /// A fraction of the gas reacts, given by a polynomial in temperature, constant term first.
+    TemperaturePolynomial { gas: usize, coefficients: Vec<f32> },
+    /// A fraction of the gas reacts, increasing linearly from min_temperature to
+    /// optimal_temperature.
+    TemperatureRamp {
+        gas: usize,
+        optimal_temperature: f32,
+        max_ratio: f32,
+        min_moles: f32,
+        hotspot_boost: f32,
+    },
+}
+
+/// A single chemical reaction between gases.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Reaction {
+    /// A unique name for this reaction.
+    pub(crate) id: String,
+    /// Reactions run in ascending priority order.
+    pub(crate) priority: i32,
+    /// The reaction only happens above this temperature, in kelvin.
+    pub(crate) min_temperature: f32,
+    /// The reaction only happens below this temperature, in kelvin.
+    pub(crate) max_temperature: f32,
+    /// Each gas must have more than this many moles for the reaction to happen.
+    pub(crate) requirements: Vec<(usize, f32)>,
+    /// How many units of reaction happen each tick.
+    pub(crate) rate: ReactionRate,
+    /// Moles of each gas consumed per unit of reaction.
+    pub(crate) reactants: Vec<(usize, f32)>,
+    /// Moles of each gas produced per unit of reaction.
+    pub(crate) products: Vec<(usize, f32)>,
+    /// Thermal energy released per unit of reaction, in joules.
+    pub(crate) energy: f32,
+    /// How much each unit of reaction counts towards fuel_burnt.
+    pub(crate) fuel: f32,
+}
+
+impl Reaction {
+    /// Whether this reaction is a fire, burning something with oxygen.
+    /// Its other reactants are what fires can use as fuel.
+    pub(crate) fn is_fire(&self) -> bool {
+        self.fuel > 0.0
+            && self
+                .reactants
+                .iter()
+                .any(|(gas, per_unit)| *gas == GAS_OXYGEN && *per_unit > 0.0)
+    }
+
+    /// Works out how many units of this reaction should happen.
+    /// `fraction` is how much of the tile is reacting, and `temperature` is its temperature.
+    /// Returns 0.0 if the reaction can't happen.
+    pub(crate) fn extent(
+        &self,
+        gases: &GasSet,
+        temperature: f32,
+        fraction: f32,
+        hotspot_step: bool,
+    ) -> f32 {
+        if temperature <= self.min_temperature || temperature >= self.max_temperature {
+            return 0.0;
+        }
+        for (gas, moles) in &self.requirements {
+            if gases.get(*gas) <= *moles {
+                return 0.0;
+            }
+        }
+
+        let mut extent = match &self.rate {
+            ReactionRate::Limiting { limits } => {
+                let mut limit = f32::INFINITY;
+                for (gas, multiplier) in limits {
+                    limit = limit.min(gases.get(*gas) * multiplier);
+                }
+                if limit.is_finite() {
+                    fraction * limit
+                } else {
+                    0.0
+                }
+            }
+            ReactionRate::TemperaturePolynomial { gas, coefficients } => {
+                let mut reaction_percent = 0.0;
+                for coefficient in coefficients.iter().rev() {
+                    reaction_percent = reaction_percent * temperature + coefficient;
+                }
+                reaction_percent.clamp(0.0, 1.0) * fraction * gases.get(*gas)
+            }
+            ReactionRate::TemperatureRamp {
+                gas,
+                optimal_temperature,
+                max_ratio,
+                min_moles,
+                hotspot_boost,
+            } => {
+                // Linear scaling fom 0 to 1 as temperatue goes from minimum to optimal.
+                let efficiency = ((temperature - self.min_temperature)
+                    / (optimal_temperature - self.min_temperature))
+                    .clamp(0.0, 1.0);
+                let boost = if hotspot_step { *hotspot_boost } else { 1.0 };
+                let available = fraction * gases.get(*gas);
+                let ramped = efficiency * max_ratio * boost * available;
+                if ramped < *min_moles {
+                    // Boost up to the minimum.
+                    min_moles.min(available)
+                } else {
+                    ramped
+                }
+            }
+        };
+
+        // Never consume more than the reacting part of the tile has.
+        for (gas, per_unit) in &self.reactants {
+            if *per_unit > 0.0 && extent * per_unit > fraction * gases.get(*gas) {
+                extent = fraction * gases.get(*gas) / per_unit;
+            }
+        }
+
+        extent.max(0.0)
+    }
+
+    /// Converts reactants into products for the given number of units.
+    /// Thermal energy is left to the caller, since hotspots handle it differently.
+    pub(crate) fn apply(&self, tile: &mut Tile, extent: f32) {
+        for (gas, per_unit) in &self.reactants {
+            tile.gases
+                .set(*gas, tile.gases.get(*gas) - extent * per_unit);
+        }
+        for (gas, per_unit) in &self.products {
+            tile.gases
+                .set(*gas, tile.gases.get(*gas) + extent * per_unit);
+        }
+        tile.fuel_burnt += extent * self.fuel;
+    }
+}
+
+/// Turns a map of gas IDs into a list of registry indices.
+fn resolve_gases(
+    registry: &GasRegistry,
+    reaction_id: &str,
+    gases: &BTreeMap<String, f32>,
+) -> eyre::Result<Vec<(usize, f32)>> {
+    gases
+        .iter()
+        .map(|(id, value)| Ok((resolve_gas(registry, reaction_id, id)?, *value)))
+        .collect()
+}
+
+/// Turns a gas ID into a registry index.
+fn resolve_gas(registry: &GasRegistry, reaction_id: &str, id: &str) -> eyre::Result<usize> {
+    registry
+        .find(id)
+        .ok_or(eyre!("Reaction {} uses unknown gas {}", reaction_id, id))
+}
+
+impl ReactionDefinition {
+    fn compile(self, registry: &GasRegistry) -> eyre::Result<Reaction> {
+        let rate = match &self.rate {
+            RateDefinition::Limiting { limits } => ReactionRate::Limiting {
+                limits: resolve_gases(registry, &self.id, limits)?,
+            },
+            RateDefinition::TemperaturePolynomial { gas, coefficients } => {
+                ReactionRate::TemperaturePolynomial {
+                    gas: resolve_gas(registry, &self.id, gas)?,
+                    coefficients: coefficients.clone(),
+                }
+            }
+            RateDefinition::TemperatureRamp {
+                gas,
+                optimal_temperature,
+                max_ratio,
+                min_moles,
+                hotspot_boost,
+            } => {
+                if *optimal_temperature <= self.min_temperature {
+                    return Err(eyre!(
+                        "Reaction {} has an optimal temperature below its minimum temperature.",
+                        self.id
+                    ));
+                }
+                ReactionRate::TemperatureRamp {
+                    gas: resolve_gas(registry, &self.id, gas)?,
+                    optimal_temperature: *optimal_temperature,
+                    max_ratio: *max_ratio,
+                    min_moles: *min_moles,
+                    hotspot_boost: *hotspot_boost,
+                }
+            }
+        };
+        Ok(Reaction {
+            requirements: resolve_gases(registry, &self.id, &self.requirements)?,
+            reactants: resolve_gases(registry, &self.id, &self.reactants)?,
+            products: resolve_gases(registry, &self.id, &self.products)?,
+            id: self.id,
+            priority: self.priority,
+            min_temperature: self.min_temperature,
+            max_temperature: self.max_temperature,
+            rate,
+            energy: self.energy,
+            fuel: self.fuel,
+        })
+    }
+}
+
+/// Parses a set of reaction definitions, sorted by priority.
+/// `json` selects JSON instead of TOML.
+pub(crate) fn parse_reactions(
+    registry: &GasRegistry,
+    text: &str,
+    json: bool,
+) -> eyre::Result<Vec<Reaction>> {
+    let file: ReactionFile = if json {
+        serde_json::from_str(text)?
+    } else {
+        toml::from_str(text)?
+    };
+    let mut reactions = file
+        .reaction
+        .into_iter()
+        .map(|definition| definition.compile(registry))
+        .collect::<eyre::Result<Vec<Reaction>>>()?;
+    for (index, reaction) in reactions.iter().enumerate() {
+        if reactions[..index]
+            .iter()
+            .any(|other| other.id == reaction.id)
+        {
+            return Err(eyre!("Duplicate reaction ID {}", reaction.id));
+        }
+    }
+    // Stable, so equal priorities keep file order.
+    reactions.sort_by_key(|reaction| reaction.priority);
+    Ok(reactions)
+}
+
+/// Loads a set of reaction definitions from a TOML or JSON file, based on its extension.
+pub(crate) fn load_reactions(registry: &GasRegistry, path: &Path) -> eyre::Result<Vec<Reaction>> {
+    let text = std::fs::read_to_string(path)?;
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    parse_reactions(registry, &text, json)
+}
+
+/// The builtin reactions.
+pub(crate) fn default_reactions(registry: &GasRegistry) -> Vec<Reaction> {
+    parse_reactions(registry, DEFAULT_REACTIONS, false).unwrap()
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_reaction(id: &str) -> Reaction {
+        default_reactions(&GasRegistry::new())
+            .into_iter()
+            .find(|reaction| reaction.id == id)
+            .unwrap()
+    }
+
+    // The default reactions should parse, in priority order.
+    #[test]
+    fn defaults_parse() {
+        let reactions = default_reactions(&GasRegistry::new());
+        let ids: Vec<&str> = reactions
+            .iter()
+            .map(|reaction| reaction.id.as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            vec!["agent_b_conversion", "nitrous_breakdown", "plasma_fire"]
+        );
+    }
+
+    // Agent B only converts CO2 while plasma is present, and is limited by the scarcest gas.
+    #[test]
+    fn agent_b_conversion() {
+        let reaction = find_reaction("agent_b_conversion");
+        let mut tile = Tile::new();
+        tile.gases.set(GAS_CARBON_DIOXIDE, 100.0);
+        tile.gases.set(GAS_AGENT_B, 100.0);
+        assert_eq!(reaction.extent(&tile.gases, 1000.0, 1.0, false), 0.0);
+
+        tile.gases.set(GAS_TOXINS, 100.0);
+        assert_eq!(reaction.extent(&tile.gases, 800.0, 1.0, false), 0.0);
+        let extent = reaction.extent(&tile.gases, 1000.0, 1.0, false);
+        assert!((extent - 5.0).abs() < TEST_TOLERANCE, "{}", extent);
+
+        reaction.apply(&mut tile, extent);
+        assert!((tile.gases.get(GAS_CARBON_DIOXIDE) - 95.0).abs() < TEST_TOLERANCE);
+        assert!((tile.gases.get(GAS_OXYGEN) - 5.0).abs() < TEST_TOLERANCE);
+        assert!((tile.gases.get(GAS_AGENT_B) - 99.75).abs() < TEST_TOLERANCE);
+        assert!((tile.gases.get(GAS_TOXINS) - 100.0).abs() < TEST_TOLERANCE);
+        assert!((tile.fuel_burnt - 5.0).abs() < TEST_TOLERANCE);
+    }
+
+    // Nitrous oxide breaks down faster as it gets hotter.
+    #[test]
+    fn nitrous_breakdown() {
+        let reaction = find_reaction("nitrous_breakdown");
+        let mut tile = Tile::new();
+        tile.gases.set(GAS_SLEEPING_AGENT, 100.0);
+        assert_eq!(reaction.extent(&tile.gases, 1000.0, 1.0, false), 0.0);
+
+        let cool = reaction.extent(&tile.gases, 2000.0, 1.0, false);
+        let hot = reaction.extent(&tile.gases, 4000.0, 1.0, false);
+        assert!(cool > 0.0);
+        assert!(hot > cool);
+
+        reaction.apply(&mut tile, cool);
+        assert!((tile.gases.get(GAS_NITROGEN) - cool).abs() < TEST_TOLERANCE);
+        assert!((tile.gases.get(GAS_OXYGEN) - cool / 2.0).abs() < TEST_TOLERANCE);
+    }
+
+    // Plasma fires burn more in hotspots, and are limited by oxygen.
+    #[test]
+    fn plasma_fire() {
+        let reaction = find_reaction("plasma_fire");
+        let mut tile = Tile::new();
+        tile.gases.set(GAS_TOXINS, 100.0);
+        tile.gases.set(GAS_OXYGEN, 100.0);
+        assert_eq!(reaction.extent(&tile.gases, T20C, 1.0, false), 0.0);
+
+        let normal = reaction.extent(&tile.gases, PLASMA_BURN_OPTIMAL_TEMP, 1.0, false);
+        let hotspot = reaction.extent(&tile.gases, PLASMA_BURN_OPTIMAL_TEMP, 1.0, true);
+        assert!((normal - 1.0).abs() < TEST_TOLERANCE, "{}", normal);
+        assert!((hotspot - 10.0).abs() < TEST_TOLERANCE, "{}", hotspot);
+
+        tile.gases.set(GAS_OXYGEN, 0.2);
+        let starved = reaction.extent(&tile.gases, PLASMA_BURN_OPTIMAL_TEMP, 1.0, false);
+        assert!((starved - 0.5).abs() < TEST_TOLERANCE, "{}", starved);
+    }
+
+    // Unknown gases and broken files should be rejected rather than silently ignored.
+    #[test]
+    fn bad_definitions() {
+        let registry = GasRegistry::new();
+        assert!(parse_reactions(
+            &registry,
+            r#"
+            [[reaction]]
+            id = "mystery"
+            rate = { type = "limiting", limits = { unobtainium = 1.0 } }
+            "#,
+            false
+        )
+        .is_err());
+        assert!(parse_reactions(&registry, "{\"reaction\": [{\"id\": \"x\"}]}", true).is_err());
+    }
+}
@@ -1,17 +1,22 @@
+use crate::milla::audit::{AuditPhase, Totals};
 use crate::milla::constants::*;
+use crate::milla::emitters::{GasEmitter, Heater};
+use crate::milla::fire;
 use crate::milla::model::*;
+use crate::milla::reactions::Reaction;
+use crate::milla::statics::gas_registry;
+use crate::milla::tick::ZLevelStats;
 use byondapi::map::ByondXYZ;
 use eyre::eyre;
 use scc::Bag;
 use std::collections::HashSet;
 
-pub(crate) fn find_walls(next: &mut ZLevel) {
-    for my_index in 0..MAP_SIZE * MAP_SIZE {
-        let x = (my_index / MAP_SIZE) as i32;
-        let y = (my_index % MAP_SIZE) as i32;
+pub(crate) fn find_walls(next: &mut ZLevelPart, tiles: &[usize]) {
+    for &my_index in tiles {
+        let (x, y) = next.get_coords(my_index);
 
         for (axis, (dx, dy)) in AXES.iter().enumerate() {
-            let their_index = match ZLevel::maybe_get_index(x + dx, y + dy) {
+            let their_index = match next.maybe_get_index(x + dx, y + dy) {
                 Some(index) => index,
                 None => {
                     // Edge of the map, acts like a wall.
@@ -51,24 +56,30 @@ pub(crate) fn find_walls(next: &mut ZLevel) {
 }
 
 /// Calculate the new wind at each boundary.
-pub(crate) fn update_wind(prev: &ZLevel, next: &mut ZLevel) {
-    for my_index in 0..MAP_SIZE * MAP_SIZE {
-        let x = (my_index / MAP_SIZE) as i32;
-        let y = (my_index % MAP_SIZE) as i32;
+/// Tiles outside the region are treated like walls, so no air moves in or out of it.
+pub(crate) fn update_wind(
+    prev: &ZLevel,
+    next: &mut ZLevelPart,
+    region: &ActiveRegion,
+    tiles: &[usize],
+) {
+    let gas_count = gas_registry().count();
+    for &my_index in tiles {
+        let (x, y) = prev.get_coords(my_index);
         let my_tile = prev.get_tile(my_index);
 
         for (axis, (dx, dy)) in AXES.iter().enumerate() {
-            let neighbor_index = match ZLevel::maybe_get_index(x + dx, y + dy) {
+            let neighbor_index = match prev.maybe_get_index(x + dx, y + dy) {
                 Some(index) => index,
                 None => continue,
             };
             let neighbor = prev.get_tile(neighbor_index);
             let my_new_tile = next.get_tile_mut(my_index);
 
-            // No wind across walls.
-            if my_new_tile.wall[axis] {
+            // No wind across walls, or out of the region.
+            if my_new_tile.wall[axis] || !region.contains(neighbor_index) {
                 my_new_tile.wind[axis] = 0.0;
-                for i in 0..GAS_COUNT {
+                for i in 0..gas_count {
                     my_new_tile.gas_flow[axis][i][GAS_FLOW_IN] = 0.0;
                     my_new_tile.gas_flow[axis][i][GAS_FLOW_OUT] = 0.0;
                 }
@@ -78,7 +89,7 @@ pub(crate) fn update_wind(prev: &ZLevel, next: &mut ZLevel) {
             // If there's no air, there's no wind.
             if my_tile.pressure() + neighbor.pressure() <= 0.0 {
                 my_new_tile.wind[axis] = 0.0;
-                for i in 0..GAS_COUNT {
+                for i in 0..gas_count {
                     my_new_tile.gas_flow[axis][i][GAS_FLOW_IN] = 0.0;
                     my_new_tile.gas_flow[axis][i][GAS_FLOW_OUT] = 0.0;
                 }
@@ -98,7 +109,7 @@ pub(crate) fn update_wind(prev: &ZLevel, next: &mut ZLevel) {
                 + WIND_ACCELERATION * (pressure_bias * WIND_STRENGTH - my_tile.wind[axis]))
                 .clamp(-MAX_WIND, MAX_WIND);
 
-            for i in 0..GAS_COUNT {
+            for i in 0..gas_count {
                 my_new_tile.gas_flow[axis][i][GAS_FLOW_IN] = 0.0;
                 my_new_tile.gas_flow[axis][i][GAS_FLOW_OUT] = 0.0;
 
@@ -129,19 +140,60 @@ pub(crate) struct AirflowOutcome {
     active_tiles: HashSet<usize>,
     max_gas_delta: f32,
     max_thermal_energy_delta: f32,
+    /// How many times flow_air_once ran before the air stabilized.
+    pub(crate) iterations: usize,
+    /// Whether the air actually stabilized before we hit MAX_ITERATIONS.
+    pub(crate) converged: bool,
+}
+
+impl AirflowOutcome {
+    fn new() -> Self {
+        AirflowOutcome {
+            active_tiles: HashSet::new(),
+            max_gas_delta: 0.0,
+            max_thermal_energy_delta: 0.0,
+            iterations: 1,
+            converged: false,
+        }
+    }
+
+    /// Combines the outcome from another strip of the same Z level into this one.
+    fn merge(&mut self, other: AirflowOutcome) {
+        self.active_tiles.extend(other.active_tiles);
+        self.max_gas_delta = self.max_gas_delta.max(other.max_gas_delta);
+        self.max_thermal_energy_delta = self
+            .max_thermal_energy_delta
+            .max(other.max_thermal_energy_delta);
+    }
 }
 
 /// Let the air flow until it stabilizes for this tick or we run out of patience.
-pub(crate) fn flow_air(prev: &ZLevel, next: &mut ZLevel) -> Result<AirflowOutcome, eyre::Error> {
-    let mut outcome = flow_air_once(prev, next, None)?;
-    for _iter in 1..MAX_ITERATIONS {
-        outcome = flow_air_once(prev, next, Some(outcome))?;
+/// Only tiles in the region take part.
+/// If `deterministic` is set, tiles are always visited in the same order, so the results are
+/// exactly repeatable.
+pub(crate) fn flow_air(
+    prev: &ZLevel,
+    next: &mut ZLevel,
+    region: &ActiveRegion,
+    deterministic: bool,
+) -> Result<AirflowOutcome, eyre::Error> {
+    let mut outcome = flow_air_once(prev, next, region, &region.tiles)?;
+    for iter in 1..MAX_ITERATIONS {
+        let mut active_tiles: Vec<usize> = outcome.active_tiles.into_iter().collect();
+        if deterministic {
+            // Gauss-Seidel results depend on the order we visit tiles in, and HashSet's order
+            // changes from run to run.
+            active_tiles.sort_unstable();
+        }
+        outcome = flow_air_once(prev, next, region, &active_tiles)?;
+        outcome.iterations = iter + 1;
 
         // Check for significant changes.
         if outcome.max_gas_delta < GAS_CHANGE_SIGNIFICANCE
             && outcome.max_thermal_energy_delta < THERMAL_CHANGE_SIGNIFICANCE
         {
             // We've stabilized.
+            outcome.converged = true;
             return Ok(outcome);
         }
     }
@@ -149,40 +201,43 @@ pub(crate) fn flow_air(prev: &ZLevel, next: &mut ZLevel) -> Result<AirflowOutcom
     Ok(outcome)
 }
 
-/// Let the air flow at every active tile by one step.
+/// Let the air flow at each of the given tiles by one step, in order.
+/// The Z level is split into strips, which flow in parallel. Tiles at the edge of a strip see
+/// their neighbours' values from this step if the neighbouring strip went first, which is still
+/// Gauss-Seidel, just visiting the tiles in a different order.
 pub(crate) fn flow_air_once(
     prev: &ZLevel,
     next: &mut ZLevel,
-    maybe_old_outcome: Option<AirflowOutcome>,
+    region: &ActiveRegion,
+    tiles: &[usize],
 ) -> Result<AirflowOutcome, eyre::Error> {
-    let mut new_outcome = AirflowOutcome {
-        active_tiles: HashSet::new(),
-        max_gas_delta: 0.0,
-        max_thermal_energy_delta: 0.0,
-    };
-
-    if let Some(old_outcome) = maybe_old_outcome {
-        for my_index in &old_outcome.active_tiles {
-            flow_air_once_at_index(prev, next, *my_index, &mut new_outcome)?;
-        }
-    } else {
-        for my_index in 0..MAP_SIZE * MAP_SIZE {
-            flow_air_once_at_index(prev, next, my_index, &mut new_outcome)?;
+    let strips = next.split_into_strips(tiles);
+    let strip_outcomes = next.for_each_strip(&strips, |next, strip| {
+        let mut strip_outcome = AirflowOutcome::new();
+        for &my_index in &strip.tiles {
+            flow_air_once_at_index(prev, next, region, my_index, &mut strip_outcome)?;
         }
-    }
+        Ok::<_, eyre::Error>(strip_outcome)
+    });
 
+    let mut new_outcome = AirflowOutcome::new();
+    for strip_outcome in strip_outcomes {
+        new_outcome.merge(strip_outcome?);
+    }
     Ok(new_outcome)
 }
 
 pub(crate) fn flow_air_once_at_index(
     prev: &ZLevel,
-    next: &mut ZLevel,
+    next: &mut ZLevelPart,
+    region: &ActiveRegion,
     my_index: usize,
     outcome: &mut AirflowOutcome,
 ) -> Result<(), eyre::Error> {
-    let x = (my_index / MAP_SIZE) as i32;
-    let y = (my_index % MAP_SIZE) as i32;
+    let (x, y) = prev.get_coords(my_index);
     let my_tile = prev.get_tile(my_index);
+    let registry = gas_registry();
+    let gas_count = registry.count();
 
     // Skip tiles that can't change.
     match my_tile.mode {
@@ -204,13 +259,13 @@ pub(crate) fn flow_air_once_at_index(
         my_new_tile.gases.copy_from(&my_tile.gases);
         my_new_tile.thermal_energy = my_tile.thermal_energy;
     }
-    let mut outgoing_gas_mult: [f32; GAS_COUNT] = [0.0; GAS_COUNT];
+    let mut outgoing_gas_mult: [f32; MAX_GAS_COUNT] = [0.0; MAX_GAS_COUNT];
     let mut total_weighted_temperature = my_tile.temperature() * my_tile.heat_capacity();
     let mut total_temperature_weights: f32 = my_tile.heat_capacity();
     for (dir, (dx, dy)) in DIRECTIONS.iter().enumerate() {
-        let neighbor_index = match ZLevel::maybe_get_index(x + dx, y + dy) {
-            Some(value) => value,
-            None => continue,
+        let neighbor_index = match prev.maybe_get_index(x + dx, y + dy) {
+            Some(value) if region.contains(value) => value,
+            _ => continue,
         };
         let (my_new_tile, new_neighbor) = next.get_pair_mut(my_index, neighbor_index);
 
@@ -233,7 +288,7 @@ pub(crate) fn flow_air_once_at_index(
             continue;
         }
 
-        for i in 0..GAS_COUNT {
+        for i in 0..gas_count {
             // Normalise the gas flow direction.
             let gas_flow_in;
             let gas_flow_out;
@@ -249,7 +304,8 @@ pub(crate) fn flow_air_once_at_index(
             // summing together this tile's value from the last iteration with the incoming values
             // from other tiles this tick.
             my_new_tile.gases.values[i] += gas_flow_in * new_neighbor.gases.values[i];
-            let temperature_weight = gas_flow_in * new_neighbor.gases.values[i] * SPECIFIC_HEATS[i];
+            let temperature_weight =
+                gas_flow_in * new_neighbor.gases.values[i] * registry.specific_heat(i);
 
             // Track the outgoing values as well.
             outgoing_gas_mult[i] += gas_flow_out;
@@ -268,7 +324,7 @@ pub(crate) fn flow_air_once_at_index(
     // to represent this tile.
     let mut max_gas_delta: f32 = 0.0;
     let my_new_tile = next.get_tile_mut(my_index);
-    for i in 0..GAS_COUNT {
+    for i in 0..gas_count {
         my_new_tile.gases.values[i] /= 1.0 + outgoing_gas_mult[i];
 
         if (prev_iter.gases.values[i] - my_new_tile.gases.values[i]).abs()
@@ -313,37 +369,156 @@ pub(crate) fn flow_air_once_at_index(
     // If any change was significant, mark this tile and all neighbors as active.
     outcome.active_tiles.insert(my_index);
     for (dx, dy) in DIRECTIONS {
-        if let Some(neighbor_index) = ZLevel::maybe_get_index(x + dx, y + dy) {
-            outcome.active_tiles.insert(neighbor_index);
+        if let Some(neighbor_index) = prev.maybe_get_index(x + dx, y + dy) {
+            if region.contains(neighbor_index) {
+                outcome.active_tiles.insert(neighbor_index);
+            }
         }
     }
 
     Ok(())
 }
 
-/// Applies effects that happen after the main airflow routine:
+/// Exchanges gas, and the heat it carries, between a Z level and the one directly above it.
+/// Only tiles that are open both up from `lower` and down from `upper` are connected.
+/// If the levels are different sizes, only the area they share is connected.
+/// Must run after both levels have finished their own tick, so it sees consistent values.
+/// Only places that were simulated on at least one of the levels are connected.
+/// Returns the indices of the tiles it changed on each level, which still need settle_tiles().
+pub(crate) fn flow_vertical(lower: &mut ZLevel, upper: &mut ZLevel) -> (Vec<usize>, Vec<usize>) {
+    let mut touched = (Vec::new(), Vec::new());
+    let gas_count = gas_registry().count();
+    let width = lower.width().min(upper.width()) as i32;
+    let height = lower.height().min(upper.height()) as i32;
+    for (x, y) in (0..width).flat_map(|x| (0..height).map(move |y| (x, y))) {
+        let lower_index = lower.maybe_get_index(x, y).unwrap();
+        let upper_index = upper.maybe_get_index(x, y).unwrap();
+        if !lower.is_dirty(lower_index) && !upper.is_dirty(upper_index) {
+            // Neither side changed this tick, so they're already settled.
+            continue;
+        }
+        let below = lower.get_tile_mut(lower_index);
+        let above = upper.get_tile_mut(upper_index);
+        if below.airtight_directions.contains(AirtightDirections::UP)
+            || above.airtight_directions.contains(AirtightDirections::DOWN)
+        {
+            continue;
+        }
+
+        let below_is_space = below.mode == AtmosMode::Space;
+        let above_is_space = above.mode == AtmosMode::Space;
+        if below_is_space && above_is_space {
+            continue;
+        }
+
+        // Work out what leaves each tile before changing either of them.
+        let below_energy = vertical_outflow_energy(below);
+        let above_energy = vertical_outflow_energy(above);
+        let mut significant = (above_energy - below_energy).abs() >= THERMAL_CHANGE_SIGNIFICANCE;
+        for gas in 0..gas_count {
+            let from_below = below.gases.values[gas] * VERTICAL_FLOW_RATE;
+            let from_above = above.gases.values[gas] * VERTICAL_FLOW_RATE;
+            significant |= (from_above - from_below).abs() >= GAS_CHANGE_SIGNIFICANCE;
+            // Space keeps nothing, so air that moves into it is simply lost.
+            if !below_is_space {
+                below.gases.values[gas] += from_above - from_below;
+            }
+            if !above_is_space {
+                above.gases.values[gas] += from_below - from_above;
+            }
+        }
+        below.gases.set_dirty();
+        above.gases.set_dirty();
+        if !below_is_space {
+            below.thermal_energy += above_energy - below_energy;
+            touched.0.push(lower_index);
+        }
+        if !above_is_space {
+            above.thermal_energy += below_energy - above_energy;
+            touched.1.push(upper_index);
+        }
+
+        // Keep both sides awake while air is still moving between them.
+        if significant {
+            lower.wake(lower_index);
+            upper.wake(upper_index);
+        } else {
+            lower.mark_dirty(lower_index);
+            upper.mark_dirty(upper_index);
+        }
+    }
+    touched
+}
+
+/// How much thermal energy leaves a tile along with the air that flows vertically out of it.
+fn vertical_outflow_energy(tile: &Tile) -> f32 {
+    let heat_capacity = tile.heat_capacity();
+    if heat_capacity <= 0.0 {
+        return 0.0;
+    }
+    tile.thermal_energy * VERTICAL_FLOW_RATE * tile.gases.heat_capacity() / heat_capacity
+}
+
+/// Runs the Z level's emitters and heaters, after airflow and before post_process(), so it sees
+/// what they did.
+pub(crate) fn apply_emitters(
+    next: &mut ZLevelPart,
+    emitters: &[GasEmitter],
+    heaters: &[Heater],
+    z: i32,
+    stats: &mut ZLevelStats,
+) {
+    for emitter in emitters {
+        apply_emitter(next, z, stats, (emitter.x, emitter.y), |tile| {
+            emitter.apply(tile)
+        });
+    }
+    for heater in heaters {
+        apply_emitter(next, z, stats, (heater.x, heater.y), |tile| {
+            heater.apply(tile)
+        });
+    }
+}
+
+/// Applies effects that happen after the main airflow routine to the given tiles:
 /// * Tile modes
 /// * Superconductivity
 /// * Reactions
 /// * Hotspot cleanup
 /// * Sanitization
 /// * Looking for interesting tiles.
+/// * Keeping chunks awake if anything significant happened.
+///
+/// Each tile superconducts with its neighbours to the east and north, so those can change too.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn post_process(
     prev: &ZLevel,
-    next: &mut ZLevel,
+    next: &mut ZLevelPart,
     environments: &Box<[Tile]>,
+    reactions: &[Reaction],
+    region: &ActiveRegion,
+    tiles: &[usize],
     new_interesting_tiles: &Bag<InterestingTile>,
     z: i32,
+    stats: &mut ZLevelStats,
 ) -> Result<(), eyre::Error> {
-    for my_index in 0..MAP_SIZE * MAP_SIZE {
-        let x = (my_index / MAP_SIZE) as i32;
-        let y = (my_index % MAP_SIZE) as i32;
+    for &my_index in tiles {
+        let (x, y) = prev.get_coords(my_index);
         let my_tile = prev.get_tile(my_index);
 
+        let before = audit_before(stats, next, &[my_index]);
         {
             let my_next_tile = next.get_tile_mut(my_index);
             apply_tile_mode(my_next_tile, environments)?;
         }
+        audit_after(
+            stats,
+            next,
+            &[my_index],
+            before,
+            AuditPhase::TileModes,
+            (x, y, z),
+        );
 
         if let AtmosMode::Space = my_tile.mode {
             // Space doesn't superconduct, has no reactions, doesn't need to be sanitized, and is never interesting. (Take that, astrophysicists and astronomers!)
@@ -351,40 +526,201 @@ pub(crate) fn post_process(
         }
 
         for (dx, dy) in AXES {
-            let their_index = match ZLevel::maybe_get_index(x + dx, y + dy) {
-                Some(index) => index,
-                None => continue,
+            let their_index = match prev.maybe_get_index(x + dx, y + dy) {
+                Some(index) if region.contains(index) => index,
+                _ => continue,
             };
 
+            let pair = [my_index, their_index];
+            let before = audit_before(stats, next, &pair);
             let (my_next_tile, their_next_tile) = next.get_pair_mut(my_index, their_index);
 
             if their_next_tile.mode != AtmosMode::Space {
                 superconduct(my_next_tile, their_next_tile, dx > 0, false);
             }
+            audit_after(
+                stats,
+                next,
+                &pair,
+                before,
+                AuditPhase::Superconduction,
+                (x, y, z),
+            );
         }
 
+        let before = audit_before(stats, next, &[my_index]);
+        exchange_innate_heat(next.get_tile_mut(my_index));
+        audit_after(
+            stats,
+            next,
+            &[my_index],
+            before,
+            AuditPhase::Superconduction,
+            (x, y, z),
+        );
+
+        let before = audit_before(stats, next, &[my_index]);
         {
             let my_next_tile = next.get_tile_mut(my_index);
             // New tick, reset the fuel tracker.
             my_next_tile.fuel_burnt = 0.0;
 
-            react(my_next_tile, false);
+            fire::catch_fire(prev, x, y, my_next_tile, reactions);
+            react(my_next_tile, false, reactions);
             if my_next_tile.hotspot_volume > 0.0 {
-                react(my_next_tile, true);
+                react(my_next_tile, true, reactions);
             }
-
+            fire::burn_solid_fuel(my_next_tile);
+            fire::smother(my_next_tile, reactions);
+        }
+        audit_after(
+            stats,
+            next,
+            &[my_index],
+            before,
+            AuditPhase::Reactions,
+            (x, y, z),
+        );
+
+        let before = audit_before(stats, next, &[my_index]);
+        {
+            let my_next_tile = next.get_tile_mut(my_index);
             // Sanitize the tile, to avoid negative/NaN/infinity spread.
-            sanitize(my_next_tile, my_tile);
+            if sanitize(my_next_tile, my_tile) {
+                stats.sanitized_tiles += 1;
+            }
+        }
+        audit_after(
+            stats,
+            next,
+            &[my_index],
+            before,
+            AuditPhase::Sanitization,
+            (x, y, z),
+        );
+
+        let interesting =
+            check_interesting(x, y, z, next, my_tile, my_index, new_interesting_tiles)?;
+        if interesting {
+            stats.interesting_tiles += 1;
         }
 
-        check_interesting(x, y, z, next, my_tile, my_index, new_interesting_tiles)?;
+        // Keep the chunk awake while anything is still happening in it.
+        if interesting || changed_significantly(my_tile, next.get_tile(my_index)) {
+            next.wake(my_index);
+        }
     }
     Ok(())
 }
 
+/// Sanitizes tiles that changed after post_process() was done with them, and checks whether
+/// they became interesting. Returns the interesting ones, which replace whatever post_process()
+/// said about the same tiles.
+pub(crate) fn settle_tiles(
+    prev: &ZLevel,
+    next: &mut ZLevel,
+    z: i32,
+    tiles: &[usize],
+    stats: &mut ZLevelStats,
+) -> Result<Vec<InterestingTile>, eyre::Error> {
+    let new_interesting_tiles = Bag::default();
+    let mut next = next.as_part();
+    for &my_index in tiles {
+        let my_tile = prev.get_tile(my_index);
+        if next.get_tile(my_index).mode == AtmosMode::Space {
+            continue;
+        }
+        if sanitize(next.get_tile_mut(my_index), my_tile) {
+            stats.sanitized_tiles += 1;
+        }
+        let (x, y) = prev.get_coords(my_index);
+        check_interesting(
+            x,
+            y,
+            z,
+            &mut next,
+            my_tile,
+            my_index,
+            &new_interesting_tiles,
+        )?;
+    }
+    Ok(new_interesting_tiles.into_iter().collect())
+}
+
+/// Runs one emitter or heater on the tile at `(x, y)`, if it exists.
+fn apply_emitter<F>(
+    next: &mut ZLevelPart,
+    z: i32,
+    stats: &mut ZLevelStats,
+    (x, y): (i32, i32),
+    f: F,
+) where
+    F: FnOnce(&mut Tile) -> bool,
+{
+    let Some(index) = next.maybe_get_index(x, y) else {
+        return;
+    };
+    let before = audit_before(stats, next, &[index]);
+    let changed = f(next.get_tile_mut(index));
+    audit_after(
+        stats,
+        next,
+        &[index],
+        before,
+        AuditPhase::Emitters,
+        (x, y, z),
+    );
+    if changed {
+        // Even a slow leak adds up, so keep the chunk awake while it's doing anything.
+        next.wake(index);
+    }
+}
+
+/// If we're auditing, sums up the tiles a step is about to change.
+fn audit_before(stats: &ZLevelStats, next: &ZLevelPart, indexes: &[usize]) -> Option<Totals> {
+    stats
+        .audit
+        .as_ref()
+        .map(|_| Totals::of_tiles(indexes.iter().map(|&index| next.get_tile(index))))
+}
+
+/// If we're auditing, records what a step changed.
+fn audit_after(
+    stats: &mut ZLevelStats,
+    next: &ZLevelPart,
+    indexes: &[usize],
+    before: Option<Totals>,
+    phase: AuditPhase,
+    coords: (i32, i32, i32),
+) {
+    if let (Some(audit), Some(before)) = (&mut stats.audit, before) {
+        let after = Totals::of_tiles(indexes.iter().map(|&index| next.get_tile(index)));
+        audit.record_tile(phase, coords, &before, &after);
+    }
+}
+
+/// Checks whether a tile changed enough during a tick that it still needs simulating.
+pub(crate) fn changed_significantly(my_tile: &Tile, my_next_tile: &Tile) -> bool {
+    if my_next_tile.hotspot_volume > 0.0 || my_next_tile.fuel_burnt > 0.0 {
+        // Fires are always doing something.
+        return true;
+    }
+    if (my_next_tile.thermal_energy - my_tile.thermal_energy).abs() >= THERMAL_CHANGE_SIGNIFICANCE
+        || (my_next_tile.innate_thermal_energy - my_tile.innate_thermal_energy).abs()
+            >= THERMAL_CHANGE_SIGNIFICANCE
+    {
+        return true;
+    }
+    (0..gas_registry().count()).any(|gas| {
+        (my_next_tile.gases.values[gas] - my_tile.gases.values[gas]).abs()
+            >= GAS_CHANGE_SIGNIFICANCE
+    })
+}
+
 pub(crate) fn sanitize(my_next_tile: &mut Tile, my_tile: &Tile) -> bool {
     let mut sanitized = false;
-    for i in 0..GAS_COUNT {
+    let gas_count = gas_registry().count();
+    for i in 0..gas_count {
         if !my_next_tile.gases.values[i].is_finite() {
             // Reset back to the last value, in the hopes that it's safe.
             my_next_tile.gases.values[i] = my_tile.gases.values[i];
@@ -406,6 +742,15 @@ pub(crate) fn sanitize(my_next_tile: &mut Tile, my_tile: &Tile) -> bool {
         my_next_tile.thermal_energy = 0.0;
         sanitized = true;
     }
+    if !my_next_tile.innate_thermal_energy.is_finite() {
+        // Reset back to the last value, in the hopes that it's safe.
+        my_next_tile.innate_thermal_energy = my_tile.innate_thermal_energy;
+        sanitized = true;
+    } else if my_next_tile.innate_thermal_energy < 0.0 {
+        // Zero out anything that becomes negative.
+        my_next_tile.innate_thermal_energy = 0.0;
+        sanitized = true;
+    }
     if !my_next_tile.wind[0].is_finite() {
         // Reset back to the last value, in the hopes that it's safe.
         my_next_tile.wind[0] = my_tile.wind[0];
@@ -417,9 +762,7 @@ pub(crate) fn sanitize(my_next_tile: &mut Tile, my_tile: &Tile) -> bool {
         sanitized = true;
     }
     if my_next_tile.gases.moles() < MINIMUM_NONZERO_MOLES {
-        for i in 0..GAS_COUNT {
-            my_next_tile.gases.values[i] = 0.0;
-        }
+        my_next_tile.gases.clear();
         my_next_tile.thermal_energy = 0.0;
         // We don't count this as sanitized because it's expected.
     }
@@ -429,15 +772,16 @@ pub(crate) fn sanitize(my_next_tile: &mut Tile, my_tile: &Tile) -> bool {
 
 #[allow(clippy::if_same_then_else)]
 /// Checks a tile to see if it's "interesting" and should be sent to BYOND.
+/// Returns whether it was.
 pub(crate) fn check_interesting(
     x: i32,
     y: i32,
     z: i32,
-    next: &mut ZLevel,
+    next: &mut ZLevelPart,
     my_tile: &Tile,
     my_index: usize,
     new_interesting_tiles: &Bag<InterestingTile>,
-) -> Result<(), eyre::Error> {
+) -> Result<bool, eyre::Error> {
     let mut reasons: ReasonFlags = ReasonFlags::empty();
     {
         let my_next_tile = next.get_tile_mut(my_index);
@@ -446,21 +790,23 @@ pub(crate) fn check_interesting(
         {
             // Fire started or stopped.
             reasons |= ReasonFlags::DISPLAY;
-        } else if (my_next_tile.gases.toxins() >= TOXINS_MIN_VISIBILITY_MOLES)
-            != (my_tile.gases.toxins() >= TOXINS_MIN_VISIBILITY_MOLES)
-        {
-            // Crossed the toxins visibility threshold.
-            reasons |= ReasonFlags::DISPLAY;
-        } else if (my_next_tile.gases.sleeping_agent() >= SLEEPING_GAS_VISIBILITY_MOLES)
-            != (my_tile.gases.sleeping_agent() >= SLEEPING_GAS_VISIBILITY_MOLES)
-        {
-            // Crossed the sleeping agent visibility threshold.
-            reasons |= ReasonFlags::DISPLAY;
+        } else {
+            let registry = gas_registry();
+            for gas in 0..registry.count() {
+                let visibility_moles = registry.visibility_moles(gas);
+                if (my_next_tile.gases.get(gas) >= visibility_moles)
+                    != (my_tile.gases.get(gas) >= visibility_moles)
+                {
+                    // Crossed a gas visibility threshold.
+                    reasons |= ReasonFlags::DISPLAY;
+                    break;
+                }
+            }
         }
 
         if my_next_tile.temperature() > PLASMA_BURN_MIN_TEMP {
             if let AtmosMode::ExposedTo { .. } = my_next_tile.mode {
-                // Since environments have fixed gases and temperatures, we only count them as
+                // Since environments set their own gases and temperatures, we only count them as
                 // interesting (for heat) if there's an active fire.
                 if my_next_tile.fuel_burnt > REACTION_SIGNIFICANCE_MOLES {
                     reasons |= ReasonFlags::HOT;
@@ -470,13 +816,18 @@ pub(crate) fn check_interesting(
                 reasons |= ReasonFlags::HOT;
             }
         }
+
+        if my_next_tile.hotspot_volume > 0.0 && my_tile.hotspot_volume <= 0.0 {
+            // A fire started here.
+            reasons |= ReasonFlags::IGNITED;
+        }
     }
     let my_next_tile = next.get_tile(my_index);
     let mut wind_x: f32 = 0.0;
     if my_next_tile.wind[AXIS_X] > 0.0 {
         wind_x += my_next_tile.wind[AXIS_X] * WIND_SPEED * BYOND_WIND_MULTIPLIER;
     }
-    if let Some(index) = ZLevel::maybe_get_index(x - 1, y) {
+    if let Some(index) = next.maybe_get_index(x - 1, y) {
         let their_next_tile = next.get_tile(index);
         if their_next_tile.wind[AXIS_X] < 0.0 {
             // This is negative, but that's good, because we want it to fight against the wind
@@ -489,7 +840,7 @@ pub(crate) fn check_interesting(
     if my_next_tile.wind[AXIS_Y] > 0.0 {
         wind_y += my_next_tile.wind[AXIS_Y] * BYOND_WIND_MULTIPLIER;
     }
-    if let Some(index) = ZLevel::maybe_get_index(x, y - 1) {
+    if let Some(index) = next.maybe_get_index(x, y - 1) {
         let their_next_tile = next.get_tile(index);
         if their_next_tile.wind[AXIS_Y] < 0.0 {
             // This is negative, but that's good, because we want it to fight against the wind
@@ -515,131 +866,48 @@ pub(crate) fn check_interesting(
         });
     }
 
-    Ok(())
+    Ok(!reasons.is_empty())
 }
 
-/// Perform chemical reactions on the tile.
-pub(crate) fn react(my_next_tile: &mut Tile, hotspot_step: bool) {
+/// Perform chemical reactions on the tile, in priority order.
+/// When `hotspot_step` is set, only the hotspot's share of the tile reacts, at the hotspot's
+/// temperature. Otherwise, the rest of the tile reacts at the tile's temperature.
+pub(crate) fn react(my_next_tile: &mut Tile, hotspot_step: bool, reactions: &[Reaction]) {
     let fraction: f32;
-    let hotspot_boost: f32;
     let mut cached_heat_capacity: f32;
     let mut cached_temperature: f32;
     let mut thermal_energy: f32;
     if hotspot_step {
         fraction = my_next_tile.hotspot_volume;
-        hotspot_boost = PLASMA_BURN_HOTSPOT_RATIO_BOOST;
         cached_heat_capacity = fraction * my_next_tile.heat_capacity();
         cached_temperature = my_next_tile.hotspot_temperature;
         thermal_energy = cached_temperature * cached_heat_capacity;
     } else {
         fraction = 1.0 - my_next_tile.hotspot_volume;
-        hotspot_boost = 1.0;
         cached_heat_capacity = fraction * my_next_tile.heat_capacity();
         thermal_energy = fraction * my_next_tile.thermal_energy;
         cached_temperature = thermal_energy / cached_heat_capacity;
     }
     let initial_thermal_energy = thermal_energy;
 
-    // Agent B converting CO2 to O2
-    if cached_temperature > AGENT_B_CONVERSION_TEMP
-        && my_next_tile.gases.agent_b() > 0.0
-        && my_next_tile.gases.carbon_dioxide() > 0.0
-        && my_next_tile.gases.toxins() > 0.0
-    {
-        let co2_converted = fraction
-            * (my_next_tile.gases.carbon_dioxide() * 0.75)
-                .min(my_next_tile.gases.toxins() * 0.25)
-                .min(my_next_tile.gases.agent_b() * 0.05);
-
-        my_next_tile
-            .gases
-            .set_carbon_dioxide(my_next_tile.gases.carbon_dioxide() - co2_converted);
-        my_next_tile
-            .gases
-            .set_oxygen(my_next_tile.gases.oxygen() + co2_converted);
-        my_next_tile
-            .gases
-            .set_agent_b(my_next_tile.gases.agent_b() - co2_converted * 0.05);
-        // Recalculate heat capacity.
-        cached_heat_capacity = fraction * my_next_tile.heat_capacity();
-        // Add in the new thermal energy.
-        thermal_energy += AGENT_B_CONVERSION_ENERGY * co2_converted;
-        // Recalculate temperature for any subsequent reactions.
-        cached_temperature = thermal_energy / cached_heat_capacity;
-        my_next_tile.fuel_burnt += co2_converted;
-    }
-    // Nitrous Oxide breaking down into nitrogen and oxygen.
-    if cached_temperature > SLEEPING_GAS_BREAKDOWN_TEMP && my_next_tile.gases.sleeping_agent() > 0.0
-    {
-        let reaction_percent = (0.00002
-            * (cached_temperature - (0.00001 * (cached_temperature.powi(2)))))
-        .max(0.0)
-        .min(1.0);
-        let nitrous_decomposed = reaction_percent * fraction * my_next_tile.gases.sleeping_agent();
-
-        my_next_tile
-            .gases
-            .set_sleeping_agent(my_next_tile.gases.sleeping_agent() - nitrous_decomposed);
-        my_next_tile
-            .gases
-            .set_nitrogen(my_next_tile.gases.nitrogen() + nitrous_decomposed);
-        my_next_tile
-            .gases
-            .set_oxygen(my_next_tile.gases.oxygen() + nitrous_decomposed / 2.0);
-
-        // Recalculate heat capacity.
-        cached_heat_capacity = fraction * my_next_tile.heat_capacity();
-        // Add in the new thermal energy.
-        thermal_energy += NITROUS_BREAKDOWN_ENERGY * nitrous_decomposed;
-        // Recalculate temperature for any subsequent reactions.
-        cached_temperature = thermal_energy / cached_heat_capacity;
-
-        my_next_tile.fuel_burnt += nitrous_decomposed;
-    }
-    // Plasmafire!
-    if cached_temperature > PLASMA_BURN_MIN_TEMP
-        && my_next_tile.gases.toxins() > 0.0
-        && my_next_tile.gases.oxygen() > 0.0
-    {
-        // How efficient is the burn?
-        // Linear scaling fom 0 to 1 as temperatue goes from minimum to optimal.
-        let efficiency = ((cached_temperature - PLASMA_BURN_MIN_TEMP)
-            / (PLASMA_BURN_OPTIMAL_TEMP - PLASMA_BURN_MIN_TEMP))
-            .max(0.0)
-            .min(1.0);
-
-        // How much plasma is available to burn?
-        let burnable_plasma = fraction * my_next_tile.gases.toxins();
-
-        // Actual burn amount.
-        let mut plasma_burnt = efficiency * PLASMA_BURN_MAX_RATIO * hotspot_boost * burnable_plasma;
-        if plasma_burnt < PLASMA_BURN_MIN_MOLES {
-            // Boost up to the minimum.
-            plasma_burnt = PLASMA_BURN_MIN_MOLES.min(burnable_plasma);
-        }
-        if plasma_burnt * PLASMA_BURN_OXYGEN_PER_PLASMA > fraction * my_next_tile.gases.oxygen() {
-            // Restrict based on available oxygen.
-            plasma_burnt = fraction * my_next_tile.gases.oxygen() / PLASMA_BURN_OXYGEN_PER_PLASMA;
-        }
-
-        my_next_tile
-            .gases
-            .set_toxins(my_next_tile.gases.toxins() - plasma_burnt);
-        my_next_tile
-            .gases
-            .set_carbon_dioxide(my_next_tile.gases.carbon_dioxide() + plasma_burnt);
-        my_next_tile
-            .gases
-            .set_oxygen(my_next_tile.gases.oxygen() - plasma_burnt * PLASMA_BURN_OXYGEN_PER_PLASMA);
+    for reaction in reactions {
+        let extent = reaction.extent(
+            &my_next_tile.gases,
+            cached_temperature,
+            fraction,
+            hotspot_step,
+        );
+        if extent <= 0.0 {
+            continue;
+        }
 
+        reaction.apply(my_next_tile, extent);
         // Recalculate heat capacity.
         cached_heat_capacity = fraction * my_next_tile.heat_capacity();
         // THEN we can add in the new thermal energy.
-        thermal_energy += PLASMA_BURN_ENERGY * plasma_burnt;
+        thermal_energy += reaction.energy * extent;
         // Recalculate temperature for any subsequent reactions.
         cached_temperature = thermal_energy / cached_heat_capacity;
-
-        my_next_tile.fuel_burnt += plasma_burnt;
     }
 
     if hotspot_step {
@@ -649,10 +917,13 @@ pub(crate) fn react(my_next_tile: &mut Tile, hotspot_step: bool) {
         let temperature_difference = cached_temperature - tile_temperature;
         if temperature_difference > 0.0 {
             let excess_thermal_energy = temperature_difference * cached_heat_capacity;
-            conduction  = excess_thermal_energy * HOTSPOT_CONDUCTION;
+            conduction = excess_thermal_energy * HOTSPOT_CONDUCTION;
             my_next_tile.thermal_energy += conduction;
         }
-        adjust_hotspot(my_next_tile, thermal_energy - initial_thermal_energy - conduction);
+        adjust_hotspot(
+            my_next_tile,
+            thermal_energy - initial_thermal_energy - conduction,
+        );
     } else {
         my_next_tile.thermal_energy += thermal_energy - initial_thermal_energy;
     }
@@ -666,15 +937,12 @@ pub(crate) fn apply_tile_mode(
     match my_next_tile.mode {
         AtmosMode::Space => {
             // Space tiles lose all gas and thermal energy every tick.
-            for gas in 0..GAS_COUNT {
-                my_next_tile.gases.values[gas] = 0.0;
-            }
-            my_next_tile.gases.set_dirty();
+            my_next_tile.gases.clear();
             my_next_tile.thermal_energy = 0.0;
         }
         AtmosMode::ExposedTo { environment_id } => {
             // Exposed tiles reset back to the same state every tick.
-            if environment_id as usize > environments.len() {
+            if environment_id as usize >= environments.len() {
                 return Err(eyre!("Invalid environment ID {}", environment_id));
             }
 
@@ -697,6 +965,22 @@ pub(crate) fn apply_tile_mode(
     Ok(())
 }
 
+/// Lets a tile's floor or walls exchange heat with its air.
+pub(crate) fn exchange_innate_heat(tile: &mut Tile) {
+    let air_heat_capacity = tile.heat_capacity();
+    if !tile.has_thermal_mass() || air_heat_capacity <= 0.0 {
+        return;
+    }
+    // Same formula as superconduction, so it can never overshoot.
+    let exchange = INNATE_HEAT_TRANSFER_COEFFICIENT
+        * (tile.innate_temperature() - tile.temperature())
+        * tile.innate_heat_capacity
+        * air_heat_capacity
+        / (tile.innate_heat_capacity + air_heat_capacity);
+    tile.innate_thermal_energy -= exchange;
+    tile.thermal_energy += exchange;
+}
+
 // Performs superconduction between two superconductivity-connected tiles.
 pub(crate) fn superconduct(my_tile: &mut Tile, their_tile: &mut Tile, is_east: bool, force: bool) {
     // Superconduction is scaled to the smaller directional superconductivity setting of the two
@@ -724,15 +1008,19 @@ pub(crate) fn superconduct(my_tile: &mut Tile, their_tile: &mut Tile, is_east: b
             .min(their_tile.superconductivity.south);
     }
 
-    let my_heat_capacity = my_tile.heat_capacity();
-    let their_heat_capacity = their_tile.heat_capacity();
+    // Walls and floors with their own heat capacity conduct through that, everything else
+    // conducts through its air.
+    let my_heat_capacity = my_tile.conducting_heat_capacity();
+    let their_heat_capacity = their_tile.conducting_heat_capacity();
     if transfer_coefficient <= 0.0 || my_heat_capacity <= 0.0 || their_heat_capacity <= 0.0 {
         // Nothing to do.
         return;
     }
+    let my_temperature = my_tile.conducting_temperature();
+    let their_temperature = their_tile.conducting_temperature();
 
     // Temporary workaround to match LINDA better for high temperatures.
-    if my_tile.temperature() > T20C || their_tile.temperature() > T20C {
+    if my_temperature > T20C || their_temperature > T20C {
         transfer_coefficient = (transfer_coefficient * 100.0).min(OPEN_HEAT_TRANSFER_COEFFICIENT);
     }
 
@@ -740,34 +1028,36 @@ pub(crate) fn superconduct(my_tile: &mut Tile, their_tile: &mut Tile, is_east: b
     // Positive means heat flow from us to them.
     // Negative means heat flow from them to us.
     let conduction = transfer_coefficient
-        * (my_tile.temperature() - their_tile.temperature())
+        * (my_temperature - their_temperature)
         * my_heat_capacity
         * their_heat_capacity
         / (my_heat_capacity + their_heat_capacity);
 
     // Half of the conduction always goes to the overall heat of the tile
-    my_tile.thermal_energy -= conduction / 2.0;
-    their_tile.thermal_energy += conduction / 2.0;
+    *my_tile.conducting_thermal_energy_mut() -= conduction / 2.0;
+    *their_tile.conducting_thermal_energy_mut() += conduction / 2.0;
 
-    // The other half can spawn or expand hotspots.
+    // The other half can spawn or expand hotspots, but only in air.
     if conduction > 0.0
-        && my_tile.temperature() > PLASMA_BURN_OPTIMAL_TEMP
-        && their_tile.temperature() < PLASMA_BURN_OPTIMAL_TEMP
+        && !their_tile.conducts_innately()
+        && my_temperature > PLASMA_BURN_OPTIMAL_TEMP
+        && their_temperature < PLASMA_BURN_OPTIMAL_TEMP
     {
         // Positive: Spawn or expand their hotspot.
         adjust_hotspot(their_tile, conduction / 2.0);
-        my_tile.thermal_energy -= conduction / 2.0;
+        *my_tile.conducting_thermal_energy_mut() -= conduction / 2.0;
     } else if conduction < 0.0
-        && my_tile.temperature() < PLASMA_BURN_OPTIMAL_TEMP
-        && their_tile.temperature() > PLASMA_BURN_OPTIMAL_TEMP
+        && !my_tile.conducts_innately()
+        && my_temperature < PLASMA_BURN_OPTIMAL_TEMP
+        && their_temperature > PLASMA_BURN_OPTIMAL_TEMP
     {
         // Negative: Spawn or expand my hotspot.
         adjust_hotspot(my_tile, -conduction / 2.0);
-        their_tile.thermal_energy += conduction / 2.0;
+        *their_tile.conducting_thermal_energy_mut() += conduction / 2.0;
     } else {
         // No need for hotspot adjustment.
-        my_tile.thermal_energy -= conduction / 2.0;
-        their_tile.thermal_energy += conduction / 2.0;
+        *my_tile.conducting_thermal_energy_mut() -= conduction / 2.0;
+        *their_tile.conducting_thermal_energy_mut() += conduction / 2.0;
     }
 }
 
@@ -809,8 +1099,8 @@ pub(crate) fn normalise_hotspot(tile: &mut Tile) {
     }
 
     if tile.hotspot_temperature < PLASMA_BURN_MIN_TEMP
-        || tile.gases.toxins() <= REACTION_SIGNIFICANCE_MOLES
-        || tile.gases.oxygen() <= REACTION_SIGNIFICANCE_MOLES
+        || (tile.gases.get(GAS_TOXINS) <= REACTION_SIGNIFICANCE_MOLES && tile.solid_fuel <= 0.0)
+        || tile.gases.get(GAS_OXYGEN) <= REACTION_SIGNIFICANCE_MOLES
     {
         // Hotspot can't sustain combustion.
         tile.thermal_energy += hotspot_extra_thermal_energy;
@@ -0,0 +1,560 @@
+//! Saving and loading the whole atmos model to and from disk.
+//!
+//! Snapshots are little-endian binary, laid out as:
+//! * The magic bytes `MILLASNP`, then the format version as a u32.
+//! * Before version 3, the map size as a u32, shared by every Z level.
+//! * The number of gases as a u32, then each gas's ID as a string.
+//! * The number of environments as a u32, then each environment as a tile. Environments that are
+//!   changing gradually are saved as they are right now, and stop changing once loaded.
+//! * The number of Z levels as a u32, then for each Z level:
+//!   * Since version 3, its width and height as u32s.
+//!   * Its frozen flag as a u8.
+//!   * Every tile, in index order.
+//! * Since version 2, the number of Z connections as a u32, then each one as a (lower, upper) pair
+//!   of u32s.
+//!
+//! Strings are a u32 length followed by that many bytes of UTF-8.
+//! Tiles are:
+//! * Airtight directions as a u8.
+//! * Atmos mode as a u8, then the environment ID as a u16. Before version 6, it was a u8.
+//! * Moles of each gas as an f32, in the order of the snapshot's gas list.
+//! * Thermal energy, superconductivity north, east, south and west, innate heat capacity,
+//!   hotspot temperature, hotspot volume, wind X, wind Y and fuel burnt, all as f32s.
+//! * Since version 4, innate thermal energy as an f32. Before that, the tile and its air shared
+//!   one temperature, so loading older snapshots splits the thermal energy between them.
+//! * Since version 5, solid fuel as an f32.
+//!
+//! Gases are matched up by ID when loading, so snapshots survive gases being registered in a
+//! different order. Walls and gas flow are recalculated every tick, so they aren't saved.
+use crate::milla::constants::*;
+use crate::milla::gases::GasRegistry;
+use crate::milla::model::*;
+use eyre::eyre;
+use eyre::Result;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Identifies a file as a MILLA snapshot.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MILLASNP";
+
+/// The current snapshot format version. Bump this whenever the layout changes.
+const SNAPSHOT_VERSION: u32 = 6;
+
+/// The oldest snapshot format version we can still load.
+const MIN_SNAPSHOT_VERSION: u32 = 1;
+
+/// Writes the active buffer and environments of `buffers` to `writer`.
+pub(crate) fn save(
+    buffers: &Buffers,
+    registry: &GasRegistry,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let gas_count = registry.count();
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    write_u32(writer, SNAPSHOT_VERSION)?;
+
+    write_u32(writer, gas_count as u32)?;
+    for info in registry.all() {
+        write_string(writer, &info.id)?;
+    }
+
+    let environments = buffers.environments.read().unwrap();
+    write_u32(writer, environments.len() as u32)?;
+    for environment in environments.iter() {
+        write_tile(writer, &environment.tile, gas_count)?;
+    }
+
+    let active = buffers.get_active().read().unwrap();
+    write_u32(writer, active.0.len() as u32)?;
+    for z_level_lock in &active.0 {
+        let z_level = z_level_lock.read().unwrap();
+        write_u32(writer, z_level.width() as u32)?;
+        write_u32(writer, z_level.height() as u32)?;
+        writer.write_all(&[z_level.frozen as u8])?;
+        for index in 0..z_level.tile_count() {
+            write_tile(writer, z_level.get_tile(index), gas_count)?;
+        }
+    }
+
+    let z_connections = buffers.z_connections.read().unwrap();
+    write_u32(writer, z_connections.len() as u32)?;
+    for (lower, upper) in z_connections.iter() {
+        write_u32(writer, *lower as u32)?;
+        write_u32(writer, *upper as u32)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Replaces the contents of `buffers` with a snapshot read from `reader`.
+///
+/// The snapshot is decoded into the inactive buffer, which is then flipped to active, so a bad
+/// snapshot leaves the current model untouched. Z levels that exist in `buffers` but not in the
+/// snapshot are reset to space.
+pub(crate) fn load(
+    buffers: &Buffers,
+    registry: &GasRegistry,
+    reader: &mut impl Read,
+) -> Result<()> {
+    let mut magic = [0; SNAPSHOT_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(eyre!("Not a MILLA snapshot."));
+    }
+    let version = read_u32(reader)?;
+    if !(MIN_SNAPSHOT_VERSION..=SNAPSHOT_VERSION).contains(&version) {
+        return Err(eyre!(
+            "Unsupported snapshot version {}, expected {} to {}.",
+            version,
+            MIN_SNAPSHOT_VERSION,
+            SNAPSHOT_VERSION
+        ));
+    }
+    let mut legacy_map_size = None;
+    if version < 3 {
+        legacy_map_size = Some(read_u32(reader)? as usize);
+    }
+
+    let gas_count = read_u32(reader)? as usize;
+    if gas_count > MAX_GAS_COUNT {
+        return Err(eyre!("Snapshot has too many gases: {}", gas_count));
+    }
+    let mut gas_map = Vec::with_capacity(gas_count);
+    for _ in 0..gas_count {
+        let id = read_string(reader)?;
+        gas_map.push(registry.find(&id).ok_or(eyre!(
+            "Snapshot contains gas {}, which isn't registered.",
+            id
+        ))?);
+    }
+
+    let environment_count = read_u32(reader)? as usize;
+    if environment_count > MAX_ENVIRONMENTS {
+        return Err(eyre!(
+            "Snapshot has too many environments: {}",
+            environment_count
+        ));
+    }
+    let mut environments = Vec::with_capacity(environment_count);
+    for _ in 0..environment_count {
+        let mut environment = Tile::new();
+        read_tile(reader, &mut environment, &gas_map, version)?;
+        environment.gases.recalculate();
+        environments.push(environment);
+    }
+
+    let z_count = read_u32(reader)? as usize;
+    if z_count > MAX_Z_LEVELS as usize {
+        return Err(eyre!("Snapshot has too many Z levels: {}", z_count));
+    }
+
+    // Read everything before touching the buffers, so a bad snapshot changes nothing.
+    let mut z_levels = Vec::with_capacity(z_count);
+    let mut total_tiles = 0;
+    for z in 0..z_count {
+        let (width, height) = match legacy_map_size {
+            Some(map_size) => (map_size, map_size),
+            None => (read_u32(reader)? as usize, read_u32(reader)? as usize),
+        };
+        total_tiles += width * height;
+        if width > MAX_MAP_SIZE || height > MAX_MAP_SIZE || total_tiles > MAX_TOTAL_TILES {
+            return Err(eyre!(
+                "Snapshot Z level {} is too big at {}x{}.",
+                z + 1,
+                width,
+                height
+            ));
+        }
+        let mut z_level = ZLevel::new(width, height);
+        let mut frozen = [0];
+        reader.read_exact(&mut frozen)?;
+        z_level.frozen = frozen[0] != 0;
+        for index in 0..z_level.tile_count() {
+            read_tile(reader, z_level.get_tile_mut(index), &gas_map, version)?;
+        }
+        z_levels.push(z_level);
+    }
+
+    let mut z_connections = Vec::new();
+    if version >= 2 {
+        let connection_count = read_u32(reader)? as usize;
+        if connection_count > z_count {
+            return Err(eyre!(
+                "Snapshot has too many Z connections: {}",
+                connection_count
+            ));
+        }
+        for _ in 0..connection_count {
+            let lower = read_u32(reader)? as usize;
+            let upper = read_u32(reader)? as usize;
+            if lower >= z_count || upper >= z_count {
+                return Err(eyre!(
+                    "Snapshot connects missing Z levels {} and {}.",
+                    lower + 1,
+                    upper + 1
+                ));
+            }
+            z_connections.push((lower, upper));
+        }
+    }
+
+    // Hold the queue until we're done, so a tick can't start halfway through.
+    let queue = buffers.write_queue.lock().unwrap();
+    let maybe_active = buffers.get_active().try_write();
+    let maybe_inactive = buffers.get_inactive().try_write();
+    if queue.ticking || maybe_active.is_err() || maybe_inactive.is_err() {
+        return Err(eyre!(
+            "Tried to load a snapshot during asynchronous, read-only atmos. Use a /datum/milla_safe/..."
+        ));
+    }
+    let mut active = maybe_active.unwrap();
+    let mut inactive = maybe_inactive.unwrap();
+    while active.0.len() < z_count {
+        active.0.push(RwLock::new(ZLevel::new(0, 0)));
+    }
+    while inactive.0.len() < z_count {
+        inactive.0.push(RwLock::new(ZLevel::new(0, 0)));
+    }
+    let mut z_levels = z_levels.into_iter();
+    for z_level_lock in inactive.0.iter_mut() {
+        let z_level = z_level_lock.get_mut().unwrap();
+        match z_levels.next() {
+            Some(loaded) => *z_level = loaded,
+            None => {
+                z_level.frozen = false;
+                for index in 0..z_level.tile_count() {
+                    *z_level.get_tile_mut(index) = Tile::new();
+                }
+            }
+        }
+        z_level.active_pressure_chunks.clear();
+        // None of this matches the other buffer any more, and all of it needs simulating.
+        z_level.wake_all();
+    }
+
+    buffers.environments.write().unwrap().replace(environments);
+    *buffers.z_connections.write().unwrap() = z_connections;
+    buffers.zones.write().unwrap().reset_all();
+    drop(active);
+    drop(inactive);
+    buffers.swap(&queue);
+    Ok(())
+}
+
+/// Saves `buffers` to a snapshot file at `path`.
+pub(crate) fn save_to_file(buffers: &Buffers, registry: &GasRegistry, path: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    save(buffers, registry, &mut writer)
+}
+
+/// Loads a snapshot file at `path` into `buffers`.
+pub(crate) fn load_from_file(buffers: &Buffers, registry: &GasRegistry, path: &Path) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    load(buffers, registry, &mut reader)
+}
+
+/// Loads a snapshot file into a fresh set of buffers, for replaying real rounds in tests and
+/// benchmarks.
+pub(crate) fn load_buffers(registry: &GasRegistry, path: &Path) -> Result<Buffers> {
+    let buffers = Buffers::new();
+    load_from_file(&buffers, registry, path)?;
+    Ok(buffers)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn write_tile(writer: &mut impl Write, tile: &Tile, gas_count: usize) -> Result<()> {
+    let (mode, environment_id) = match tile.mode {
+        AtmosMode::Space => (0, 0),
+        AtmosMode::Sealed => (1, 0),
+        AtmosMode::ExposedTo { environment_id } => (2, environment_id),
+        AtmosMode::NoDecay => (3, 0),
+    };
+    writer.write_all(&[tile.airtight_directions.bits(), mode])?;
+    writer.write_all(&environment_id.to_le_bytes())?;
+    for gas in 0..gas_count {
+        write_f32(writer, tile.gases.get(gas))?;
+    }
+    for value in [
+        tile.thermal_energy,
+        tile.superconductivity.north,
+        tile.superconductivity.east,
+        tile.superconductivity.south,
+        tile.superconductivity.west,
+        tile.innate_heat_capacity,
+        tile.hotspot_temperature,
+        tile.hotspot_volume,
+        tile.wind[AXIS_X],
+        tile.wind[AXIS_Y],
+        tile.fuel_burnt,
+        tile.innate_thermal_energy,
+        tile.solid_fuel,
+    ] {
+        write_f32(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let length = read_u32(reader)? as usize;
+    if length > u8::MAX as usize {
+        return Err(eyre!("Suspiciously long string in snapshot: {}", length));
+    }
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Reads a tile into `tile`, mapping the snapshot's gases to registry indices with `gas_map`.
+fn read_tile(
+    reader: &mut impl Read,
+    tile: &mut Tile,
+    gas_map: &[usize],
+    version: u32,
+) -> Result<()> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let [airtight, mode] = header;
+    let environment_id = if version >= 6 {
+        let mut bytes = [0; 2];
+        reader.read_exact(&mut bytes)?;
+        u16::from_le_bytes(bytes)
+    } else {
+        let mut bytes = [0; 1];
+        reader.read_exact(&mut bytes)?;
+        bytes[0] as u16
+    };
+    tile.airtight_directions = AirtightDirections::from_bits_truncate(airtight);
+    tile.mode = match mode {
+        0 => AtmosMode::Space,
+        1 => AtmosMode::Sealed,
+        2 => AtmosMode::ExposedTo { environment_id },
+        3 => AtmosMode::NoDecay,
+        _ => return Err(eyre!("Invalid atmos mode in snapshot: {}", mode)),
+    };
+    tile.gases.clear();
+    for gas in gas_map {
+        tile.gases.set(*gas, read_f32(reader)?);
+    }
+    tile.thermal_energy = read_f32(reader)?;
+    tile.superconductivity.north = read_f32(reader)?;
+    tile.superconductivity.east = read_f32(reader)?;
+    tile.superconductivity.south = read_f32(reader)?;
+    tile.superconductivity.west = read_f32(reader)?;
+    tile.innate_heat_capacity = read_f32(reader)?;
+    tile.hotspot_temperature = read_f32(reader)?;
+    tile.hotspot_volume = read_f32(reader)?;
+    tile.wind[AXIS_X] = read_f32(reader)?;
+    tile.wind[AXIS_Y] = read_f32(reader)?;
+    tile.fuel_burnt = read_f32(reader)?;
+    if version >= 4 {
+        tile.innate_thermal_energy = read_f32(reader)?;
+    } else {
+        // The thermal energy used to cover both the tile and its air, at the same temperature.
+        let total_heat_capacity = tile.heat_capacity() + tile.innate_heat_capacity;
+        let temperature = if total_heat_capacity > 0.0 {
+            tile.thermal_energy / total_heat_capacity
+        } else {
+            0.0
+        };
+        tile.thermal_energy = tile.heat_capacity() * temperature;
+        tile.innate_thermal_energy = tile.innate_heat_capacity * temperature;
+    }
+    tile.solid_fuel = if version >= 5 { read_f32(reader)? } else { 0.0 };
+    Ok(())
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::tick;
+
+    /// Builds a small, arbitrary model to save.
+    fn example_buffers() -> Buffers {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 10, 8).unwrap();
+        buffers.init_z_level(1, 6, 6).unwrap();
+        let mut air = Tile::new();
+        air.gases.set(GAS_OXYGEN, 20.0);
+        air.gases.set(GAS_NITROGEN, 80.0);
+        air.thermal_energy = air.heat_capacity() * T20C;
+        buffers.create_environment(air).unwrap();
+
+        let active = buffers.get_active().read().unwrap();
+        {
+            let mut z_level = active.0[0].write().unwrap();
+            for x in 1..4 {
+                for y in 1..4 {
+                    let index = z_level.maybe_get_index(x, y).unwrap();
+                    let tile = z_level.get_tile_mut(index);
+                    tile.mode = AtmosMode::Sealed;
+                    tile.gases.set(GAS_TOXINS, x as f32);
+                    tile.gases.set(GAS_OXYGEN, y as f32);
+                    tile.thermal_energy = 1000.0 * (x + y) as f32;
+                    tile.superconductivity.north = 0.1;
+                    tile.hotspot_temperature = 500.0;
+                    tile.hotspot_volume = 0.5;
+                    tile.innate_heat_capacity = 100.0;
+                    tile.innate_thermal_energy = 30000.0 * x as f32;
+                    tile.solid_fuel = y as f32;
+                }
+            }
+            let index = z_level.maybe_get_index(5, 5).unwrap();
+            let tile = z_level.get_tile_mut(index);
+            tile.mode = AtmosMode::ExposedTo { environment_id: 0 };
+            tile.airtight_directions = AirtightDirections::NORTH | AirtightDirections::WEST;
+        }
+        active.0[1].write().unwrap().frozen = true;
+        drop(active);
+        buffers.set_z_connected(0, 1, true).unwrap();
+        buffers
+    }
+
+    /// Asserts that the active buffers of `a` and `b` hold the same tiles.
+    fn assert_same(a: &Buffers, b: &Buffers) {
+        let a_active = a.get_active().read().unwrap();
+        let b_active = b.get_active().read().unwrap();
+        assert_eq!(a_active.0.len(), b_active.0.len());
+        for z in 0..a_active.0.len() {
+            let a_level = a_active.0[z].read().unwrap();
+            let b_level = b_active.0[z].read().unwrap();
+            assert_eq!(a_level.frozen, b_level.frozen, "z {}", z);
+            assert_eq!(a_level.width(), b_level.width(), "z {}", z);
+            assert_eq!(a_level.height(), b_level.height(), "z {}", z);
+            for index in 0..a_level.tile_count() {
+                let a_tile = a_level.get_tile(index);
+                let b_tile = b_level.get_tile(index);
+                assert_eq!(a_tile.airtight_directions, b_tile.airtight_directions);
+                assert_eq!(a_tile.mode, b_tile.mode, "{}, {}", z, index);
+                assert_eq!(a_tile.gases.values, b_tile.gases.values, "{}, {}", z, index);
+                assert_eq!(a_tile.thermal_energy, b_tile.thermal_energy);
+                assert_eq!(
+                    a_tile.superconductivity.north,
+                    b_tile.superconductivity.north
+                );
+                assert_eq!(a_tile.innate_heat_capacity, b_tile.innate_heat_capacity);
+                assert_eq!(a_tile.innate_thermal_energy, b_tile.innate_thermal_energy);
+                assert_eq!(a_tile.hotspot_temperature, b_tile.hotspot_temperature);
+                assert_eq!(a_tile.hotspot_volume, b_tile.hotspot_volume);
+                assert_eq!(a_tile.wind, b_tile.wind);
+                assert_eq!(a_tile.fuel_burnt, b_tile.fuel_burnt);
+                assert_eq!(a_tile.solid_fuel, b_tile.solid_fuel);
+            }
+        }
+    }
+
+    // A saved model should load back identically, and still tick afterwards.
+    #[test]
+    fn round_trip() {
+        let registry = GasRegistry::new();
+        let original = example_buffers();
+        let mut bytes = Vec::new();
+        save(&original, &registry, &mut bytes).unwrap();
+
+        let loaded = Buffers::new();
+        load(&loaded, &registry, &mut bytes.as_slice()).unwrap();
+        assert_same(&original, &loaded);
+        assert_eq!(loaded.environments.read().unwrap().len(), 1);
+        assert_eq!(*loaded.z_connections.read().unwrap(), vec![(0, 1)]);
+
+        tick::tick(&loaded).unwrap();
+        let active = loaded.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        let tile = z_level.get_tile(z_level.maybe_get_index(5, 5).unwrap());
+        assert_eq!(tile.gases.get(GAS_OXYGEN), 20.0);
+        assert_eq!(tile.gases.get(GAS_NITROGEN), 80.0);
+    }
+
+    // Loading a snapshot should reset Z levels it doesn't cover.
+    #[test]
+    fn extra_z_levels_reset() {
+        let registry = GasRegistry::new();
+        let small = Buffers::new();
+        small.init_z_level(0, 4, 4).unwrap();
+        let mut bytes = Vec::new();
+        save(&small, &registry, &mut bytes).unwrap();
+
+        let big = example_buffers();
+        load(&big, &registry, &mut bytes.as_slice()).unwrap();
+        let active = big.get_active().read().unwrap();
+        assert_eq!(active.0.len(), 2);
+        assert_eq!(active.0[0].read().unwrap().width(), 4);
+        let z_level = active.0[1].read().unwrap();
+        assert!(!z_level.frozen);
+    }
+
+    // Loading in the middle of a tick should be refused, rather than ending the tick early.
+    #[test]
+    fn refuses_during_tick() {
+        let registry = GasRegistry::new();
+        let original = example_buffers();
+        let mut bytes = Vec::new();
+        save(&original, &registry, &mut bytes).unwrap();
+
+        let target = example_buffers();
+        target.begin_tick();
+        assert!(load(&target, &registry, &mut bytes.as_slice()).is_err());
+        assert!(target.write_queue.lock().unwrap().ticking);
+        target.cancel_tick();
+        load(&target, &registry, &mut bytes.as_slice()).unwrap();
+        assert!(!target.write_queue.lock().unwrap().ticking);
+    }
+
+    // Bad snapshots should be rejected without touching the model.
+    #[test]
+    fn bad_snapshots() {
+        let registry = GasRegistry::new();
+        let original = example_buffers();
+        let mut bytes = Vec::new();
+        save(&original, &registry, &mut bytes).unwrap();
+
+        let target = example_buffers();
+        assert!(load(&target, &registry, &mut &b"NOTMILLA"[..]).is_err());
+        assert!(load(&target, &registry, &mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(load(&target, &registry, &mut &bytes[..bytes.len() / 2]).is_err());
+        let mut wrong_version = bytes.clone();
+        wrong_version[SNAPSHOT_MAGIC.len()] = 99;
+        assert!(load(&target, &registry, &mut wrong_version.as_slice()).is_err());
+        assert_same(&original, &target);
+
+        // The inactive buffer shouldn't have been half-written either.
+        let inactive = target.get_inactive().read().unwrap();
+        assert_eq!(inactive.0.len(), 2);
+        let z_level = inactive.0[0].read().unwrap();
+        for index in 0..z_level.tile_count() {
+            assert_eq!(
+                z_level.get_tile(index).gases.values,
+                Tile::new().gases.values
+            );
+        }
+    }
+}
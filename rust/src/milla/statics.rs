@@ -1,15 +1,61 @@
+use crate::milla::audit::ZLevelAudit;
+use crate::milla::explosion::ExplosionTile;
+use crate::milla::gases::GasRegistry;
+use crate::milla::mixture::GasMixtures;
 use crate::milla::model::*;
-use std::sync::{atomic::AtomicUsize, Mutex, OnceLock};
+use crate::milla::reactions::{self, Reaction};
+use crate::milla::telemetry::TickTelemetry;
+use crate::milla::watch::WatchEvent;
+use std::sync::{atomic::AtomicBool, atomic::AtomicUsize, Arc, Mutex, OnceLock, RwLock};
+use std::time::Instant;
 
 /// The buffers that contain the atmos model.
 /// OnceLock means we only ever set this once, and it's read-only after that.
 /// (The RwLocks inside it are what let us modify the model anyway.)
 pub(crate) static BUFFERS: OnceLock<Buffers> = OnceLock::new();
 
+/// The gases we know how to simulate.
+/// Starts with the builtin gases, BYOND may add more during init.
+pub(crate) static GAS_REGISTRY: OnceLock<GasRegistry> = OnceLock::new();
+
+/// Fetches the gas registry, creating it with the builtin gases if needed.
+pub(crate) fn gas_registry() -> &'static GasRegistry {
+    GAS_REGISTRY.get_or_init(GasRegistry::new)
+}
+
+/// The reactions we run every tick, in priority order.
+/// Starts with the builtin reactions, BYOND may replace them with its own definitions.
+/// Each tick grabs a copy of the Arc, so replacing them never affects a tick in progress.
+pub(crate) static REACTIONS: OnceLock<RwLock<Arc<Vec<Reaction>>>> = OnceLock::new();
+
+/// Fetches the current reactions, loading the builtin ones if needed.
+pub(crate) fn current_reactions() -> &'static RwLock<Arc<Vec<Reaction>>> {
+    REACTIONS.get_or_init(|| RwLock::new(Arc::new(reactions::default_reactions(gas_registry()))))
+}
+
+/// Gas mixtures BYOND is using outside the map, like in tanks and lungs.
+/// Only BYOND touches these, so they aren't part of the buffers.
+pub(crate) static GAS_MIXTURES: Mutex<GasMixtures> = Mutex::new(GasMixtures::new());
+
 /// The current set of interesting tiles.
 /// We only write this once per tick, and only read it on user input.
 pub(crate) static INTERESTING_TILES: Mutex<Vec<InterestingTile>> = Mutex::new(Vec::new());
 
+/// The watch rules that fired during the last tick.
+/// Written once per tick, like INTERESTING_TILES.
+pub(crate) static WATCH_EVENTS: Mutex<Vec<WatchEvent>> = Mutex::new(Vec::new());
+
+/// What audit mode found during the last tick, for each Z level.
+pub(crate) static AUDIT_REPORTS: Mutex<Vec<ZLevelAudit>> = Mutex::new(Vec::new());
+
+/// Explosions that finished working out, waiting for BYOND to collect them, by explosion ID.
+/// Also records when each one finished, so ones BYOND never collects can be thrown away.
+pub(crate) static EXPLOSIONS: Mutex<Vec<(usize, Instant, Vec<ExplosionTile>)>> =
+    Mutex::new(Vec::new());
+
+/// The ID the next explosion will get.
+pub(crate) static NEXT_EXPLOSION_ID: AtomicUsize = AtomicUsize::new(1);
+
 /// The current set of tiles BYOND wants the pressure of.
 /// Written to via BYOND call.
 /// Read from and cleared via BYOND call.
@@ -17,3 +63,18 @@ pub(crate) static TRACKED_PRESSURE_TILES: Mutex<Vec<(i32, i32, usize)>> = Mutex:
 
 /// How long the last tick took, in milliseconds.
 pub(crate) static TICK_TIME: AtomicUsize = AtomicUsize::new(0);
+
+/// How many ticks have finished, used to number telemetry.
+pub(crate) static TICK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Telemetry from the last tick, if there's been one.
+pub(crate) static TICK_TELEMETRY: Mutex<Option<TickTelemetry>> = Mutex::new(None);
+
+/// Whether each tick's telemetry should be appended to the telemetry log.
+pub(crate) static TELEMETRY_LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How many tiles the last tick simulated.
+pub(crate) static TICK_ACTIVE_TILES: AtomicUsize = AtomicUsize::new(0);
+
+/// How many tiles the last tick skipped, because nothing was happening near them.
+pub(crate) static TICK_SKIPPED_TILES: AtomicUsize = AtomicUsize::new(0);
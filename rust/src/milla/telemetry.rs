@@ -0,0 +1,197 @@
+//! Per-tick telemetry, for keeping an eye on how MILLA performs over a round.
+//!
+//! Each tick produces one TickTelemetry, which BYOND can fetch as JSON, and which can also be
+//! appended to a log file, one JSON object per line. The log is rotated once it gets too big.
+use crate::logging;
+use crate::milla::constants::*;
+use crate::milla::tick::ZLevelStats;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What happened while ticking a single Z level, in a form that's friendly to serialize.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ZLevelTelemetry {
+    /// Which Z level this is, 1-indexed like BYOND.
+    pub(crate) z: i32,
+    /// How long the whole Z level took, in milliseconds.
+    pub(crate) total_ms: f64,
+    /// How long each phase took, in milliseconds.
+    pub(crate) find_walls_ms: f64,
+    pub(crate) update_wind_ms: f64,
+    pub(crate) flow_air_ms: f64,
+    pub(crate) post_process_ms: f64,
+    /// How many Gauss-Seidel iterations flow_air used.
+    pub(crate) flow_iterations: usize,
+    /// Whether flow_air gave up at MAX_ITERATIONS.
+    pub(crate) hit_max_iterations: bool,
+    pub(crate) sanitized_tiles: usize,
+    pub(crate) active_chunks: usize,
+    pub(crate) active_tiles: usize,
+    pub(crate) skipped_tiles: usize,
+    pub(crate) interesting_tiles: usize,
+}
+
+impl From<&ZLevelStats> for ZLevelTelemetry {
+    fn from(stats: &ZLevelStats) -> Self {
+        ZLevelTelemetry {
+            z: stats.z + 1,
+            total_ms: millis(stats.duration),
+            find_walls_ms: millis(stats.find_walls_duration),
+            update_wind_ms: millis(stats.update_wind_duration),
+            flow_air_ms: millis(stats.flow_air_duration),
+            post_process_ms: millis(stats.post_process_duration),
+            flow_iterations: stats.flow_iterations,
+            hit_max_iterations: stats.hit_max_iterations,
+            sanitized_tiles: stats.sanitized_tiles,
+            active_chunks: stats.active_chunks,
+            active_tiles: stats.active_tiles,
+            skipped_tiles: stats.skipped_tiles,
+            interesting_tiles: stats.interesting_tiles,
+        }
+    }
+}
+
+/// Everything we know about a single tick.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct TickTelemetry {
+    /// Counts up by one every tick, so gaps in the log show dropped ticks.
+    pub(crate) tick: usize,
+    /// When the tick finished, as an RFC 3339 timestamp.
+    pub(crate) finished_at: String,
+    /// How long the whole tick took, in milliseconds.
+    pub(crate) total_ms: f64,
+    /// How many writes BYOND made during the tick, which waited until it finished.
+    pub(crate) queued_writes: usize,
+    pub(crate) z_levels: Vec<ZLevelTelemetry>,
+}
+
+impl TickTelemetry {
+    pub(crate) fn new(
+        tick: usize,
+        finished_at: String,
+        duration: Duration,
+        queued_writes: usize,
+        stats: &[ZLevelStats],
+    ) -> Self {
+        TickTelemetry {
+            tick,
+            finished_at,
+            total_ms: millis(duration),
+            queued_writes,
+            z_levels: stats.iter().map(ZLevelTelemetry::from).collect(),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        // Nothing in here can fail to serialize.
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Where rotated log number `generation` lives. Generation 0 is the current log.
+fn rotated_path(path: &Path, generation: usize) -> PathBuf {
+    if generation == 0 {
+        return path.to_path_buf();
+    }
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", generation));
+    PathBuf::from(rotated)
+}
+
+/// Appends a line to the log at `path`, first rotating it if it would grow past `max_bytes`.
+/// Rotated logs get .1, .2 and so on appended to their name, with .1 the most recent, and only
+/// `max_files` of them are kept.
+pub(crate) fn append_to_rotating_log(
+    path: &Path,
+    line: &str,
+    max_bytes: u64,
+    max_files: usize,
+) -> std::io::Result<()> {
+    let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if size > 0 && size + line.len() as u64 + 1 > max_bytes {
+        let _ = fs::remove_file(rotated_path(path, max_files));
+        for generation in (0..max_files).rev() {
+            let from = rotated_path(path, generation);
+            if from.exists() {
+                fs::rename(from, rotated_path(path, generation + 1))?;
+            }
+        }
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Appends a tick's telemetry to the telemetry log, reporting any failure as a BYOND stack trace.
+pub(crate) fn log_telemetry(telemetry: &TickTelemetry) -> eyre::Result<()> {
+    if let Err(err) = append_to_rotating_log(
+        Path::new("data/milla_telemetry.log"),
+        &telemetry.to_json(),
+        TELEMETRY_LOG_MAX_BYTES,
+        TELEMETRY_LOG_FILES,
+    ) {
+        logging::dm_call_stack_trace(format!("Failed to write MILLA telemetry log: {}", err))?;
+    }
+    Ok(())
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Telemetry should come out as JSON with the fields BYOND expects.
+    #[test]
+    fn json() {
+        let stats = ZLevelStats {
+            z: 2,
+            flow_iterations: MAX_ITERATIONS,
+            hit_max_iterations: true,
+            flow_air_duration: Duration::from_millis(3),
+            ..Default::default()
+        };
+        let telemetry =
+            TickTelemetry::new(7, "now".to_string(), Duration::from_millis(5), 2, &[stats]);
+        let json: serde_json::Value = serde_json::from_str(&telemetry.to_json()).unwrap();
+        assert_eq!(json["tick"], 7);
+        assert_eq!(json["total_ms"], 5.0);
+        assert_eq!(json["queued_writes"], 2);
+        assert_eq!(json["z_levels"][0]["z"], 3);
+        assert_eq!(json["z_levels"][0]["flow_air_ms"], 3.0);
+        assert_eq!(json["z_levels"][0]["hit_max_iterations"], true);
+    }
+
+    // Logs should rotate once they're full, keeping only so many old ones.
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("milla_telemetry_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("telemetry.log");
+
+        for line in ["one", "two", "three", "four", "five"] {
+            append_to_rotating_log(&path, line, 8, 2).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "five\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "four\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "three\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
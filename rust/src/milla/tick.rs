@@ -1,79 +1,238 @@
+use crate::milla::audit::{AuditPhase, Totals, ZLevelAudit};
 use crate::milla::model::*;
 use crate::milla::simulate;
 use crate::milla::statics::*;
 use eyre;
 use scc::Bag;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::RwLock;
-use std::thread;
-use std::thread::ScopedJoinHandle;
+use std::time::{Duration, Instant};
 use thread_priority;
 
-/// Runs a single tick of the atmospherics model, multi-threaded by Z level.
-pub(crate) fn tick(buffers: &Buffers) -> Result<(), eyre::Error> {
+/// What happened while ticking a single Z level.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ZLevelStats {
+    /// Which Z level this is, 0-indexed.
+    pub(crate) z: i32,
+    /// How long the Z level took to tick.
+    pub(crate) duration: Duration,
+    /// How long finding walls took.
+    pub(crate) find_walls_duration: Duration,
+    /// How long updating the wind took.
+    pub(crate) update_wind_duration: Duration,
+    /// How long flow_air took.
+    pub(crate) flow_air_duration: Duration,
+    /// How long post_process took.
+    pub(crate) post_process_duration: Duration,
+    /// How many iterations flow_air needed, out of MAX_ITERATIONS.
+    pub(crate) flow_iterations: usize,
+    /// Whether flow_air gave up at MAX_ITERATIONS before the air stabilized.
+    pub(crate) hit_max_iterations: bool,
+    /// How many tiles had to be sanitized.
+    pub(crate) sanitized_tiles: usize,
+    /// How many tiles were interesting.
+    pub(crate) interesting_tiles: usize,
+    /// How many tiles were simulated.
+    pub(crate) active_tiles: usize,
+    /// How many tiles were skipped, because nothing was happening near them.
+    pub(crate) skipped_tiles: usize,
+    /// How many chunks were simulated.
+    pub(crate) active_chunks: usize,
+    /// Where gas and heat came from and went, if audit mode is on.
+    pub(crate) audit: Option<ZLevelAudit>,
+}
+
+impl ZLevelStats {
+    /// Adds in what happened in one strip of the Z level.
+    fn add_strip(&mut self, strip: ZLevelStats) {
+        self.sanitized_tiles += strip.sanitized_tiles;
+        self.interesting_tiles += strip.interesting_tiles;
+        if let (Some(audit), Some(strip_audit)) = (&mut self.audit, strip.audit) {
+            audit.merge(strip_audit);
+        }
+    }
+}
+
+/// Runs a single tick of the atmospherics model on the worker pool.
+/// Z levels tick in parallel, and each Z level is split into strips that also run in parallel.
+/// Returns stats for each Z level, in Z order.
+pub(crate) fn tick(buffers: &Buffers) -> Result<Vec<ZLevelStats>, eyre::Error> {
     assert!(thread_priority::ThreadPriority::Min
         .set_for_current()
         .is_ok());
+    let pool = buffers.worker_pool()?;
+    // From here until the flip, BYOND's writes wait in the queue.
+    buffers.begin_tick();
+    // Environments only change between ticks. Exposed tiles that are asleep would never notice,
+    // so wake them up.
+    let changed_environments = buffers.environments.write().unwrap().advance();
+    if !changed_environments.is_empty() {
+        let active = buffers.get_active().read().unwrap();
+        for z_level in active.0.iter() {
+            z_level
+                .write()
+                .unwrap()
+                .wake_exposed_to(&changed_environments);
+        }
+    }
     let prev = buffers.get_active().read().unwrap();
     let next = buffers.get_inactive().read().unwrap();
 
     let new_interesting_tiles: Bag<InterestingTile> = Bag::default();
     let mut result: eyre::Result<()> = Ok(());
-    let handle_results: RwLock<Vec<eyre::Result<()>>> = RwLock::new(Vec::new());
+    let handle_results: RwLock<Vec<eyre::Result<ZLevelStats>>> = RwLock::new(Vec::new());
+    let mut stats: Vec<ZLevelStats> = Vec::new();
 
-    // The scope tells Rust that all the threads we create here will end by the time the scope
-    // closes. This allows us to pass things into them that are only borrowed for the lifetime of
-    // this function.
-    thread::scope(|s| {
+    // The scope tells Rust that all the jobs we start here will end by the time the scope closes.
+    // This allows us to pass things into them that are only borrowed for the lifetime of this
+    // function.
+    pool.scope(|s| {
         // Force most things to be captured by reference, despite the `move`
-        // in the thread spawn, which is really just for `z`.
+        // in the spawn, which is really just for `z`.
         let prev = &prev;
         let next = &next;
         let new_interesting_tiles = &new_interesting_tiles;
 
-        // Handle each Z level in its own thread.
-        let mut handles = Vec::<ScopedJoinHandle<()>>::new();
+        // Handle each Z level as its own job.
         let handle_results = &handle_results;
         for z in 0..prev.0.len() {
-            handles.push(s.spawn(move || {
-                assert!(thread_priority::ThreadPriority::Min
-                    .set_for_current()
-                    .is_ok());
-                let result = tick_z_level(
-                    buffers,
-                    &prev.0[z],
-                    &next.0[z],
-                    z as i32,
-                    &new_interesting_tiles,
-                );
+            s.spawn(move |_| {
+                // The scope would re-raise a panic in this thread, skipping cancel_tick() and
+                // leaving BYOND's writes queued forever, so it's turned into a failed Z level.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    tick_z_level(
+                        buffers,
+                        &prev.0[z],
+                        &next.0[z],
+                        z as i32,
+                        new_interesting_tiles,
+                    )
+                }))
+                .unwrap_or_else(|_| Err(eyre::eyre!("Z level {} panicked.", z + 1)));
                 let mut results = handle_results.write().unwrap();
                 results.push(result);
-            }));
-        }
-        for handle in handles {
-            let _ = handle.join();
-        }
-        let readable_results = handle_results.read().unwrap();
-        for index in 0..readable_results.len() {
-            if readable_results[index].is_err() {
-                result = Err(eyre::eyre!(
-                    "MILLA worker thread failed: {:#?}",
-                    readable_results[index].as_ref().err()
-                ));
-            }
+            });
         }
     });
+    for z_result in handle_results.into_inner().unwrap() {
+        match z_result {
+            Ok(z_stats) => stats.push(z_stats),
+            Err(err) => {
+                result = Err(eyre::eyre!("MILLA worker thread failed: {:#?}", err));
+            }
+        }
+    }
+
+    if let Err(err) = result {
+        drop(prev);
+        drop(next);
+        buffers.cancel_tick();
+        return Err(err);
+    }
 
-    result?;
+    // Gas machinery and vertical flow both happen after post-processing, so the tiles they touch
+    // get settled separately.
+    let mut touched: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
 
+    // Pipes can reach across Z levels, so gas machinery waits until they're all done, and runs
+    // in a fixed order.
+    for (z, index) in buffers.pipenets.write().unwrap().process(&next) {
+        touched.entry(z).or_default().insert(index);
+    }
+
+    // Vertical flow crosses Z levels too, and runs in a fixed order to keep the results
+    // consistent.
+    let z_connections = buffers.z_connections.read().unwrap().clone();
+    for (lower, upper) in z_connections {
+        if lower >= next.0.len() || upper >= next.0.len() {
+            continue;
+        }
+        if prev.0[lower].read().unwrap().frozen || prev.0[upper].read().unwrap().frozen {
+            continue;
+        }
+        let mut lower_level = next.0[lower].write().unwrap();
+        let mut upper_level = next.0[upper].write().unwrap();
+        let (lower_touched, upper_touched) =
+            simulate::flow_vertical(&mut lower_level, &mut upper_level);
+        touched.entry(lower).or_default().extend(lower_touched);
+        touched.entry(upper).or_default().extend(upper_touched);
+    }
+    let mut settled_coords = HashSet::new();
+    let mut settled_interesting_tiles = Vec::new();
+    for (z, tiles) in touched {
+        let prev_level = prev.0[z].read().unwrap();
+        let tiles: Vec<usize> = tiles.into_iter().collect();
+        for &index in &tiles {
+            let (x, y) = prev_level.get_coords(index);
+            // +1 here to match the BYOND coordinates interesting tiles use.
+            settled_coords.insert((x as i16 + 1, y as i16 + 1, z as i16 + 1));
+        }
+        let mut z_stats = ZLevelStats::default();
+        let settled = simulate::settle_tiles(
+            &prev_level,
+            &mut next.0[z].write().unwrap(),
+            z as i32,
+            &tiles,
+            &mut z_stats,
+        );
+        drop(prev_level);
+        match settled {
+            Ok(interesting) => settled_interesting_tiles.extend(interesting),
+            Err(err) => {
+                drop(prev);
+                drop(next);
+                buffers.cancel_tick();
+                return Err(err);
+            }
+        }
+        if let Some(stats) = stats.iter_mut().find(|stats| stats.z == z as i32) {
+            stats.sanitized_tiles += z_stats.sanitized_tiles;
+        }
+    }
+
+    // Flipping applies the queued writes, which needs the buffers.
+    drop(prev);
+    drop(next);
     buffers.flip();
 
+    // Watch rules check after the flip, so they also see what BYOND's writes changed.
+    let watch_events = buffers
+        .watch_rules
+        .write()
+        .unwrap()
+        .check(&buffers.get_active().read().unwrap());
+    *WATCH_EVENTS.lock().unwrap() = watch_events;
+
+    // The Z levels' threads all add to the bag at once, so it comes out in a different order
+    // every time.
+    let mut new_interesting_tiles: Vec<InterestingTile> =
+        new_interesting_tiles.into_iter().collect();
+    // Settled tiles were checked again after post-processing, and that check has the final say.
+    new_interesting_tiles
+        .retain(|interesting| !settled_coords.contains(&interesting.coords.coordinates()));
+    new_interesting_tiles.extend(settled_interesting_tiles);
+    if buffers.deterministic.load(Relaxed) {
+        sort_interesting_tiles(&mut new_interesting_tiles);
+    }
+
     let mut interesting_tiles = INTERESTING_TILES.lock().unwrap();
     // drake_no: Last tick's interesting tiles.
     interesting_tiles.clear();
     // drake_yes: This tick's interesting tiles.
     interesting_tiles.extend(new_interesting_tiles);
 
-    Ok(())
+    stats.sort_by_key(|z_stats| z_stats.z);
+    Ok(stats)
+}
+
+/// Puts interesting tiles in a fixed order, by Z, then X, then Y.
+pub(crate) fn sort_interesting_tiles(tiles: &mut [InterestingTile]) {
+    tiles.sort_by_key(|tile| {
+        let (x, y, z) = tile.coords.coordinates();
+        (z, x, y)
+    });
 }
 
 /// Runs a single tick of one Z level's atmospherics model.
@@ -83,28 +242,110 @@ pub(crate) fn tick_z_level(
     next_atmos_lock: &RwLock<ZLevel>,
     z: i32,
     new_interesting_tiles: &Bag<InterestingTile>,
-) -> eyre::Result<()> {
+) -> eyre::Result<ZLevelStats> {
+    let start = Instant::now();
+    let mut stats = ZLevelStats {
+        z,
+        ..Default::default()
+    };
     let environments;
     {
         let global_environments = buffers.environments.read().unwrap();
-        environments = global_environments.clone().into_boxed_slice();
+        environments = global_environments.tiles();
     }
+    let reactions = current_reactions().read().unwrap().clone();
+    let emitters = buffers.emitters.read().unwrap().on_z_level(z);
+    let heaters = buffers.heaters.read().unwrap().on_z_level(z);
     let prev = prev_atmos_lock.read().unwrap();
     let mut next = next_atmos_lock.write().unwrap();
 
     // Initialize the new frame as a copy of the old one.
+    // Only the parts that changed since the last tick need copying, the rest already match.
     next.copy_from(&prev);
 
-    if !prev.frozen {
-        simulate::find_walls(&mut next);
-        simulate::update_wind(&prev, &mut next);
-        simulate::flow_air(&prev, &mut next)?;
-        simulate::post_process(&prev, &mut next, &environments, new_interesting_tiles, z)?;
+    if prev.frozen {
+        stats.skipped_tiles = prev.tile_count();
+    } else {
+        let region = prev.active_region();
+        stats.active_tiles = region.tiles.len();
+        stats.skipped_tiles = prev.tile_count() - region.tiles.len();
+        stats.active_chunks = region.chunk_count();
+        next.mark_simulated(&region);
+        if buffers.audit.load(Relaxed) {
+            stats.audit = Some(ZLevelAudit::new(z));
+        }
+        let auditing = stats.audit.is_some();
+        // Each phase runs on strips of the Z level in parallel, see ZLevel::for_each_strip().
+        let strips = next.split_into_strips(&region.tiles);
+
+        let before_wind = stats
+            .audit
+            .as_ref()
+            .map(|_| Totals::of_tiles(region.tiles.iter().map(|&index| next.get_tile(index))));
+        let phase_start = Instant::now();
+        next.for_each_strip(&strips, |next, strip| {
+            simulate::find_walls(next, &strip.tiles)
+        });
+        stats.find_walls_duration = phase_start.elapsed();
+        let phase_start = Instant::now();
+        next.for_each_strip(&strips, |next, strip| {
+            simulate::update_wind(&prev, next, &region, &strip.tiles)
+        });
+        stats.update_wind_duration = phase_start.elapsed();
+        let before_flow = stats
+            .audit
+            .as_ref()
+            .map(|_| Totals::of_tiles(region.tiles.iter().map(|&index| next.get_tile(index))));
+        let deterministic = buffers.deterministic.load(Relaxed);
+        let phase_start = Instant::now();
+        let outcome = simulate::flow_air(&prev, &mut next, &region, deterministic)?;
+        stats.flow_air_duration = phase_start.elapsed();
+        stats.flow_iterations = outcome.iterations;
+        stats.hit_max_iterations = !outcome.converged;
+        if let (Some(audit), Some(before_wind), Some(before_flow)) =
+            (&mut stats.audit, before_wind, before_flow)
+        {
+            let after_flow =
+                Totals::of_tiles(region.tiles.iter().map(|&index| next.get_tile(index)));
+            audit.initial = before_wind;
+            audit.record(AuditPhase::Wind, &before_wind, &before_flow);
+            let boundaries = Totals::of_flow_boundaries(&next, &region);
+            audit.record_flow(&before_flow, &after_flow, &boundaries);
+        }
+        let phase_start = Instant::now();
+        simulate::apply_emitters(&mut next.as_part(), &emitters, &heaters, z, &mut stats);
+        let strip_results = next.for_each_strip(&strips, |next, strip| {
+            let mut strip_stats = ZLevelStats {
+                z,
+                audit: auditing.then(|| ZLevelAudit::new(z)),
+                ..Default::default()
+            };
+            simulate::post_process(
+                &prev,
+                next,
+                &environments,
+                &reactions,
+                &region,
+                &strip.tiles,
+                new_interesting_tiles,
+                z,
+                &mut strip_stats,
+            )?;
+            Ok::<_, eyre::Error>(strip_stats)
+        });
+        for strip_stats in strip_results {
+            stats.add_strip(strip_stats?);
+        }
+        stats.post_process_duration = phase_start.elapsed();
 
         next.active_pressure_chunks.clear();
+        if let Some(audit) = &mut stats.audit {
+            audit.finish();
+        }
     }
 
-    Ok(())
+    stats.duration = start.elapsed();
+    Ok(stats)
 }
 
 // Yay, tests!
@@ -112,6 +353,9 @@ pub(crate) fn tick_z_level(
 mod tests {
     use super::*;
     use crate::milla::constants::*;
+    use crate::milla::emitters::GasEmitter;
+    use byondapi::map::ByondXYZ;
+    use rayon::{ThreadPool, ThreadPoolBuilder};
 
     fn set_with_defaults<F>(legend: F) -> impl Fn(char) -> Tile
     where
@@ -155,8 +399,9 @@ mod tests {
             // Reverse the Y direction, so +Y is up, like in the game.
             let y = pattern.len() - inv_y - 1;
             for x in 0..pattern[inv_y].len() {
+                let index = z_level.maybe_get_index(x as i32, y as i32).unwrap();
                 z_level
-                    .get_tile_mut(ZLevel::maybe_get_index(x as i32, y as i32).unwrap())
+                    .edit_tile(index)
                     .copy_from(&legend(pattern[inv_y].chars().nth(x).unwrap()));
             }
         }
@@ -206,7 +451,7 @@ mod tests {
             // Reverse the Y direction, so +Y is up, like in the game.
             let y = pattern.len() - inv_y - 1;
             for x in 0..pattern[inv_y].len() {
-                let actual = z_level.get_tile(ZLevel::maybe_get_index(x as i32, y as i32).unwrap());
+                let actual = z_level.get_tile(z_level.maybe_get_index(x as i32, y as i32).unwrap());
                 let checker = legend(pattern[inv_y].chars().nth(x).unwrap());
                 checker.check(actual, x as i32, y as i32);
             }
@@ -228,7 +473,7 @@ mod tests {
         fn sealed() -> Self {
             Self::new(AtmosMode::Sealed)
         }
-        fn exposed_to(environment_id: u8) -> Self {
+        fn exposed_to(environment_id: u16) -> Self {
             Self::new(AtmosMode::ExposedTo { environment_id })
         }
         fn wall() -> Self {
@@ -259,27 +504,27 @@ mod tests {
             self
         }
         fn oxygen(mut self, value: f32) -> Self {
-            self.0.gases.set_oxygen(value);
+            self.0.gases.set(GAS_OXYGEN, value);
             self
         }
         fn carbon_dioxide(mut self, value: f32) -> Self {
-            self.0.gases.set_carbon_dioxide(value);
+            self.0.gases.set(GAS_CARBON_DIOXIDE, value);
             self
         }
         fn nitrogen(mut self, value: f32) -> Self {
-            self.0.gases.set_nitrogen(value);
+            self.0.gases.set(GAS_NITROGEN, value);
             self
         }
         fn toxins(mut self, value: f32) -> Self {
-            self.0.gases.set_toxins(value);
+            self.0.gases.set(GAS_TOXINS, value);
             self
         }
         fn sleeping_agent(mut self, value: f32) -> Self {
-            self.0.gases.set_sleeping_agent(value);
+            self.0.gases.set(GAS_SLEEPING_AGENT, value);
             self
         }
         fn agent_b(mut self, value: f32) -> Self {
-            self.0.gases.set_agent_b(value);
+            self.0.gases.set(GAS_AGENT_B, value);
             self
         }
         fn thermal_energy(mut self, value: f32) -> Self {
@@ -294,6 +539,19 @@ mod tests {
             self.0.thermal_energy = value * self.0.heat_capacity();
             self
         }
+        fn innate_temperature(mut self, value: f32) -> Self {
+            self.0.innate_thermal_energy = value * self.0.innate_heat_capacity;
+            self
+        }
+        fn solid_fuel(mut self, value: f32) -> Self {
+            self.0.solid_fuel = value;
+            self
+        }
+        fn hotspot(mut self, temperature: f32, volume: f32) -> Self {
+            self.0.hotspot_temperature = temperature;
+            self.0.hotspot_volume = volume;
+            self
+        }
         fn build(self) -> Tile {
             self.0
         }
@@ -310,6 +568,7 @@ mod tests {
         temperature_: Option<f32>,
     }
 
+    #[allow(dead_code)]
     impl TileChecker {
         fn new() -> Self {
             TileChecker {
@@ -326,9 +585,9 @@ mod tests {
         fn check(self, tile: &Tile, x: i32, y: i32) {
             if let Some(value) = self.oxygen_ {
                 assert!(
-                    (tile.gases.oxygen() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_OXYGEN) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.oxygen(),
+                    tile.gases.get(GAS_OXYGEN),
                     value,
                     x,
                     y
@@ -336,9 +595,9 @@ mod tests {
             }
             if let Some(value) = self.carbon_dioxide_ {
                 assert!(
-                    (tile.gases.carbon_dioxide() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_CARBON_DIOXIDE) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.carbon_dioxide(),
+                    tile.gases.get(GAS_CARBON_DIOXIDE),
                     value,
                     x,
                     y
@@ -346,9 +605,9 @@ mod tests {
             }
             if let Some(value) = self.nitrogen_ {
                 assert!(
-                    (tile.gases.nitrogen() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_NITROGEN) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.nitrogen(),
+                    tile.gases.get(GAS_NITROGEN),
                     value,
                     x,
                     y
@@ -356,9 +615,9 @@ mod tests {
             }
             if let Some(value) = self.toxins_ {
                 assert!(
-                    (tile.gases.toxins() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_TOXINS) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.toxins(),
+                    tile.gases.get(GAS_TOXINS),
                     value,
                     x,
                     y
@@ -366,9 +625,9 @@ mod tests {
             }
             if let Some(value) = self.sleeping_agent_ {
                 assert!(
-                    (tile.gases.sleeping_agent() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_SLEEPING_AGENT) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.sleeping_agent(),
+                    tile.gases.get(GAS_SLEEPING_AGENT),
                     value,
                     x,
                     y
@@ -376,9 +635,9 @@ mod tests {
             }
             if let Some(value) = self.agent_b_ {
                 assert!(
-                    (tile.gases.agent_b() - value).abs() < TEST_TOLERANCE,
+                    (tile.gases.get(GAS_AGENT_B) - value).abs() < TEST_TOLERANCE,
                     "{} != {} @ ({}, {})",
-                    tile.gases.agent_b(),
+                    tile.gases.get(GAS_AGENT_B),
                     value,
                     x,
                     y
@@ -448,5 +707,576 @@ mod tests {
     // unfortunately, the compiler won't let it be applied in the middle of
     // the code right now. It formats right, it just won't compile.
 
-    // TODO
+    // Air should flow between connected Z levels through tiles that are open vertically, and
+    // nowhere else. Levels of different sizes should connect where they overlap.
+    #[test]
+    fn vertical_flow() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 3, 2).unwrap();
+        buffers.init_z_level(1, 4, 3).unwrap();
+        buffers.init_z_level(2, 3, 2).unwrap();
+        buffers.set_z_connected(0, 1, true).unwrap();
+        let pattern = [
+            "###", //
+            "XX#",
+        ];
+        let below = set_with_defaults(|_| None);
+        set_pattern(&buffers, &pattern, &below, 0);
+        let above = set_with_defaults(|c| match c {
+            'X' => Some(TileBuilder::sealed().build()),
+            _ => None,
+        });
+        set_pattern(&buffers, &pattern, &above, 1);
+        set_pattern(&buffers, &pattern, &below, 2);
+        // Tiles start closed vertically, so open both sides everywhere but the lower (1, 0).
+        {
+            let active = buffers.get_active().read().unwrap();
+            for (z, direction) in [(0, AirtightDirections::UP), (1, AirtightDirections::DOWN)] {
+                let mut z_level = active.0[z].write().unwrap();
+                for x in 0..2 {
+                    if z == 0 && x == 1 {
+                        continue;
+                    }
+                    let index = z_level.maybe_get_index(x, 0).unwrap();
+                    z_level
+                        .edit_tile(index)
+                        .airtight_directions
+                        .set(direction, false);
+                }
+            }
+        }
+
+        tick(&buffers).unwrap();
+
+        let moved = 100.0 * VERTICAL_FLOW_RATE;
+        expect_pattern(
+            &buffers,
+            &["XY#"],
+            expect_with_defaults(|c| match c {
+                'X' => Some(
+                    TileChecker::new()
+                        .oxygen(100.0 - moved)
+                        .thermal_energy(100.0 - moved),
+                ),
+                'Y' => Some(TileChecker::new().oxygen(100.0).thermal_energy(100.0)),
+                _ => None,
+            }),
+            0,
+        );
+        expect_pattern(
+            &buffers,
+            &["XY#"],
+            expect_with_defaults(|c| match c {
+                'X' => Some(TileChecker::new().oxygen(moved).thermal_energy(moved)),
+                'Y' => Some(TileChecker::new().oxygen(0.0).thermal_energy(0.0)),
+                _ => None,
+            }),
+            1,
+        );
+        // Z level 3 isn't connected to anything.
+        expect_pattern(&buffers, &["XX#"], expect_with_defaults(|_| None), 2);
+    }
+
+    // Audit mode should blame space for the air it eats, and not see any real drift from flow.
+    #[test]
+    fn audit_space_leak() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 3).unwrap();
+        set_pattern(
+            &buffers,
+            &["###", "XX ", "###"],
+            set_with_defaults(|_| None),
+            0,
+        );
+        buffers.audit.store(true, Relaxed);
+        let stats = tick(&buffers).unwrap();
+
+        let audit = stats[0].audit.as_ref().unwrap();
+        assert!(audit.changes[AuditPhase::TileModes as usize].total_moles() < 0.0);
+        assert!(!audit.is_significant(), "{}", audit);
+    }
+
+    // A Z level that panics should fail the tick, rather than leave BYOND's writes queued.
+    #[test]
+    fn panicking_z_level_cancels_tick() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 1, 1).unwrap();
+        // Poison the inactive Z level, so ticking it panics.
+        let _ = std::thread::scope(|s| {
+            s.spawn(|| {
+                let inactive = buffers.get_inactive().read().unwrap();
+                let _z_level = inactive.0[0].write().unwrap();
+                panic!("Poisoning the Z level.");
+            })
+            .join()
+        });
+        assert!(tick(&buffers).is_err());
+        assert!(!buffers.write_queue.lock().unwrap().ticking);
+    }
+
+    // Once the air settles, ticks should skip it entirely, until something changes.
+    #[test]
+    fn idle_chunks_skipped() {
+        let buffers = Buffers::new();
+        let size = ACTIVE_CHUNK_SIZE * 3;
+        buffers.init_z_level(0, size, size).unwrap();
+        let row = "X".repeat(size);
+        let pattern = vec![row.as_str(); size];
+        set_pattern(&buffers, &pattern, set_with_defaults(|_| None), 0);
+
+        // Everything starts awake, but nothing happens.
+        let stats = tick(&buffers).unwrap();
+        assert_eq!(stats[0].active_tiles, size * size);
+        let stats = tick(&buffers).unwrap();
+        assert_eq!(stats[0].active_tiles, 0);
+        assert_eq!(stats[0].skipped_tiles, size * size);
+
+        // Adding air in a corner should only wake up the chunks next to it.
+        {
+            let active = buffers.get_active().read().unwrap();
+            let mut z_level = active.0[0].write().unwrap();
+            let index = z_level.maybe_get_index(0, 0).unwrap();
+            z_level.edit_tile(index).gases.set(GAS_OXYGEN, 200.0);
+        }
+        let stats = tick(&buffers).unwrap();
+        let woken = ACTIVE_CHUNK_SIZE * 2;
+        assert_eq!(stats[0].active_tiles, woken * woken);
+        assert_eq!(stats[0].skipped_tiles, size * size - woken * woken);
+
+        // It should settle down again without losing air.
+        let mut settled = false;
+        for _ in 0..1000 {
+            if tick(&buffers).unwrap()[0].active_tiles == 0 {
+                settled = true;
+                break;
+            }
+        }
+        assert!(settled);
+        let active = buffers.get_active().read().unwrap();
+        let inactive = buffers.get_inactive().read().unwrap();
+        let active_level = active.0[0].read().unwrap();
+        let inactive_level = inactive.0[0].read().unwrap();
+        let mut total = 0.0;
+        for index in 0..active_level.tile_count() {
+            let oxygen = active_level.get_tile(index).gases.get(GAS_OXYGEN);
+            // Skipped chunks still have to match between the buffers.
+            assert_eq!(oxygen, inactive_level.get_tile(index).gases.get(GAS_OXYGEN));
+            total += oxygen;
+        }
+        // Iterative airflow isn't perfectly conservative even without skipping, so allow for
+        // a little drift.
+        let expected = (size * size) as f32 * 100.0 + 100.0;
+        assert!(
+            (total - expected).abs() < expected * 0.001,
+            "{} vs {}",
+            total,
+            expected
+        );
+    }
+
+    /// Sets up a small, busy Z level, with fire, wind and a leak to space.
+    fn deterministic_buffers() -> Buffers {
+        let buffers = Buffers::new();
+        buffers.deterministic.store(true, Relaxed);
+        buffers.init_z_level(0, 6, 5).unwrap();
+        set_pattern(
+            &buffers,
+            &[
+                "######", //
+                "#XFXX#", "#XXXX ", "#FXX #", "######",
+            ],
+            set_with_defaults(|c| match c {
+                'F' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(100.0)
+                        .toxins(50.0)
+                        .temperature(1000.0)
+                        .build(),
+                ),
+                _ => None,
+            }),
+            0,
+        );
+        buffers
+    }
+
+    // In deterministic mode, the same model should give exactly the same state every tick.
+    #[test]
+    fn deterministic_ticks() {
+        let first = deterministic_buffers();
+        let second = deterministic_buffers();
+        for tick_number in 0..20 {
+            tick(&first).unwrap();
+            tick(&second).unwrap();
+            let first_active = first.get_active().read().unwrap();
+            let second_active = second.get_active().read().unwrap();
+            let first_hash = first_active.0[0].read().unwrap().state_hash();
+            let second_hash = second_active.0[0].read().unwrap().state_hash();
+            assert_eq!(first_hash, second_hash, "diverged on tick {}", tick_number);
+        }
+    }
+
+    // Interesting tiles should come out in the same order no matter which thread found them.
+    #[test]
+    fn interesting_tiles_sorted() {
+        let interesting = |x, y, z| InterestingTile {
+            tile: Tile::new(),
+            coords: ByondXYZ::with_coords((x, y, z)),
+            reasons: ReasonFlags::empty(),
+            wind_x: 0.0,
+            wind_y: 0.0,
+        };
+        let mut tiles = vec![
+            interesting(2, 1, 2),
+            interesting(1, 2, 1),
+            interesting(1, 1, 2),
+            interesting(1, 1, 1),
+        ];
+        sort_interesting_tiles(&mut tiles);
+        let coords: Vec<_> = tiles.iter().map(|tile| tile.coords.coordinates()).collect();
+        assert_eq!(coords, vec![(1, 1, 1), (1, 2, 1), (1, 1, 2), (2, 1, 2)]);
+    }
+
+    // Emitters should keep adding gas to a sealed room, even once the rest of it has gone idle.
+    #[test]
+    fn emitters_wake_idle_chunks() {
+        let buffers = Buffers::new();
+        let size = ACTIVE_CHUNK_SIZE * 2;
+        buffers.init_z_level(0, size, size).unwrap();
+        let row = "X".repeat(size);
+        let pattern = vec![row.as_str(); size];
+        set_pattern(&buffers, &pattern, set_with_defaults(|_| None), 0);
+        tick(&buffers).unwrap();
+        assert_eq!(tick(&buffers).unwrap()[0].active_tiles, 0);
+
+        let mut emitter = GasEmitter {
+            x: 0,
+            y: 0,
+            z: 0,
+            rates: [0.0; MAX_GAS_COUNT],
+            temperature: T20C,
+            pressure_cap: None,
+        };
+        emitter.rates[GAS_TOXINS] = 0.001;
+        let id = buffers.emitters.write().unwrap().add(emitter).unwrap();
+        for _ in 0..10 {
+            tick(&buffers).unwrap();
+        }
+        let total_toxins = || {
+            let active = buffers.get_active().read().unwrap();
+            let z_level = active.0[0].read().unwrap();
+            (0..z_level.tile_count())
+                .map(|index| z_level.get_tile(index).gases.get(GAS_TOXINS))
+                .sum::<f32>()
+        };
+        // Airflow drifts a little, so allow for that.
+        assert!((total_toxins() - 0.01).abs() < 0.001, "{}", total_toxins());
+        assert!(tick(&buffers).unwrap()[0].active_tiles > 0);
+
+        buffers.emitters.write().unwrap().remove(id).unwrap();
+        let before = total_toxins();
+        for _ in 0..5 {
+            tick(&buffers).unwrap();
+        }
+        assert!((total_toxins() - before).abs() < 0.001);
+    }
+
+    // Changing an environment should reach exposed tiles, even once they've gone idle.
+    #[test]
+    fn environment_changes_wake_exposed_tiles() {
+        let buffers = Buffers::new();
+        let size = ACTIVE_CHUNK_SIZE * 2;
+        buffers.init_z_level(0, size, size).unwrap();
+        let air = TileBuilder::exposed_to(0)
+            .oxygen(20.0)
+            .nitrogen(80.0)
+            .temperature(T20C)
+            .build();
+        let id = buffers.create_environment(air.clone()).unwrap();
+        {
+            let active = buffers.get_active().read().unwrap();
+            let mut z_level = active.0[0].write().unwrap();
+            for index in 0..z_level.tile_count() {
+                *z_level.edit_tile(index) = air.clone();
+            }
+        }
+        for _ in 0..10 {
+            if tick(&buffers).unwrap()[0].active_tiles == 0 {
+                break;
+            }
+        }
+        assert_eq!(tick(&buffers).unwrap()[0].active_tiles, 0);
+
+        let mut cold = air.clone();
+        cold.thermal_energy = cold.heat_capacity() * 100.0;
+        buffers
+            .environments
+            .write()
+            .unwrap()
+            .update(id, cold, 2)
+            .unwrap();
+        assert!(tick(&buffers).unwrap()[0].active_tiles > 0);
+        tick(&buffers).unwrap();
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        for index in [0, z_level.tile_count() - 1] {
+            TileChecker::new()
+                .temperature(100.0)
+                .check(z_level.get_tile(index), 0, 0);
+        }
+    }
+
+    // A cold wall should cool the air on both sides of it, through superconduction and its own
+    // heat capacity, without losing any heat overall.
+    #[test]
+    fn cold_wall_cools_rooms() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 3, 1).unwrap();
+        set_pattern(
+            &buffers,
+            &["AWA"],
+            set_with_defaults(|c| match c {
+                'A' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(100.0)
+                        .temperature(T20C)
+                        .innate_heat_capacity(1000.0)
+                        .innate_temperature(T20C)
+                        .build(),
+                ),
+                'W' => Some(
+                    TileBuilder::wall()
+                        .superconducts(OPEN_HEAT_TRANSFER_COEFFICIENT)
+                        .innate_heat_capacity(100000.0)
+                        .innate_temperature(200.0)
+                        .build(),
+                ),
+                _ => None,
+            }),
+            0,
+        );
+        let total_energy = || {
+            let active = buffers.get_active().read().unwrap();
+            let z_level = active.0[0].read().unwrap();
+            (0..z_level.tile_count())
+                .map(|index| {
+                    let tile = z_level.get_tile(index);
+                    tile.thermal_energy + tile.innate_thermal_energy
+                })
+                .sum::<f32>()
+        };
+        let before = total_energy();
+
+        for _ in 0..50 {
+            tick(&buffers).unwrap();
+        }
+
+        let after = total_energy();
+        assert!(
+            (after - before).abs() < before * 0.0001,
+            "{} vs {}",
+            before,
+            after
+        );
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        let room = z_level.get_tile(0);
+        let wall = z_level.get_tile(1);
+        // The wall cools the floor, and the floor cools the air.
+        assert!(room.innate_temperature() < room.temperature());
+        assert!(room.temperature() < T20C);
+        assert!(wall.innate_temperature() > 200.0);
+        assert_eq!(wall.thermal_energy, 0.0);
+    }
+
+    // A fire should spread down a line of solid fuel on its own, and stop where the fuel does.
+    #[test]
+    fn fire_spreads_through_solid_fuel() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 6, 1).unwrap();
+        let fuelled = || {
+            TileBuilder::sealed()
+                .oxygen(20.0)
+                .nitrogen(80.0)
+                .temperature(T20C)
+                .solid_fuel(10.0)
+        };
+        set_pattern(
+            &buffers,
+            &["BFFFWA"],
+            set_with_defaults(|c| match c {
+                'B' => Some(fuelled().hotspot(PLASMA_BURN_OPTIMAL_TEMP, 0.5).build()),
+                'F' => Some(fuelled().build()),
+                'W' => Some(TileBuilder::wall().build()),
+                'A' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(20.0)
+                        .nitrogen(80.0)
+                        .temperature(T20C)
+                        .build(),
+                ),
+                _ => None,
+            }),
+            0,
+        );
+
+        for _ in 0..20 {
+            tick(&buffers).unwrap();
+        }
+
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        for x in 0..4 {
+            let tile = z_level.get_tile(z_level.maybe_get_index(x, 0).unwrap());
+            assert!(tile.solid_fuel < 10.0, "{}: {}", x, tile.solid_fuel);
+            assert!(tile.gases.get(GAS_CARBON_DIOXIDE) > 0.0);
+        }
+        let beyond_wall = z_level.get_tile(z_level.maybe_get_index(5, 0).unwrap());
+        assert_eq!(beyond_wall.hotspot_volume, 0.0);
+        assert_eq!(beyond_wall.gases.get(GAS_CARBON_DIOXIDE), 0.0);
+    }
+
+    // Air should flow between strips, without any going missing at the edges.
+    #[test]
+    fn air_crosses_strips() {
+        let buffers = Buffers::new();
+        let width = STRIP_WIDTH * 3;
+        buffers.init_z_level(0, width, 1).unwrap();
+        let row = "X".repeat(STRIP_WIDTH) + &"0".repeat(width - STRIP_WIDTH);
+        set_pattern(&buffers, &[row.as_str()], set_with_defaults(|_| None), 0);
+
+        for _ in 0..50 {
+            tick(&buffers).unwrap();
+        }
+
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        let oxygen = |x| {
+            let index = z_level.maybe_get_index(x as i32, 0).unwrap();
+            z_level.get_tile(index).gases.get(GAS_OXYGEN)
+        };
+        assert!(oxygen(STRIP_WIDTH) > 0.0);
+        assert!(oxygen(STRIP_WIDTH * 2) > 0.0);
+        let total: f32 = (0..width).map(oxygen).sum();
+        // Iterative airflow isn't perfectly conservative, even within a strip, so allow for a
+        // little drift.
+        let expected = STRIP_WIDTH as f32 * 100.0;
+        assert!(
+            (total - expected).abs() < expected * 0.005,
+            "{} vs {}",
+            total,
+            expected
+        );
+    }
+
+    // Walls at the edge of a strip should superconduct into the next one.
+    #[test]
+    fn heat_crosses_strips() {
+        let buffers = Buffers::new();
+        let width = STRIP_WIDTH * 2;
+        buffers.init_z_level(0, width, 1).unwrap();
+        let row = "H".repeat(STRIP_WIDTH - 1) + "W" + &"C".repeat(STRIP_WIDTH);
+        set_pattern(
+            &buffers,
+            &[row.as_str()],
+            set_with_defaults(|c| match c {
+                'H' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(100.0)
+                        .temperature(T20C * 2.0)
+                        .build(),
+                ),
+                'C' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(100.0)
+                        .temperature(T20C)
+                        .build(),
+                ),
+                'W' => Some(
+                    TileBuilder::wall()
+                        .superconducts(OPEN_HEAT_TRANSFER_COEFFICIENT)
+                        .innate_heat_capacity(1000.0)
+                        .innate_temperature(T20C)
+                        .build(),
+                ),
+                _ => None,
+            }),
+            0,
+        );
+
+        for _ in 0..20 {
+            tick(&buffers).unwrap();
+        }
+
+        let active = buffers.get_active().read().unwrap();
+        let z_level = active.0[0].read().unwrap();
+        let index = z_level.maybe_get_index(STRIP_WIDTH as i32, 0).unwrap();
+        assert!(z_level.get_tile(index).temperature() > T20C);
+    }
+
+    /// Ticks just Z level 0, with its strips running on `pool`.
+    fn tick_on_pool(buffers: &Buffers, pool: &ThreadPool) {
+        {
+            let active = buffers.get_active().read().unwrap();
+            let inactive = buffers.get_inactive().read().unwrap();
+            let interesting_tiles = Bag::default();
+            pool.install(|| {
+                tick_z_level(buffers, &active.0[0], &inactive.0[0], 0, &interesting_tiles)
+            })
+            .unwrap();
+        }
+        buffers.flip();
+    }
+
+    /// Sets up a busy Z level several strips wide, with fires on either side of every strip edge.
+    fn wide_deterministic_buffers() -> Buffers {
+        let buffers = Buffers::new();
+        buffers.deterministic.store(true, Relaxed);
+        let width = STRIP_WIDTH * 3;
+        buffers.init_z_level(0, width, 5).unwrap();
+        let wall = "#".repeat(width);
+        let fires: String = (0..width)
+            .map(|x| match x % STRIP_WIDTH {
+                0 => 'F',
+                x if x == STRIP_WIDTH - 1 => 'F',
+                _ => 'X',
+            })
+            .collect();
+        let air = "X".repeat(width);
+        let leak = "X".repeat(width - 1) + " ";
+        set_pattern(
+            &buffers,
+            &[&wall, &fires, &air, &leak, &wall],
+            set_with_defaults(|c| match c {
+                'F' => Some(
+                    TileBuilder::sealed()
+                        .oxygen(100.0)
+                        .toxins(50.0)
+                        .temperature(1000.0)
+                        .build(),
+                ),
+                _ => None,
+            }),
+            0,
+        );
+        buffers
+    }
+
+    // In deterministic mode, splitting a Z level between more threads shouldn't change anything.
+    #[test]
+    fn thread_count_keeps_results() {
+        let one_thread = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let four_threads = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let first = wide_deterministic_buffers();
+        let second = wide_deterministic_buffers();
+        for tick_number in 0..10 {
+            tick_on_pool(&first, &one_thread);
+            tick_on_pool(&second, &four_threads);
+            let first_active = first.get_active().read().unwrap();
+            let second_active = second.get_active().read().unwrap();
+            let first_hash = first_active.0[0].read().unwrap().state_hash();
+            let second_hash = second_active.0[0].read().unwrap().state_hash();
+            assert_eq!(first_hash, second_hash, "diverged on tick {}", tick_number);
+        }
+    }
 }
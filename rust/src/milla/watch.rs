@@ -0,0 +1,317 @@
+use crate::milla::model::*;
+use bitflags::bitflags;
+use byondapi::map::{byond_locatexyz, ByondXYZ};
+use byondapi::prelude::*;
+use eyre::eyre;
+use eyre::Result;
+
+/// What a watch rule looks at on each tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WatchQuantity {
+    /// Moles of the gas with this index.
+    Gas(usize),
+    /// Pressure, in kPa.
+    Pressure,
+    /// Temperature, in kelvin.
+    Temperature,
+}
+
+impl WatchQuantity {
+    fn value(&self, tile: &Tile) -> f32 {
+        match self {
+            WatchQuantity::Gas(gas) => tile.gases.get(*gas),
+            WatchQuantity::Pressure => tile.pressure(),
+            WatchQuantity::Temperature => tile.temperature(),
+        }
+    }
+}
+
+bitflags! {
+    /// Which ways a value has to cross a threshold for a watch rule to fire.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct WatchDirection: u8 {
+        const RISING = 1 << 0;
+        const FALLING = 1 << 1;
+    }
+}
+
+/// Something BYOND wants to hear about, instead of polling for it.
+/// Covers a rectangle of tiles on a single Z level. Coordinates are 0-indexed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WatchRule {
+    pub(crate) low: (i32, i32),
+    pub(crate) high: (i32, i32),
+    pub(crate) z: i32,
+    pub(crate) quantity: WatchQuantity,
+    pub(crate) threshold: f32,
+    pub(crate) direction: WatchDirection,
+}
+
+/// A watch rule that fired during a tick.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WatchEvent {
+    /// The rule's ID.
+    pub(crate) rule: usize,
+    /// The first tile that crossed the threshold, 0-indexed.
+    pub(crate) coords: (i32, i32, i32),
+    /// The new value at that tile.
+    pub(crate) value: f32,
+    /// How many tiles crossed the threshold.
+    pub(crate) tiles: usize,
+}
+
+impl From<&WatchEvent> for Vec<ByondValue> {
+    /// Converts a watch event into BYOND values.
+    /// Must match the order in code/__DEFINES/rust.dm
+    fn from(value: &WatchEvent) -> Self {
+        let (x, y, z) = value.coords;
+        vec![
+            ByondValue::from(value.rule as f32),
+            // +1 here to convert from our 0-indexing to BYOND's 1-indexing.
+            byond_locatexyz(ByondXYZ::with_coords((
+                x as i16 + 1,
+                y as i16 + 1,
+                z as i16 + 1,
+            )))
+            .unwrap(),
+            ByondValue::from(value.value),
+            ByondValue::from(value.tiles as f32),
+        ]
+    }
+}
+
+impl WatchRule {
+    /// Reads the rule's quantity on every tile it covers, column by column.
+    /// Tiles the Z level doesn't have read as NaN, which never crosses anything.
+    fn read_values(&self, z_level: &ZLevel) -> Vec<f32> {
+        let mut values = Vec::new();
+        for x in self.low.0..=self.high.0 {
+            for y in self.low.1..=self.high.1 {
+                values.push(match z_level.maybe_get_index(x, y) {
+                    Some(index) => self.quantity.value(z_level.get_tile(index)),
+                    None => f32::NAN,
+                });
+            }
+        }
+        values
+    }
+}
+
+/// A watch rule, and the values it saw the last time it was checked.
+struct WatchedRule {
+    rule: WatchRule,
+    last_values: Vec<f32>,
+}
+
+/// Every watch rule BYOND has registered.
+/// IDs are positions in the list, and are reused once removed.
+#[derive(Default)]
+pub(crate) struct WatchRules {
+    rules: Vec<Option<WatchedRule>>,
+}
+
+impl WatchRules {
+    /// Adds a rule watching `z_level`, and returns its ID.
+    /// The rule starts from the tiles' current values, so it only fires on later changes.
+    pub(crate) fn add(&mut self, mut rule: WatchRule, z_level: &ZLevel) -> Result<usize> {
+        if !rule.threshold.is_finite() {
+            return Err(eyre!("Invalid watch threshold {}", rule.threshold));
+        }
+        if rule.direction.is_empty() {
+            return Err(eyre!("Watch rules need a direction."));
+        }
+        // Make sure low is actually the low corner.
+        let (x1, y1) = rule.low;
+        let (x2, y2) = rule.high;
+        rule.low = (x1.min(x2), y1.min(y2));
+        rule.high = (x1.max(x2), y1.max(y2));
+        let last_values = rule.read_values(z_level);
+        Ok(insert_into_free_slot(
+            &mut self.rules,
+            WatchedRule { rule, last_values },
+        ))
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) -> Result<()> {
+        match self.rules.get_mut(id) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(eyre!("No watch rule with ID {}", id)),
+        }
+    }
+
+    /// Finds the rules that fired since they were last checked, whether the tick or BYOND changed
+    /// the tiles.
+    /// Rules only fire when a value crosses their threshold, not while it stays past it.
+    pub(crate) fn check(&mut self, model: &Model) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        for (id, maybe_rule) in self.rules.iter_mut().enumerate() {
+            let Some(WatchedRule { rule, last_values }) = maybe_rule else {
+                continue;
+            };
+            let Some(z_level_lock) = model.0.get(rule.z as usize) else {
+                continue;
+            };
+            let values = rule.read_values(&z_level_lock.read().unwrap());
+            let mut event: Option<WatchEvent> = None;
+            let columns = (rule.low.0..=rule.high.0)
+                .flat_map(|x| (rule.low.1..=rule.high.1).map(move |y| (x, y)));
+            for (((x, y), &before), &after) in columns.zip(last_values.iter()).zip(values.iter()) {
+                let rose = before < rule.threshold && after >= rule.threshold;
+                let fell = before >= rule.threshold && after < rule.threshold;
+                if !(rose && rule.direction.contains(WatchDirection::RISING)
+                    || fell && rule.direction.contains(WatchDirection::FALLING))
+                {
+                    continue;
+                }
+                match event.as_mut() {
+                    Some(event) => event.tiles += 1,
+                    None => {
+                        event = Some(WatchEvent {
+                            rule: id,
+                            coords: (x, y, rule.z),
+                            value: after,
+                            tiles: 1,
+                        })
+                    }
+                }
+            }
+            *last_values = values;
+            events.extend(event);
+        }
+        events
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::milla::constants::*;
+
+    fn set_oxygen(model: &Model, x: i32, y: i32, oxygen: f32) {
+        let mut z_level = model.0[0].write().unwrap();
+        let index = z_level.maybe_get_index(x, y).unwrap();
+        let tile = z_level.edit_tile(index);
+        tile.mode = AtmosMode::Sealed;
+        tile.gases.set(GAS_OXYGEN, oxygen);
+        tile.thermal_energy = tile.heat_capacity() * T20C;
+    }
+
+    fn oxygen_rule(low: (i32, i32), high: (i32, i32), direction: WatchDirection) -> WatchRule {
+        WatchRule {
+            low,
+            high,
+            z: 0,
+            quantity: WatchQuantity::Gas(GAS_OXYGEN),
+            threshold: 20.0,
+            direction,
+        }
+    }
+
+    // Rules should fire once per check, only when crossing their threshold in the right direction.
+    #[test]
+    fn rules_fire_on_crossing() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 4).unwrap();
+        let model = buffers.get_active().read().unwrap();
+        set_oxygen(&model, 0, 0, 10.0);
+        set_oxygen(&model, 1, 0, 10.0);
+
+        let mut rules = WatchRules::default();
+        let z_level = model.0[0].read().unwrap();
+        let rising = rules
+            .add(
+                oxygen_rule((3, 3), (0, 0), WatchDirection::RISING),
+                &z_level,
+            )
+            .unwrap();
+        let falling = rules
+            .add(
+                oxygen_rule((0, 0), (0, 0), WatchDirection::FALLING),
+                &z_level,
+            )
+            .unwrap();
+        let pressure = rules
+            .add(
+                WatchRule {
+                    low: (1, 0),
+                    high: (1, 0),
+                    z: 0,
+                    quantity: WatchQuantity::Pressure,
+                    threshold: ONE_ATMOSPHERE * 100.0,
+                    direction: WatchDirection::all(),
+                },
+                &z_level,
+            )
+            .unwrap();
+        drop(z_level);
+
+        set_oxygen(&model, 0, 0, 30.0);
+        set_oxygen(&model, 1, 0, 30.0);
+        let events = rules.check(&model);
+        assert_eq!(
+            events,
+            vec![WatchEvent {
+                rule: rising,
+                coords: (0, 0, 0),
+                value: 30.0,
+                tiles: 2,
+            }]
+        );
+        // Staying past the threshold shouldn't fire again.
+        assert_eq!(rules.check(&model), vec![]);
+        // Going back down should only trigger the falling rule.
+        set_oxygen(&model, 0, 0, 10.0);
+        set_oxygen(&model, 1, 0, 10.0);
+        let events = rules.check(&model);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].rule, falling);
+
+        rules.remove(rising).unwrap();
+        assert!(rules.remove(rising).is_err());
+        set_oxygen(&model, 0, 0, 30.0);
+        assert_eq!(rules.check(&model), vec![]);
+        assert!(rules.remove(pressure).is_ok());
+    }
+
+    // Plain writes from BYOND should fire rules too, whether they land between ticks or get
+    // queued and applied at the flip.
+    #[test]
+    fn rules_see_byond_writes() {
+        let buffers = Buffers::new();
+        buffers.init_z_level(0, 4, 4).unwrap();
+        let mut rules = WatchRules::default();
+        let rule = rules
+            .add(
+                oxygen_rule((0, 0), (3, 3), WatchDirection::all()),
+                &buffers.get_active().read().unwrap().0[0].read().unwrap(),
+            )
+            .unwrap();
+
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.set(GAS_OXYGEN, 30.0))
+            .unwrap();
+        let events = rules.check(&buffers.get_active().read().unwrap());
+        assert_eq!(
+            events,
+            vec![WatchEvent {
+                rule,
+                coords: (1, 1, 0),
+                value: 30.0,
+                tiles: 1,
+            }]
+        );
+
+        buffers.begin_tick();
+        buffers
+            .write_tile(1, 1, 0, |tile| tile.gases.set(GAS_OXYGEN, 0.0))
+            .unwrap();
+        buffers.flip();
+        let events = rules.check(&buffers.get_active().read().unwrap());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, 0.0);
+    }
+}
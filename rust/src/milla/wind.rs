@@ -0,0 +1,110 @@
+//! The force wind exerts on things, and how far that pushes them.
+//!
+//! Wind sets how much of each gas flows across the boundary between two tiles, and that flow
+//! pushes on anything in its way. An object feels the average of the flows across the two
+//! boundaries of its tile along each axis.
+use crate::milla::constants::*;
+use crate::milla::model::*;
+
+/// How hard the air flowing across a tile's +axis boundary pushes towards +axis, in kPa.
+fn boundary_pressure(tile: &Tile, axis: usize) -> f32 {
+    if tile.wall[axis] {
+        return 0.0;
+    }
+    let mut pressure = 0.0;
+    for gas in 0..MAX_GAS_COUNT {
+        let net_flow =
+            tile.gas_flow[axis][gas][GAS_FLOW_OUT] - tile.gas_flow[axis][gas][GAS_FLOW_IN];
+        pressure += net_flow * tile.partial_pressure(gas);
+    }
+    pressure
+}
+
+/// The force the wind exerts on an object in the tile at (x, y), along each axis, in newtons.
+/// Tile::wind isn't added on top, because update_wind() already turns it into extra gas flow
+/// towards the wind, and counting it again would push twice.
+pub(crate) fn wind_force(z_level: &ZLevel, x: i32, y: i32) -> [f32; AXES.len()] {
+    let mut force = [0.0; AXES.len()];
+    let Some(index) = z_level.maybe_get_index(x, y) else {
+        return force;
+    };
+    let my_tile = z_level.get_tile(index);
+    for (axis, (dx, dy)) in AXES.iter().enumerate() {
+        // Our tile owns the boundary towards +axis, and our neighbor owns the one towards -axis.
+        let mut pressure = boundary_pressure(my_tile, axis);
+        if let Some(their_index) = z_level.maybe_get_index(x - dx, y - dy) {
+            pressure += boundary_pressure(z_level.get_tile(their_index), axis);
+        }
+        // Average the two boundaries, and convert from kPa to Pa.
+        force[axis] = pressure / 2.0 * 1000.0 * WIND_DRAG_AREA;
+    }
+    force
+}
+
+/// How far a force moves an object of `mass` kilograms this tick, along each axis, in tiles.
+/// Friction holds the object in place until the wind is strong enough to overcome it.
+pub(crate) fn wind_displacement(force: [f32; AXES.len()], mass: f32) -> [f32; AXES.len()] {
+    if mass.is_nan() || mass <= 0.0 {
+        // Massless and nonsense objects stay put.
+        return [0.0; AXES.len()];
+    }
+    let magnitude = force[AXIS_X].hypot(force[AXIS_Y]);
+    let friction = mass * STANDARD_GRAVITY * WIND_FRICTION;
+    if magnitude.is_nan() || magnitude <= friction {
+        return [0.0; AXES.len()];
+    }
+    let acceleration = (magnitude - friction) / mass;
+    let distance =
+        (0.5 * acceleration * WIND_PUSH_TIME.powi(2) / TILE_WIDTH).min(MAX_WIND_DISPLACEMENT);
+    [
+        force[AXIS_X] / magnitude * distance,
+        force[AXIS_Y] / magnitude * distance,
+    ]
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Air rushing towards +X should push things that way, and only things light enough to move.
+    #[test]
+    fn breach_pushes_things() {
+        let mut z_level = ZLevel::new(3, 1);
+        for x in 0..3 {
+            let tile = z_level.edit_tile(z_level.maybe_get_index(x, 0).unwrap());
+            tile.mode = AtmosMode::Sealed;
+            tile.gases.set(GAS_NITROGEN, 100.0);
+            tile.thermal_energy = 100.0 * SPECIFIC_HEAT_NITROGEN * T20C;
+            tile.gases.set_dirty();
+            tile.gas_flow[AXIS_X][GAS_NITROGEN][GAS_FLOW_OUT] = 1.0;
+        }
+
+        // The middle tile sees flow on both sides, the first only on its +X side.
+        let middle = wind_force(&z_level, 1, 0);
+        let first = wind_force(&z_level, 0, 0);
+        assert!(middle[AXIS_X] > 0.0);
+        assert_eq!(middle[AXIS_Y], 0.0);
+        assert!((first[AXIS_X] - middle[AXIS_X] / 2.0).abs() < TEST_TOLERANCE);
+
+        let light = wind_displacement(middle, 1.0);
+        assert!(light[AXIS_X] > 0.0);
+        assert!(light[AXIS_X] <= MAX_WIND_DISPLACEMENT);
+        assert_eq!(light[AXIS_Y], 0.0);
+        assert_eq!(wind_displacement(middle, 1e6), [0.0, 0.0]);
+        assert_eq!(wind_displacement(middle, f32::NAN), [0.0, 0.0]);
+    }
+
+    // Diffusion flows both ways equally, so it shouldn't push anything.
+    #[test]
+    fn diffusion_is_still() {
+        let mut z_level = ZLevel::new(2, 1);
+        for x in 0..2 {
+            let tile = z_level.edit_tile(z_level.maybe_get_index(x, 0).unwrap());
+            tile.gases.set(GAS_OXYGEN, 100.0);
+            tile.gases.set_dirty();
+            tile.gas_flow[AXIS_X][GAS_OXYGEN] = [DIFFUSION_SPEED; 2];
+        }
+        assert_eq!(wind_force(&z_level, 1, 0), [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::milla::model::*;
+
+/// The four horizontal neighbors of a tile, with the airtight direction on each side of the
+/// boundary between them.
+pub(crate) const NEIGHBORS: [(i32, i32, AirtightDirections, AirtightDirections); 4] = [
+    (0, 1, AirtightDirections::NORTH, AirtightDirections::SOUTH),
+    (1, 0, AirtightDirections::EAST, AirtightDirections::WEST),
+    (0, -1, AirtightDirections::SOUTH, AirtightDirections::NORTH),
+    (-1, 0, AirtightDirections::WEST, AirtightDirections::EAST),
+];
+
+/// A group of tiles that share air with each other.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Zone {
+    /// Indexes of the tiles in this zone.
+    pub(crate) tiles: Vec<usize>,
+    /// Whether any tile in this zone is open to space.
+    pub(crate) breached: bool,
+}
+
+/// The zones on a single Z level.
+/// Kept up to date lazily: changes are recorded as they happen, and only the zones near them are
+/// rebuilt the next time anyone asks.
+#[derive(Debug, Default)]
+pub(crate) struct ZoneMap {
+    tile_count: usize,
+    /// Which zone each tile is in. Space and solid walls aren't in any zone.
+    zone_of: Vec<Option<usize>>,
+    zones: Vec<Option<Zone>>,
+    /// Tiles whose airtightness or atmos mode changed since the last refresh.
+    changed: Vec<usize>,
+    /// Whether we need to start over from scratch.
+    needs_rebuild: bool,
+}
+
+impl ZoneMap {
+    /// Records that a tile's airtightness or atmos mode changed.
+    pub(crate) fn mark_changed(&mut self, index: usize) {
+        self.changed.push(index);
+    }
+
+    /// Throws away every zone, they'll be rebuilt on the next refresh.
+    pub(crate) fn reset(&mut self) {
+        self.needs_rebuild = true;
+        self.changed.clear();
+    }
+
+    /// Which zone a tile is in, if any. Only accurate after refresh().
+    pub(crate) fn zone_of(&self, index: usize) -> Option<usize> {
+        self.zone_of.get(index).copied().flatten()
+    }
+
+    /// Fetches a zone by ID. Only accurate after refresh().
+    pub(crate) fn get_zone(&self, id: usize) -> Option<&Zone> {
+        self.zones.get(id)?.as_ref()
+    }
+
+    /// Brings the zones up to date with the Z level.
+    pub(crate) fn refresh(&mut self, z_level: &ZLevel) {
+        let mut seeds = Vec::new();
+        if self.needs_rebuild || self.tile_count != z_level.tile_count() {
+            self.tile_count = z_level.tile_count();
+            self.zone_of = vec![None; self.tile_count];
+            self.zones.clear();
+            self.changed.clear();
+            self.needs_rebuild = false;
+            seeds.extend(0..self.tile_count);
+        } else {
+            // A change can split or merge the zones it touches, or change whether they're
+            // breached, so those zones are rebuilt. Everything else stays as it is.
+            for index in std::mem::take(&mut self.changed) {
+                let (x, y) = z_level.get_coords(index);
+                seeds.push(index);
+                self.free_zone_at(index, &mut seeds);
+                for (dx, dy, _, _) in NEIGHBORS {
+                    if let Some(neighbor) = z_level.maybe_get_index(x + dx, y + dy) {
+                        self.free_zone_at(neighbor, &mut seeds);
+                    }
+                }
+            }
+        }
+
+        let mut stack = Vec::new();
+        for seed in seeds {
+            if self.zone_of[seed].is_some() || !in_zone(z_level.get_tile(seed)) {
+                continue;
+            }
+            let id = insert_into_free_slot(&mut self.zones, Zone::default());
+            let mut zone = Zone::default();
+            self.zone_of[seed] = Some(id);
+            stack.push(seed);
+            while let Some(index) = stack.pop() {
+                zone.tiles.push(index);
+                let (x, y) = z_level.get_coords(index);
+                let tile = z_level.get_tile(index);
+                for (dx, dy, my_direction, their_direction) in NEIGHBORS {
+                    let Some(neighbor) = z_level.maybe_get_index(x + dx, y + dy) else {
+                        // Edge of the map, acts like a wall.
+                        continue;
+                    };
+                    let neighbor_tile = z_level.get_tile(neighbor);
+                    if tile.airtight_directions.contains(my_direction)
+                        || neighbor_tile.airtight_directions.contains(their_direction)
+                    {
+                        continue;
+                    }
+                    if neighbor_tile.mode == AtmosMode::Space {
+                        zone.breached = true;
+                        continue;
+                    }
+                    if self.zone_of[neighbor] == Some(id) || !in_zone(neighbor_tile) {
+                        continue;
+                    }
+                    if self.zone_of[neighbor].is_some() {
+                        // We've reached an untouched zone, so it's merging into this one.
+                        self.free_zone_at(neighbor, &mut Vec::new());
+                    }
+                    self.zone_of[neighbor] = Some(id);
+                    stack.push(neighbor);
+                }
+            }
+            self.zones[id] = Some(zone);
+        }
+    }
+
+    /// Removes the zone containing `index`, if any, and adds its tiles to `seeds`.
+    fn free_zone_at(&mut self, index: usize, seeds: &mut Vec<usize>) {
+        let Some(id) = self.zone_of[index] else {
+            return;
+        };
+        let Some(zone) = self.zones[id].take() else {
+            return;
+        };
+        for &tile in &zone.tiles {
+            self.zone_of[tile] = None;
+        }
+        seeds.extend(zone.tiles);
+    }
+}
+
+/// Whether a tile can be part of a zone at all.
+/// Space isn't, and neither is anything airtight on every side, like a wall.
+fn in_zone(tile: &Tile) -> bool {
+    tile.mode != AtmosMode::Space
+        && !tile.airtight_directions.contains(
+            AirtightDirections::NORTH
+                | AirtightDirections::EAST
+                | AirtightDirections::SOUTH
+                | AirtightDirections::WEST,
+        )
+}
+
+/// The zones on every Z level.
+#[derive(Debug, Default)]
+pub(crate) struct Zones {
+    levels: Vec<ZoneMap>,
+}
+
+impl Zones {
+    /// Fetches the zones for a Z level, creating an empty map if needed.
+    pub(crate) fn level_mut(&mut self, z: usize) -> &mut ZoneMap {
+        while self.levels.len() <= z {
+            let mut zone_map = ZoneMap::default();
+            zone_map.reset();
+            self.levels.push(zone_map);
+        }
+        &mut self.levels[z]
+    }
+
+    /// Throws away the zones on every Z level.
+    pub(crate) fn reset_all(&mut self) {
+        for zone_map in &mut self.levels {
+            zone_map.reset();
+        }
+    }
+}
+
+// Yay, tests!
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_tile(
+        z_level: &mut ZLevel,
+        x: i32,
+        y: i32,
+        mode: AtmosMode,
+        airtight: AirtightDirections,
+    ) {
+        let index = z_level.maybe_get_index(x, y).unwrap();
+        let tile = z_level.edit_tile(index);
+        tile.mode = mode;
+        tile.airtight_directions = airtight;
+    }
+
+    // Walls should split a Z level into zones, and opening or closing them should update the
+    // zones incrementally.
+    #[test]
+    fn split_merge_and_breach() {
+        // A 5x3 level: two rooms on either side of a wall at x=2, with a door at (2, 1).
+        let mut z_level = ZLevel::new(5, 3);
+        for x in 0..5 {
+            for y in 0..3 {
+                let airtight = if x == 2 && y != 1 {
+                    AirtightDirections::all()
+                } else {
+                    AirtightDirections::empty()
+                };
+                set_tile(&mut z_level, x, y, AtmosMode::Sealed, airtight);
+            }
+        }
+        let door = z_level.maybe_get_index(2, 1).unwrap();
+        let left = z_level.maybe_get_index(0, 0).unwrap();
+        let right = z_level.maybe_get_index(4, 2).unwrap();
+        let wall = z_level.maybe_get_index(2, 0).unwrap();
+
+        let mut zone_map = ZoneMap::default();
+        zone_map.reset();
+        zone_map.refresh(&z_level);
+        assert_eq!(zone_map.zone_of(wall), None);
+        let id = zone_map.zone_of(left).unwrap();
+        assert_eq!(zone_map.zone_of(right), Some(id));
+        assert_eq!(zone_map.get_zone(id).unwrap().tiles.len(), 13);
+        assert!(!zone_map.get_zone(id).unwrap().breached);
+
+        // Close the door.
+        set_tile(
+            &mut z_level,
+            2,
+            1,
+            AtmosMode::Sealed,
+            AirtightDirections::all(),
+        );
+        zone_map.mark_changed(door);
+        zone_map.refresh(&z_level);
+        let left_id = zone_map.zone_of(left).unwrap();
+        let right_id = zone_map.zone_of(right).unwrap();
+        assert_ne!(left_id, right_id);
+        assert_eq!(zone_map.zone_of(door), None);
+        assert_eq!(zone_map.get_zone(left_id).unwrap().tiles.len(), 6);
+        assert_eq!(zone_map.get_zone(right_id).unwrap().tiles.len(), 6);
+
+        // Open a hole to space in the right room.
+        let hole = z_level.maybe_get_index(4, 0).unwrap();
+        set_tile(
+            &mut z_level,
+            4,
+            0,
+            AtmosMode::Space,
+            AirtightDirections::empty(),
+        );
+        zone_map.mark_changed(hole);
+        zone_map.refresh(&z_level);
+        let right_id = zone_map.zone_of(right).unwrap();
+        assert!(zone_map.get_zone(right_id).unwrap().breached);
+        assert!(
+            !zone_map
+                .get_zone(zone_map.zone_of(left).unwrap())
+                .unwrap()
+                .breached
+        );
+
+        // Open the door again, and the whole thing should be breached.
+        set_tile(
+            &mut z_level,
+            2,
+            1,
+            AtmosMode::Sealed,
+            AirtightDirections::empty(),
+        );
+        zone_map.mark_changed(door);
+        zone_map.refresh(&z_level);
+        let id = zone_map.zone_of(left).unwrap();
+        assert_eq!(zone_map.zone_of(right), Some(id));
+        assert_eq!(zone_map.zone_of(hole), None);
+        assert_eq!(zone_map.get_zone(id).unwrap().tiles.len(), 12);
+        assert!(zone_map.get_zone(id).unwrap().breached);
+    }
+}